@@ -1,16 +1,56 @@
+use itertools::Itertools;
 use pombase_gocam::GoCamModel;
 
-use crate::{allowed_relation_config::AllowedRelConfig, ontology_info::OntologyInfo};
+use crate::{allowed_relation_config::{AllowedRelConfig, ConfigDescendants, RuleKey},
+            ontology_info::OntologyInfo};
+use crate::diagnostic::{Diagnostic, DiagnosticKind, DiagnosticRelation, DiagnosticTerm, Severity};
 
 use pombase_gocam::REL_NAMES;
 
+// return true if the config line for rule_term_id applies to term_id
+fn rule_applies(ontology_info: &OntologyInfo, descendants: ConfigDescendants,
+                term_id: &str, rule_term_id: &str)
+    -> bool
+{
+    match descendants {
+        ConfigDescendants::All => ontology_info.is_a(term_id, rule_term_id),
+        ConfigDescendants::SelfOnly => term_id == rule_term_id,
+        ConfigDescendants::Disabled => false,
+        ConfigDescendants::MaxDepth(max_depth) => {
+            ontology_info.is_a_distance(term_id, rule_term_id)
+                .is_some_and(|distance| distance <= max_depth)
+        },
+    }
+}
+
+// the config keys that could apply to a term: its is_a ancestors and the
+// term itself, which is added if the closure has no row for it
+fn rule_term_ids<'a>(ontology_info: &'a OntologyInfo, term_id: &'a str) -> Vec<&'a str> {
+    let mut ret: Vec<&str> = ontology_info.get_term_parents(term_id)
+        .into_iter()
+        .flatten()
+        .filter(|(rel, _)| *rel == "rdfs:subClassOf")
+        .map(|(_, parent_term_id)| parent_term_id)
+        .collect();
+
+    if !ret.contains(&term_id) {
+        ret.push(term_id);
+    }
+
+    ret
+}
+
 pub(crate) fn check_relations(model: &GoCamModel,
                               config: &AllowedRelConfig,
                               ontology_info: &OntologyInfo)
-    -> Vec<String>
+    -> Vec<Diagnostic>
 {
     let mut ret = vec![];
 
+    let pair_rules = config.pair_config().iter()
+        .sorted_by(|(a_key, _), (b_key, _)| a_key.cmp(b_key))
+        .collect::<Vec<_>>();
+
     for (_, source_idx, edge, object_idx) in model.edge_iterator() {
         let subject_node = model.graph().node_weight(source_idx).unwrap();
         let object_node = model.graph().node_weight(object_idx).unwrap();
@@ -18,27 +58,90 @@ pub(crate) fn check_relations(model: &GoCamModel,
             continue;
         }
         let subject_term_id = &subject_node.node_id;
+        let object_term_id = &object_node.node_id;
         let rel_id = &edge.id;
-        let Some(term_id_and_parents) = ontology_info.get_term_parents(subject_term_id)
-        else {
-            ret.push(format!("can't find parents of {}", subject_term_id));
-            continue;
+
+        let make_diagnostic = |kind, severity| {
+            let mut diagnostic = Diagnostic::new(kind, severity, model.id());
+            diagnostic.individual_ids = vec![subject_node.individual_gocam_id.to_string(),
+                                             object_node.individual_gocam_id.to_string()];
+            diagnostic.subject_term = Some(DiagnosticTerm {
+                id: subject_term_id.to_owned(),
+                name: subject_node.label.to_owned(),
+            });
+            diagnostic.object_term = Some(DiagnosticTerm {
+                id: object_term_id.to_owned(),
+                name: object_node.label.to_owned(),
+            });
+            diagnostic
+        };
+
+        let make_disallowed = |kind, rule_source: String| {
+            let rel_name = REL_NAMES.get(rel_id).unwrap();
+            let mut diagnostic = make_diagnostic(kind, Severity::Error);
+            diagnostic.relation = Some(DiagnosticRelation {
+                id: rel_id.to_owned(),
+                name: rel_name.to_string(),
+            });
+            diagnostic.rule_source = Some(rule_source);
+            diagnostic
         };
 
-        for (parent_rel, parent_term_id) in term_id_and_parents {
-            if parent_rel != "rdfs:subClassOf" {
+        // the object and pair rules are still checked if the subject isn't
+        // in the closure
+        if ontology_info.get_term_parents(subject_term_id).is_none() {
+            ret.push(make_diagnostic(DiagnosticKind::MissingTermParents, Severity::Warning));
+        }
+
+        for rule_term_id in rule_term_ids(ontology_info, subject_term_id) {
+            let Some(allowed_term_rels) = config.term_config().get(rule_term_id)
+            else {
+                continue;
+            };
+
+            let descendants = config.descendants(&RuleKey::Subject(rule_term_id.to_owned()));
+
+            if !rule_applies(ontology_info, descendants, subject_term_id, rule_term_id) {
                 continue;
             }
 
-            let Some(allowed_term_rels) = config.term_config().get(parent_term_id.as_str())
+            if !allowed_term_rels.contains(rel_id) {
+                ret.push(make_disallowed(DiagnosticKind::DisallowedRelation,
+                                         rule_term_id.to_owned()));
+            }
+        }
+
+        for rule_term_id in rule_term_ids(ontology_info, object_term_id) {
+            let Some(allowed_term_rels) = config.object_term_config().get(rule_term_id)
             else {
                 continue;
             };
 
+            let descendants = config.descendants(&RuleKey::Object(rule_term_id.to_owned()));
+
+            if !rule_applies(ontology_info, descendants, object_term_id, rule_term_id) {
+                continue;
+            }
+
             if !allowed_term_rels.contains(rel_id) {
-                let rel_name = REL_NAMES.get(rel_id).unwrap();
-                ret.push(format!("relation {} ({}) not allowed for {} because of config for {}",
-                         rel_name, rel_id, subject_term_id, parent_term_id));
+                ret.push(make_disallowed(DiagnosticKind::DisallowedObjectRelation,
+                                         rule_term_id.to_owned()));
+            }
+        }
+
+        for ((rule_subject_id, rule_object_id), allowed_pair_rels) in &pair_rules {
+            let descendants = config.descendants(&RuleKey::Pair(rule_subject_id.to_owned(),
+                                                                rule_object_id.to_owned()));
+
+            if !rule_applies(ontology_info, descendants, subject_term_id, rule_subject_id) ||
+                !rule_applies(ontology_info, descendants, object_term_id, rule_object_id)
+            {
+                continue;
+            }
+
+            if !allowed_pair_rels.contains(rel_id) {
+                ret.push(make_disallowed(DiagnosticKind::DisallowedPairRelation,
+                                         format!("{} -> {}", rule_subject_id, rule_object_id)));
             }
         }
     }
@@ -53,13 +156,15 @@ mod tests {
 
     use pombase_gocam::parse_gocam_py_model;
 
+    use crate::diagnostic::{DiagnosticKind, Severity};
+
     use super::check_relations;
 
     #[test]
     fn check_test() {
         let closure_file = File::open("tests/data/closure.tsv").unwrap();
         let mut closure_buf_reader = BufReader::new(closure_file);
-        let closure = crate::parse_closure(&mut closure_buf_reader).unwrap();
+        let closure = crate::ontology_info::parse_closure(&mut closure_buf_reader).unwrap();
 
         let config_file = File::open("tests/data/config.tsv").unwrap();
         let mut config_buf_reader = BufReader::new(config_file);
@@ -71,6 +176,146 @@ mod tests {
         let warnings = check_relations(&model, &config, &closure);
 
         assert_eq!(warnings.len(), 2);
-        assert_eq!(warnings[0], "relation directly negatively regulates (RO:0002630) not allowed for GO:0003713 because of config for GO:0003824");
+        assert_eq!(warnings[0].to_string(), "relation directly negatively regulates (RO:0002630) not allowed for GO:0003713 because of config for GO:0003824");
+        assert_eq!(warnings[0].kind, DiagnosticKind::DisallowedRelation);
+        assert_eq!(warnings[0].severity, Severity::Error);
+        assert_eq!(warnings[0].model_id, "gomodel:67ae98b500000055");
+        assert_eq!(warnings[0].individual_ids.len(), 2);
+        assert_eq!(warnings[0].subject_term_id(), "GO:0003713");
+        assert_eq!(warnings[0].relation.as_ref().unwrap().id, "RO:0002630");
+        assert_eq!(warnings[0].rule_source.as_deref(), Some("GO:0003824"));
+    }
+
+    #[test]
+    fn check_descendants_test() {
+        let closure_file = File::open("tests/data/closure.tsv").unwrap();
+        let mut closure_buf_reader = BufReader::new(closure_file);
+        let closure = crate::ontology_info::parse_closure(&mut closure_buf_reader).unwrap();
+
+        let mut source = File::open("tests/data/67ae98b500000055.yaml").unwrap();
+        let model = parse_gocam_py_model(&mut source).unwrap();
+
+        let rule_sources = |descendants: &str| {
+            let config_text =
+                format!("GO:0003824\tcatalytic activity\tdirectly positively regulates|constitutively_upstream_of|provides input for|indirectly negatively regulates\t{}\n",
+                        descendants);
+            let config =
+                crate::parse_allowed_relations_config(&mut config_text.as_bytes()).unwrap();
+            check_relations(&model, &config, &closure).into_iter()
+                .filter_map(|warning| warning.rule_source)
+                .filter(|rule_source| rule_source == "GO:0003824")
+                .count()
+        };
+
+        assert!(rule_sources("ALL") > 0);
+        assert_eq!(rule_sources("1"), rule_sources("ALL"));
+        assert_eq!(rule_sources("SELF"), 0);
+        assert_eq!(rule_sources("0"), 0);
+        assert_eq!(rule_sources("NONE"), 0);
+
+        // a rule for the exact term of the subject, which has a directly
+        // negatively regulates edge
+        let exact_rule_sources = |descendants: &str| {
+            let config_text =
+                format!("GO:0003713\ttranscription coactivator activity\tdirectly positively regulates\t{}\n",
+                        descendants);
+            let config =
+                crate::parse_allowed_relations_config(&mut config_text.as_bytes()).unwrap();
+            check_relations(&model, &config, &closure).into_iter()
+                .filter_map(|warning| warning.rule_source)
+                .filter(|rule_source| rule_source == "GO:0003713")
+                .count()
+        };
+
+        assert!(exact_rule_sources("SELF") > 0);
+        assert_eq!(exact_rule_sources("0"), exact_rule_sources("SELF"));
+        assert_eq!(exact_rule_sources("ALL"), exact_rule_sources("SELF"));
+        assert_eq!(exact_rule_sources("NONE"), 0);
+    }
+
+    #[test]
+    fn check_object_and_pair_test() {
+        let closure_file = File::open("tests/data/closure.tsv").unwrap();
+        let mut closure_buf_reader = BufReader::new(closure_file);
+        let closure = crate::ontology_info::parse_closure(&mut closure_buf_reader).unwrap();
+
+        let mut source = File::open("tests/data/67ae98b500000055.yaml").unwrap();
+        let model = parse_gocam_py_model(&mut source).unwrap();
+
+        let object_config_text =
+            "GO:0003824\tcatalytic activity\tprovides input for\tALL\t\tobject\n";
+        let object_config =
+            crate::parse_allowed_relations_config(&mut object_config_text.as_bytes()).unwrap();
+
+        let object_warnings = check_relations(&model, &object_config, &closure);
+        assert!(!object_warnings.is_empty());
+
+        for warning in &object_warnings {
+            assert_eq!(warning.kind, DiagnosticKind::DisallowedObjectRelation);
+            assert!(closure.is_a(warning.object_term_id(), "GO:0003824"));
+            assert_ne!(warning.relation.as_ref().unwrap().id, "RO:0002413");
+        }
+
+        let pair_config_text =
+            "GO:0003824\tcatalytic activity\tprovides input for\tALL\t\tpair\tGO:0003824\n";
+        let pair_config =
+            crate::parse_allowed_relations_config(&mut pair_config_text.as_bytes()).unwrap();
+
+        let pair_warnings = check_relations(&model, &pair_config, &closure);
+        assert!(!pair_warnings.is_empty());
+
+        for warning in &pair_warnings {
+            assert_eq!(warning.kind, DiagnosticKind::DisallowedPairRelation);
+            assert!(closure.is_a(warning.subject_term_id(), "GO:0003824"));
+            assert!(closure.is_a(warning.object_term_id(), "GO:0003824"));
+            assert_eq!(warning.rule_source.as_deref(), Some("GO:0003824 -> GO:0003824"));
+        }
+
+        // pair warnings are a subset of the object warnings for the same rule
+        assert!(pair_warnings.len() <= object_warnings.len());
+    }
+
+    #[test]
+    fn check_unknown_subject_test() {
+        // GO:0003713 is the subject of a directly negatively regulates edge
+        // to GO:0001227, a catalytic activity
+        let closure_text = std::fs::read_to_string("tests/data/closure.tsv").unwrap();
+        let closure_text: String = closure_text.lines()
+            .filter(|line| !line.starts_with("GO:0003713\t"))
+            .map(|line| format!("{}\n", line))
+            .collect();
+        let closure =
+            crate::ontology_info::parse_closure(&mut closure_text.as_bytes()).unwrap();
+
+        let mut source = File::open("tests/data/67ae98b500000055.yaml").unwrap();
+        let model = parse_gocam_py_model(&mut source).unwrap();
+
+        let object_config_text =
+            "GO:0003824\tcatalytic activity\tprovides input for\tALL\t\tobject\n";
+        let object_config =
+            crate::parse_allowed_relations_config(&mut object_config_text.as_bytes()).unwrap();
+
+        let warnings = check_relations(&model, &object_config, &closure);
+
+        assert!(warnings.iter().any(|warning| {
+            warning.kind == DiagnosticKind::MissingTermParents &&
+                warning.subject_term_id() == "GO:0003713"
+        }));
+        assert!(warnings.iter().any(|warning| {
+            warning.kind == DiagnosticKind::DisallowedObjectRelation &&
+                warning.subject_term_id() == "GO:0003713" &&
+                warning.object_term_id() == "GO:0001227"
+        }));
+
+        // a rule for the term itself applies without the closure
+        let self_config_text =
+            "GO:0003713\ttranscription coactivator activity\tdirectly positively regulates\tSELF\n";
+        let self_config =
+            crate::parse_allowed_relations_config(&mut self_config_text.as_bytes()).unwrap();
+
+        assert!(check_relations(&model, &self_config, &closure).iter().any(|warning| {
+            warning.kind == DiagnosticKind::DisallowedRelation &&
+                warning.rule_source.as_deref() == Some("GO:0003713")
+        }));
     }
 }
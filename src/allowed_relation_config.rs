@@ -6,6 +6,14 @@ use thiserror::Error;
 pub enum AllowedRelConfigError {
     #[error("I/O error: {0}")]
     IOError(#[from] std::io::Error),
+    #[error("unknown value in descendents column for {term_id}: {value}")]
+    UnknownDescendants { term_id: String, value: String },
+    #[error("conflicting descendents values for {term_id}")]
+    ConflictingDescendants { term_id: String },
+    #[error("unknown value in applies_to column for {term_id}: {value}")]
+    UnknownAppliesTo { term_id: String, value: String },
+    #[error("pair rule for {term_id} has no object_term")]
+    MissingObjectTerm { term_id: String },
 }
 
 use pombase_gocam::REL_NAMES;
@@ -13,14 +21,99 @@ use pombase_gocam::REL_NAMES;
 pub type TermId = String;
 pub type RelId = String;
 
+/// Which terms a config line applies to, from the "descendents" column
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConfigDescendants {
+    // "ALL": the term and all of its descendants
+    All,
+    // "SELF": only the term itself
+    SelfOnly,
+    // "NONE": the rule is switched off
+    Disabled,
+    // a number: the term and its descendants up to that many is_a steps below
+    MaxDepth(usize),
+}
+
+impl ConfigDescendants {
+    fn parse(term_id: &str, value: &str) -> Result<ConfigDescendants, AllowedRelConfigError> {
+        match value.trim() {
+            "" | "ALL" => Ok(ConfigDescendants::All),
+            "SELF" => Ok(ConfigDescendants::SelfOnly),
+            "NONE" => Ok(ConfigDescendants::Disabled),
+            value => {
+                let Ok(depth) = value.parse::<usize>()
+                else {
+                    return Err(AllowedRelConfigError::UnknownDescendants {
+                        term_id: term_id.to_owned(),
+                        value: value.to_owned(),
+                    });
+                };
+                Ok(ConfigDescendants::MaxDepth(depth))
+            }
+        }
+    }
+}
+
+/// What a config line is keyed on, from the "applies_to" and "object_term"
+/// columns
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum RuleKey {
+    // "subject" or empty: the relations allowed from activities with this term
+    Subject(TermId),
+    // "object": the relations allowed to activities with this term
+    Object(TermId),
+    // "pair": the relations allowed from the first term to the second
+    Pair(TermId, TermId),
+}
+
 pub(crate) struct AllowedRelConfig {
     pub _term_config: HashMap<TermId, HashSet<RelId>>,
+    pub _object_term_config: HashMap<TermId, HashSet<RelId>>,
+    pub _pair_config: HashMap<(TermId, TermId), HashSet<RelId>>,
+    pub _descendants: HashMap<RuleKey, ConfigDescendants>,
 }
 
 impl AllowedRelConfig {
     pub fn term_config(&self) -> &HashMap<TermId, HashSet<RelId>> {
         &self._term_config
     }
+
+    pub fn object_term_config(&self) -> &HashMap<TermId, HashSet<RelId>> {
+        &self._object_term_config
+    }
+
+    pub fn pair_config(&self) -> &HashMap<(TermId, TermId), HashSet<RelId>> {
+        &self._pair_config
+    }
+
+    pub fn descendants(&self, key: &RuleKey) -> ConfigDescendants {
+        self._descendants.get(key).copied()
+            .unwrap_or(ConfigDescendants::All)
+    }
+}
+
+fn parse_rule_key(term_id: &str, bits: &[&str])
+    -> Result<RuleKey, AllowedRelConfigError>
+{
+    let applies_to = bits.get(5).map(|s| s.trim()).unwrap_or("");
+
+    match applies_to {
+        "" | "subject" => Ok(RuleKey::Subject(term_id.to_owned())),
+        "object" => Ok(RuleKey::Object(term_id.to_owned())),
+        "pair" => {
+            let object_term_id = bits.get(6).map(|s| s.trim()).unwrap_or("");
+            if object_term_id.is_empty() {
+                return Err(AllowedRelConfigError::MissingObjectTerm {
+                    term_id: term_id.to_owned(),
+                });
+            }
+            Ok(RuleKey::Pair(term_id.to_owned(), object_term_id.to_owned()))
+        },
+        _ => Err(AllowedRelConfigError::UnknownAppliesTo {
+            term_id: term_id.to_owned(),
+            value: applies_to.to_owned(),
+        }),
+    }
 }
 
 pub(crate) fn parse_allowed_relations_config(buf_reader: &mut dyn BufRead)
@@ -31,6 +124,9 @@ pub(crate) fn parse_allowed_relations_config(buf_reader: &mut dyn BufRead)
         .map(|(k, v)| (v.to_string(), k.to_string())).collect();
 
     let mut term_config = HashMap::new();
+    let mut object_term_config = HashMap::new();
+    let mut pair_config = HashMap::new();
+    let mut descendants_map = HashMap::new();
 
     for line_result in buf_reader.lines() {
         let line = line_result?;
@@ -49,8 +145,23 @@ pub(crate) fn parse_allowed_relations_config(buf_reader: &mut dyn BufRead)
             continue;
         }
 
+        let rule_key = parse_rule_key(term_id, &bits)?;
+
+        let descendants =
+            ConfigDescendants::parse(term_id, bits.get(3).unwrap_or(&""))?;
+
+        if let Some(existing) = descendants_map.insert(rule_key.clone(), descendants) &&
+            existing != descendants
+        {
+            return Err(AllowedRelConfigError::ConflictingDescendants {
+                term_id: term_id.to_owned(),
+            });
+        }
+
         let allowed_rel_names = bits[2].to_owned();
 
+        let mut rel_ids = HashSet::new();
+
         for allowed_rel_name in allowed_rel_names.split("|") {
             let allowed_rel_name = allowed_rel_name.trim();
             if allowed_rel_name.is_empty() {
@@ -62,14 +173,35 @@ pub(crate) fn parse_allowed_relations_config(buf_reader: &mut dyn BufRead)
                 eprintln!("unknown relation name in allowed relation config: {}", allowed_rel_name);
                 continue;
             };
-            term_config.entry(term_id.to_owned())
-                .or_insert_with(HashSet::new)
-                .insert(rel_id.to_owned());
+            rel_ids.insert(rel_id.to_owned());
+        }
+
+        if rel_ids.is_empty() {
+            continue;
+        }
+
+        match rule_key {
+            RuleKey::Subject(term_id) => {
+                term_config.entry(term_id).or_insert_with(HashSet::new)
+                    .extend(rel_ids);
+            },
+            RuleKey::Object(term_id) => {
+                object_term_config.entry(term_id).or_insert_with(HashSet::new)
+                    .extend(rel_ids);
+            },
+            RuleKey::Pair(subject_term_id, object_term_id) => {
+                pair_config.entry((subject_term_id, object_term_id))
+                    .or_insert_with(HashSet::new)
+                    .extend(rel_ids);
+            },
         }
     }
 
     Ok(AllowedRelConfig {
         _term_config: term_config,
+        _object_term_config: object_term_config,
+        _pair_config: pair_config,
+        _descendants: descendants_map,
     })
 }
 
@@ -79,6 +211,8 @@ mod tests {
     use std::io::BufReader;
     use std::fs::File;
 
+    use super::{ConfigDescendants, RuleKey};
+
     #[test]
     fn parse_test() {
         let file = File::open("tests/data/config.tsv").unwrap();
@@ -95,5 +229,49 @@ mod tests {
         expected_rels.insert("RO:0002409".to_owned());
         expected_rels.insert("RO:0002413".to_owned());
         assert_eq!(rels, &expected_rels);
+
+        assert_eq!(config.descendants(&RuleKey::Subject("GO:0003824".to_owned())),
+                   ConfigDescendants::All);
+    }
+
+    #[test]
+    fn parse_descendants_test() {
+        let config_text = "term\tterm_name\tallowed_relations\tdescendents\tnotes\n\
+                           GO:0048018\treceptor ligand activity\tdirectly positively regulates\tSELF\t\n\
+                           GO:0038023\tsignaling receptor activity\tdirectly positively regulates\tNONE\t\n\
+                           GO:0003824\tcatalytic activity\tprovides input for\t2\n";
+        let config = crate::parse_allowed_relations_config(&mut config_text.as_bytes()).unwrap();
+
+        let descendants = |term_id: &str| {
+            config.descendants(&RuleKey::Subject(term_id.to_owned()))
+        };
+
+        assert_eq!(descendants("GO:0048018"), ConfigDescendants::SelfOnly);
+        assert_eq!(descendants("GO:0038023"), ConfigDescendants::Disabled);
+        assert_eq!(descendants("GO:0003824"), ConfigDescendants::MaxDepth(2));
+
+        let bad_config_text = "GO:0003824\tcatalytic activity\tprovides input for\tSOME\n";
+        assert!(crate::parse_allowed_relations_config(&mut bad_config_text.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn parse_object_and_pair_test() {
+        let config_text = "term\tterm_name\tallowed_relations\tdescendents\tnotes\tapplies_to\tobject_term\n\
+                           GO:0003824\tcatalytic activity\tdirectly positively regulates\tALL\t\tobject\n\
+                           GO:0003824\tcatalytic activity\tprovides input for\tALL\t\tsubject\n\
+                           GO:0003824\tcatalytic activity\tprovides input for|directly positively regulates\tSELF\t\tpair\tGO:0048018\n";
+        let config = crate::parse_allowed_relations_config(&mut config_text.as_bytes()).unwrap();
+
+        assert_eq!(config.term_config().len(), 1);
+        assert_eq!(config.object_term_config().len(), 1);
+        assert_eq!(config.object_term_config().get("GO:0003824").unwrap().len(), 1);
+
+        let pair_key = ("GO:0003824".to_owned(), "GO:0048018".to_owned());
+        assert_eq!(config.pair_config().get(&pair_key).unwrap().len(), 2);
+        assert_eq!(config.descendants(&RuleKey::Pair(pair_key.0, pair_key.1)),
+                   ConfigDescendants::SelfOnly);
+
+        let bad_config_text = "GO:0003824\tcatalytic activity\tprovides input for\tALL\t\tpair\n";
+        assert!(crate::parse_allowed_relations_config(&mut bad_config_text.as_bytes()).is_err());
     }
 }
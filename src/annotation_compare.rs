@@ -0,0 +1,299 @@
+use std::{collections::HashMap, fmt::Display, io::BufRead};
+
+use thiserror::Error;
+
+use crate::go_format_writer::GoAnnotation;
+use crate::ontology_info::OntologyInfo;
+
+#[derive(Error, Debug)]
+pub enum AnnotationFileError {
+    #[error("I/O error: {0}")]
+    IOError(#[from] std::io::Error),
+    #[error("line {line_number} has {count} columns, expected at least {expected}")]
+    ColumnCountError { line_number: usize, count: usize, expected: usize },
+    #[error("line {line_number}: DB object ID has no DB prefix: {id}")]
+    MissingPrefix { line_number: usize, id: String },
+}
+
+/// An annotation read from an existing GAF or GPAD file
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct ExistingAnnotation {
+    pub line_number: usize,
+    pub object_db: String,
+    pub object_id: String,
+    pub negated: bool,
+    // GAF qualifier label (eg. "enables") or GPAD relation ID
+    pub relation: String,
+    pub term_id: String,
+    pub reference: String,
+    // a GO evidence code from a GAF or an ECO ID from a GPAD file
+    pub evidence: String,
+}
+
+fn parse_gaf_line(line_number: usize, columns: &[&str])
+    -> Result<ExistingAnnotation, AnnotationFileError>
+{
+    if columns.len() < 15 {
+        return Err(AnnotationFileError::ColumnCountError {
+            line_number,
+            count: columns.len(),
+            expected: 15,
+        });
+    }
+
+    let mut qualifiers = columns[3].split('|');
+    let negated = columns[3].split('|').any(|qualifier| qualifier == "NOT");
+    let relation = qualifiers.find(|qualifier| *qualifier != "NOT").unwrap_or_default();
+
+    Ok(ExistingAnnotation {
+        line_number,
+        object_db: columns[0].to_owned(),
+        object_id: columns[1].to_owned(),
+        negated,
+        relation: relation.to_owned(),
+        term_id: columns[4].to_owned(),
+        reference: columns[5].to_owned(),
+        evidence: columns[6].to_owned(),
+    })
+}
+
+fn parse_gpad_line(line_number: usize, columns: &[&str])
+    -> Result<ExistingAnnotation, AnnotationFileError>
+{
+    if columns.len() < 6 {
+        return Err(AnnotationFileError::ColumnCountError {
+            line_number,
+            count: columns.len(),
+            expected: 6,
+        });
+    }
+
+    let Some((object_db, object_id)) = columns[0].split_once(':')
+    else {
+        return Err(AnnotationFileError::MissingPrefix {
+            line_number,
+            id: columns[0].to_owned(),
+        });
+    };
+
+    Ok(ExistingAnnotation {
+        line_number,
+        object_db: object_db.to_owned(),
+        object_id: object_id.to_owned(),
+        negated: columns[1] == "NOT",
+        relation: columns[2].to_owned(),
+        term_id: columns[3].to_owned(),
+        reference: columns[4].to_owned(),
+        evidence: columns[5].to_owned(),
+    })
+}
+
+/// Read a GAF or, if it has a "!gpa-version" header, a GPAD 2.0 file
+pub(crate) fn parse_annotation_file(reader: &mut dyn BufRead)
+    -> Result<Vec<ExistingAnnotation>, AnnotationFileError>
+{
+    let mut ret = vec![];
+    let mut is_gpad = false;
+
+    for (idx, line_result) in reader.lines().enumerate() {
+        let line = line_result?;
+        let line_number = idx + 1;
+
+        if line.starts_with("!gpa-version") {
+            is_gpad = true;
+        }
+
+        if line.starts_with('!') || line.trim().is_empty() {
+            continue;
+        }
+
+        let columns: Vec<_> = line.split('\t').map(str::trim).collect();
+
+        let annotation =
+            if is_gpad {
+                parse_gpad_line(line_number, &columns)?
+            } else {
+                parse_gaf_line(line_number, &columns)?
+            };
+
+        ret.push(annotation);
+    }
+
+    Ok(ret)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub(crate) enum ComparisonStatus {
+    // not in the existing file with this reference and evidence
+    Missing,
+    // already in the existing file
+    Duplicate,
+    // the existing file has a NOT annotation of the gene to the term or
+    // to an ancestor of it
+    Conflict,
+}
+
+impl Display for ComparisonStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            ComparisonStatus::Missing => "missing",
+            ComparisonStatus::Duplicate => "duplicate",
+            ComparisonStatus::Conflict => "conflict",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// How one GO-CAM annotation compares with an existing annotation file
+#[derive(Clone, Debug)]
+pub(crate) struct AnnotationComparison<'a> {
+    pub status: ComparisonStatus,
+    pub annotation: &'a GoAnnotation,
+    // the line in the existing file that is duplicated or conflicting
+    pub existing: Option<&'a ExistingAnnotation>,
+}
+
+impl AnnotationComparison<'_> {
+    pub fn to_tsv(&self) -> String {
+        let annotation = self.annotation;
+        let existing_line = self.existing
+            .map(|existing| existing.line_number.to_string())
+            .unwrap_or_default();
+
+        format!("{}\t{}:{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}", self.status,
+                annotation.object_db, annotation.gene_uniquename,
+                annotation.relation.label(), annotation.term_id, annotation.aspect,
+                annotation.reference, annotation.go_ev_code, annotation.model_id,
+                existing_line)
+    }
+}
+
+pub(crate) const COMPARISON_TSV_HEADER: &str =
+    "status\tdb_object\tqualifier\tterm_id\taspect\treference\tevidence\tmodel_id\texisting_line";
+
+fn is_duplicate(annotation: &GoAnnotation, existing: &ExistingAnnotation) -> bool {
+    !existing.negated && existing.reference == annotation.reference &&
+        (existing.evidence == annotation.go_ev_code || existing.evidence == annotation.eco_code)
+}
+
+fn is_conflict(annotation: &GoAnnotation, existing: &ExistingAnnotation,
+               ontology_info: Option<&OntologyInfo>) -> bool {
+    existing.negated &&
+        (existing.term_id == annotation.term_id ||
+         ontology_info.is_some_and(|ontology_info| {
+             ontology_info.is_a(&annotation.term_id, &existing.term_id)
+         }))
+}
+
+/// Compare annotations made from GO-CAM models with an existing GAF or
+/// GPAD file.  NOT annotations to ancestors of a term are only found if
+/// ontology_info has a closure.
+pub(crate) fn compare_annotations<'a>(annotations: &'a [GoAnnotation],
+                                      existing_annotations: &'a [ExistingAnnotation],
+                                      ontology_info: Option<&OntologyInfo>)
+    -> Vec<AnnotationComparison<'a>>
+{
+    let mut existing_by_object: HashMap<(&str, &str), Vec<&ExistingAnnotation>> = HashMap::new();
+
+    for existing in existing_annotations {
+        existing_by_object.entry((&existing.object_db, &existing.object_id))
+            .or_default()
+            .push(existing);
+    }
+
+    annotations.iter()
+        .map(|annotation| {
+            let object_existing = existing_by_object
+                .get(&(annotation.object_db.as_str(), annotation.gene_uniquename.as_str()))
+                .map(Vec::as_slice)
+                .unwrap_or_default();
+
+            if let Some(existing) = object_existing.iter()
+                .find(|existing| is_conflict(annotation, existing, ontology_info))
+            {
+                return AnnotationComparison {
+                    status: ComparisonStatus::Conflict,
+                    annotation,
+                    existing: Some(*existing),
+                };
+            }
+
+            if let Some(existing) = object_existing.iter()
+                .find(|existing| {
+                    existing.term_id == annotation.term_id && is_duplicate(annotation, existing)
+                })
+            {
+                return AnnotationComparison {
+                    status: ComparisonStatus::Duplicate,
+                    annotation,
+                    existing: Some(*existing),
+                };
+            }
+
+            AnnotationComparison {
+                status: ComparisonStatus::Missing,
+                annotation,
+                existing: None,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::BTreeMap, fs::File, io::BufReader};
+
+    use pombase_gocam::gocam_py::gocam_py_parse;
+
+    use crate::go_format_writer::{annotations_from_model, tests::test_config};
+    use crate::ontology_info::parse_closure;
+
+    use super::{ComparisonStatus, compare_annotations, parse_annotation_file};
+
+    fn status_counts(path: &str, with_closure: bool) -> BTreeMap<ComparisonStatus, usize> {
+        let mut source = File::open("tests/data/67ae98b500000055.yaml").unwrap();
+        let model = gocam_py_parse(&mut source).unwrap();
+        let annotations = annotations_from_model(&test_config(), &model).annotations;
+        assert_eq!(annotations.len(), 22);
+
+        let file = File::open(path).unwrap();
+        let existing = parse_annotation_file(&mut BufReader::new(file)).unwrap();
+        assert_eq!(existing.len(), 4);
+
+        let ontology_info = if with_closure {
+            let closure_file = File::open("tests/data/closure.tsv").unwrap();
+            Some(parse_closure(&mut BufReader::new(closure_file)).unwrap())
+        } else {
+            None
+        };
+
+        let comparisons = compare_annotations(&annotations, &existing, ontology_info.as_ref());
+
+        let conflict = comparisons.iter()
+            .find(|comparison| comparison.status == ComparisonStatus::Conflict)
+            .unwrap();
+        assert_eq!(conflict.annotation.gene_uniquename, "SPAC3H1.07");
+        assert_eq!(conflict.existing.unwrap().line_number, 6);
+
+        let mut counts = BTreeMap::new();
+        for comparison in comparisons {
+            *counts.entry(comparison.status).or_default() += 1;
+        }
+        counts
+    }
+
+    #[test]
+    fn compare_test() {
+        for path in ["tests/data/existing.gaf", "tests/data/existing.gpad"] {
+            let counts = status_counts(path, false);
+            assert_eq!(counts[&ComparisonStatus::Missing], 20);
+            assert_eq!(counts[&ComparisonStatus::Duplicate], 1);
+            assert_eq!(counts[&ComparisonStatus::Conflict], 1);
+
+            // the NOT annotation to GO:0003824 conflicts with a descendant
+            let counts = status_counts(path, true);
+            assert_eq!(counts[&ComparisonStatus::Missing], 19);
+            assert_eq!(counts[&ComparisonStatus::Conflict], 2);
+        }
+    }
+}
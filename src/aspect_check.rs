@@ -0,0 +1,209 @@
+use pombase_gocam::{GoCamModel, GoCamNodeType, gocam_py::GoCamPyModel};
+
+use crate::diagnostic::{Diagnostic, DiagnosticCollector, DiagnosticKind, DiagnosticTerm,
+                        Severity};
+use crate::gocam_py_terms::{SlotKind, term_slots};
+use crate::ontology_info::OntologyInfo;
+
+const MOLECULAR_FUNCTION: &str = "molecular_function";
+const BIOLOGICAL_PROCESS: &str = "biological_process";
+const CELLULAR_COMPONENT: &str = "cellular_component";
+
+const GO_ASPECTS: [&str; 3] = [MOLECULAR_FUNCTION, BIOLOGICAL_PROCESS, CELLULAR_COMPONENT];
+
+// collects one Diagnostic per (term, field) pair
+struct AspectCollector<'a> {
+    model_id: &'a str,
+    ontology_info: &'a OntologyInfo,
+    diagnostics: DiagnosticCollector<(String, String)>,
+}
+
+impl<'a> AspectCollector<'a> {
+    fn new(model_id: &'a str, ontology_info: &'a OntologyInfo) -> AspectCollector<'a> {
+        AspectCollector {
+            model_id,
+            ontology_info,
+            diagnostics: DiagnosticCollector::default(),
+        }
+    }
+
+    // Terms without a namespace or from outside GO (eg. cell types in
+    // occurs_in or phases in happens_during) aren't checked
+    fn check(&mut self, term_id: &str, term_name: &str, field: &str,
+             expected_aspect: &str, individual_id: &str) {
+        let model_id = self.model_id;
+
+        let Some(actual_aspect) = self.ontology_info.term_namespace(term_id)
+        else {
+            return;
+        };
+
+        if actual_aspect == expected_aspect || !GO_ASPECTS.contains(&actual_aspect) {
+            return;
+        }
+
+        let key = (term_id.to_owned(), field.to_owned());
+
+        self.diagnostics.add(key, Some(individual_id), || {
+            let mut diagnostic =
+                Diagnostic::new(DiagnosticKind::WrongAspect, Severity::Error, model_id);
+            diagnostic.subject_term = Some(DiagnosticTerm {
+                id: term_id.to_owned(),
+                name: term_name.to_owned(),
+            });
+            diagnostic.field = Some(field.to_owned());
+            diagnostic.expected_aspect = Some(expected_aspect.to_owned());
+            diagnostic.actual_aspect = Some(actual_aspect.to_owned());
+            diagnostic
+        });
+    }
+
+    fn check_id(&mut self, term_id: &str, field: &str, expected_aspect: &str,
+                individual_id: &str) {
+        let ontology_info = self.ontology_info;
+        let term_name = ontology_info.term_name(term_id).unwrap_or_default();
+        self.check(term_id, term_name, field, expected_aspect, individual_id);
+    }
+}
+
+/// Find terms that are in a slot for a different GO aspect, eg. a
+/// biological_process term used as a molecular_function.  Needs the
+/// namespaces from an ontology file.
+pub(crate) fn find_aspect_mismatches(model: &GoCamModel, ontology_info: &OntologyInfo)
+    -> Vec<Diagnostic>
+{
+    let mut collector = AspectCollector::new(model.id(), ontology_info);
+
+    for (_, node) in model.node_iterator() {
+        let individual_id = node.individual_gocam_id.to_string();
+
+        if node.is_activity() {
+            collector.check(&node.node_id, &node.label, "molecular_function",
+                            MOLECULAR_FUNCTION, &individual_id);
+
+            for occurs_in in node.occurs_in.iter() {
+                collector.check(occurs_in.id(), occurs_in.label(), "occurs_in",
+                                CELLULAR_COMPONENT, &individual_id);
+            }
+
+            if let Some(ref part_of_process) = node.part_of_process {
+                collector.check(part_of_process.id(), &part_of_process.label,
+                                "part_of_process", BIOLOGICAL_PROCESS, &individual_id);
+            }
+        }
+
+        if let GoCamNodeType::Chemical(ref chemical) = node.node_type &&
+            let Some(ref located_in) = chemical.located_in
+        {
+            collector.check(located_in.id(), located_in.label(), "chemical.located_in",
+                            CELLULAR_COMPONENT, &individual_id);
+        }
+
+        if let Some(ref happens_during) = node.happens_during {
+            collector.check(happens_during.id(), happens_during.label_or_id(),
+                            "happens_during", BIOLOGICAL_PROCESS, &individual_id);
+        }
+    }
+
+    collector.diagnostics.into_diagnostics()
+}
+
+// the aspect of the terms that a slot needs, if it needs one
+fn expected_aspect(slot_kind: SlotKind) -> Option<&'static str> {
+    match slot_kind {
+        SlotKind::MolecularFunction => Some(MOLECULAR_FUNCTION),
+        SlotKind::BiologicalProcess | SlotKind::HappensDuring => Some(BIOLOGICAL_PROCESS),
+        SlotKind::CellularComponent => Some(CELLULAR_COMPONENT),
+        SlotKind::Enabler | SlotKind::ComplexMember | SlotKind::Evidence |
+        SlotKind::Molecule => None,
+    }
+}
+
+/// The same as find_aspect_mismatches() but with the field paths of the
+/// gocam-py model, eg. "activities[2].occurs_in.term"
+pub(crate) fn find_aspect_mismatches_in_gocam_py(model: &GoCamPyModel,
+                                                 ontology_info: &OntologyInfo)
+    -> Vec<Diagnostic>
+{
+    let mut collector = AspectCollector::new(&model.id, ontology_info);
+
+    for slot in term_slots(model) {
+        if let Some(expected_aspect) = expected_aspect(slot.kind) {
+            collector.check_id(slot.term_id, &slot.field, expected_aspect, slot.individual_id);
+        }
+    }
+
+    collector.diagnostics.into_diagnostics()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+
+    use pombase_gocam::{gocam_py::gocam_py_parse, parse_gocam_py_model};
+
+    use crate::diagnostic::DiagnosticKind;
+    use crate::ontology_info::{OntologyInfo, TermInfo};
+
+    use super::{find_aspect_mismatches, find_aspect_mismatches_in_gocam_py};
+
+    fn term_info(id: &str, name: &str, namespace: &str) -> TermInfo {
+        TermInfo {
+            id: id.to_owned(),
+            name: Some(name.to_owned()),
+            namespace: Some(namespace.to_owned()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn aspect_test() {
+        let mut ontology_info = OntologyInfo::default();
+        ontology_info.add_terms(vec![
+            // correct
+            term_info("GO:0005886", "plasma membrane", "cellular_component"),
+            // an MF term pretending to be a BP
+            term_info("GO:0042929", "ferrichrome transmembrane transporter activity",
+                      "biological_process"),
+            // not a GO aspect so ignored
+            term_info("CHEBI:36080", "protein", "chebi_ontology"),
+            // correct in occurs_in, wrong in happens_during below
+            term_info("GO:0005829", "cytosol", "cellular_component"),
+        ]);
+
+        let mut source = File::open("tests/data/67ae98b500000055.yaml").unwrap();
+        let mut py_model = gocam_py_parse(&mut source).unwrap();
+
+        let diagnostics = find_aspect_mismatches_in_gocam_py(&py_model, &ontology_info);
+
+        assert_eq!(diagnostics.len(), 1);
+        let diagnostic = &diagnostics[0];
+        assert_eq!(diagnostic.kind, DiagnosticKind::WrongAspect);
+        assert_eq!(diagnostic.field.as_deref(), Some("activities[0].molecular_function.term"));
+        assert_eq!(diagnostic.individual_ids,
+                   vec!["gomodel:67ae98b500000055/67b1629100000017".to_owned()]);
+        assert_eq!(diagnostic.to_string(),
+                   "activities[0].molecular_function.term GO:0042929 ferrichrome transmembrane transporter activity is a biological_process term, expected molecular_function");
+
+        py_model.activities[0].part_of.as_mut().unwrap().happens_during =
+            Some("GO:0005829".to_owned());
+
+        let diagnostics = find_aspect_mismatches_in_gocam_py(&py_model, &ontology_info);
+        let happens_during_diagnostic = diagnostics.iter()
+            .find(|diagnostic| {
+                diagnostic.field.as_deref() == Some("activities[0].part_of.happens_during.term")
+            })
+            .unwrap();
+        assert_eq!(happens_during_diagnostic.expected_aspect.as_deref(),
+                   Some("biological_process"));
+
+        let mut source = File::open("tests/data/67ae98b500000055.yaml").unwrap();
+        let model = parse_gocam_py_model(&mut source).unwrap();
+
+        let diagnostics = find_aspect_mismatches(&model, &ontology_info);
+
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].field.as_deref(), Some("molecular_function"));
+        assert_eq!(diagnostics[0].subject_term.as_ref().unwrap().id, "GO:0042929");
+    }
+}
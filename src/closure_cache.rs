@@ -0,0 +1,260 @@
+use std::{fs::{self, File}, io::{Read, Write}, path::{Path, PathBuf},
+          sync::atomic::{AtomicUsize, Ordering}};
+
+use crate::ontology_info::{ClosureError, InternedId, OntologyInfo, TermClosure,
+                           parse_closure};
+
+// bump the version when the layout changes so old caches are rebuilt
+const CACHE_MAGIC: &[u8; 8] = b"GCTCLO01";
+
+static TEMP_FILE_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// The cache for "closure.tsv" is "closure.tsv.cache" in the same
+/// directory
+pub(crate) fn cache_path(closure_path: &Path) -> PathBuf {
+    let mut file_name = closure_path.file_name().unwrap_or_default().to_owned();
+    file_name.push(".cache");
+    closure_path.with_file_name(file_name)
+}
+
+// FNV-1a, which is fast enough that hashing the closure file costs much
+// less than parsing it
+fn checksum(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+
+    for byte in bytes {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+
+    hash
+}
+
+fn write_u32(buf: &mut Vec<u8>, value: u32) {
+    buf.extend_from_slice(&value.to_le_bytes());
+}
+
+fn encode(closure: &TermClosure, source_checksum: u64) -> Vec<u8> {
+    let mut buf = vec![];
+
+    buf.extend_from_slice(CACHE_MAGIC);
+    buf.extend_from_slice(&source_checksum.to_le_bytes());
+
+    write_u32(&mut buf, closure.ids.len() as u32);
+    for id in &closure.ids {
+        write_u32(&mut buf, id.len() as u32);
+        buf.extend_from_slice(id.as_bytes());
+    }
+
+    write_u32(&mut buf, closure.parent_offsets.len() as u32);
+    for offset in &closure.parent_offsets {
+        write_u32(&mut buf, *offset);
+    }
+
+    write_u32(&mut buf, closure.parents.len() as u32);
+    for (rel, parent) in &closure.parents {
+        write_u32(&mut buf, *rel);
+        write_u32(&mut buf, *parent);
+    }
+
+    buf
+}
+
+struct CacheReader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> CacheReader<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        if self.bytes.len() < len {
+            return None;
+        }
+        let (ret, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Some(ret)
+    }
+
+    fn read_u32(&mut self) -> Option<u32> {
+        Some(u32::from_le_bytes(self.take(4)?.try_into().ok()?))
+    }
+
+    fn read_u64(&mut self) -> Option<u64> {
+        Some(u64::from_le_bytes(self.take(8)?.try_into().ok()?))
+    }
+}
+
+// None if the cache is for a different version of the source file or is
+// truncated or from an older version of this code
+fn decode(bytes: &[u8], source_checksum: u64) -> Option<TermClosure> {
+    let mut reader = CacheReader { bytes };
+
+    if reader.take(CACHE_MAGIC.len())? != CACHE_MAGIC ||
+        reader.read_u64()? != source_checksum
+    {
+        return None;
+    }
+
+    let id_count = reader.read_u32()? as usize;
+    let mut ids = Vec::with_capacity(id_count);
+    for _ in 0..id_count {
+        let len = reader.read_u32()? as usize;
+        ids.push(String::from_utf8(reader.take(len)?.to_vec()).ok()?);
+    }
+
+    let offset_count = reader.read_u32()? as usize;
+    let mut parent_offsets = Vec::with_capacity(offset_count);
+    for _ in 0..offset_count {
+        parent_offsets.push(reader.read_u32()?);
+    }
+
+    let parent_count = reader.read_u32()? as usize;
+    let mut parents: Vec<(InternedId, InternedId)> = Vec::with_capacity(parent_count);
+    for _ in 0..parent_count {
+        parents.push((reader.read_u32()?, reader.read_u32()?));
+    }
+
+    let id_count = ids.len() as InternedId;
+
+    if !reader.bytes.is_empty() || parent_offsets.len() != ids.len() + 1 ||
+        parent_offsets.windows(2).any(|offsets| offsets[0] > offsets[1]) ||
+        parent_offsets.last().is_some_and(|last| *last as usize != parents.len()) ||
+        parents.iter().any(|(rel, parent)| *rel >= id_count || *parent >= id_count)
+    {
+        return None;
+    }
+
+    // the lookups binary search the IDs and the parents of each term, so
+    // they must be sorted
+    let parents_sorted = parent_offsets.windows(2).all(|offsets| {
+        parents[offsets[0] as usize..offsets[1] as usize].windows(2)
+            .all(|pair| pair[0] < pair[1])
+    });
+
+    if !ids.windows(2).all(|pair| pair[0] < pair[1]) || !parents_sorted {
+        return None;
+    }
+
+    Some(TermClosure {
+        ids,
+        parent_offsets,
+        parents,
+    })
+}
+
+// returns the closure and true if it came from the cache
+fn read_closure_with_cache(closure_path: &Path, cache_path: &Path)
+    -> Result<(OntologyInfo, bool), ClosureError>
+{
+    let mut source = vec![];
+    File::open(closure_path)?.read_to_end(&mut source)?;
+
+    let source_checksum = checksum(&source);
+
+    if let Ok(mut cache_file) = File::open(cache_path) {
+        let mut cache_bytes = vec![];
+        cache_file.read_to_end(&mut cache_bytes)?;
+
+        if let Some(closure) = decode(&cache_bytes, source_checksum) {
+            let ontology_info = OntologyInfo {
+                closure,
+                ..Default::default()
+            };
+            return Ok((ontology_info, true));
+        }
+    }
+
+    let ontology_info = parse_closure(&mut source.as_slice())?;
+
+    // written to a temporary file and renamed so that another process
+    // reading the closure never sees a partial cache
+    let temp_count = TEMP_FILE_COUNTER.fetch_add(1, Ordering::Relaxed);
+    let temp_path =
+        cache_path.with_extension(format!("tmp.{}.{}", std::process::id(), temp_count));
+
+    // a read-only directory shouldn't stop the checks from running
+    let write_result = File::create(&temp_path)
+        .and_then(|mut cache_file| {
+            cache_file.write_all(&encode(&ontology_info.closure, source_checksum))
+        })
+        .and_then(|_| fs::rename(&temp_path, cache_path));
+    if let Err(err) = write_result {
+        let _ = fs::remove_file(&temp_path);
+        eprintln!("failed to write closure cache {}: {}", cache_path.display(), err);
+    }
+
+    Ok((ontology_info, false))
+}
+
+/// Read a closure file, using the cache file next to it if it was made
+/// from the same version of the closure.  Otherwise the closure is parsed
+/// and the cache rewritten.
+pub(crate) fn read_closure_file(closure_path: &Path) -> Result<OntologyInfo, ClosureError> {
+    let (ontology_info, _) = read_closure_with_cache(closure_path, &cache_path(closure_path))?;
+    Ok(ontology_info)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{self, File};
+    use std::io::BufReader;
+
+    use crate::ontology_info::parse_closure;
+
+    use super::{cache_path, decode, encode, read_closure_with_cache};
+
+    #[test]
+    fn cache_test() {
+        let dir = std::env::temp_dir().join(format!("closure_cache_test_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let closure_path = dir.join("closure.tsv");
+        fs::copy("tests/data/closure.tsv", &closure_path).unwrap();
+        let cache_path = cache_path(&closure_path);
+        assert_eq!(cache_path.file_name().unwrap(), "closure.tsv.cache");
+
+        let (parsed, from_cache) = read_closure_with_cache(&closure_path, &cache_path).unwrap();
+        assert!(!from_cache);
+        assert!(cache_path.exists());
+
+        let (cached, from_cache) = read_closure_with_cache(&closure_path, &cache_path).unwrap();
+        assert!(from_cache);
+        assert_eq!(parsed.closure, cached.closure);
+        assert!(cached.is_a("GO:0042929", "GO:0003824"));
+
+        // a changed closure file makes the cache stale
+        let mut contents = fs::read_to_string(&closure_path).unwrap();
+        contents.push_str("GO:9999999\trdfs:subClassOf\tGO:0003824\n");
+        fs::write(&closure_path, contents).unwrap();
+
+        let (rebuilt, from_cache) = read_closure_with_cache(&closure_path, &cache_path).unwrap();
+        assert!(!from_cache);
+        assert!(rebuilt.is_a("GO:9999999", "GO:0003824"));
+
+        // a corrupt cache is ignored
+        fs::write(&cache_path, b"GCTCLO01 truncated").unwrap();
+        let (_, from_cache) = read_closure_with_cache(&closure_path, &cache_path).unwrap();
+        assert!(!from_cache);
+
+        // no temporary files are left behind
+        let mut file_names: Vec<_> = fs::read_dir(&dir).unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        file_names.sort();
+        assert_eq!(file_names, vec!["closure.tsv", "closure.tsv.cache"]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn decode_unsorted_test() {
+        let file = File::open("tests/data/closure.tsv").unwrap();
+        let mut closure = parse_closure(&mut BufReader::new(file)).unwrap().closure;
+
+        assert_eq!(decode(&encode(&closure, 1), 1).unwrap(), closure);
+        assert!(decode(&encode(&closure, 1), 2).is_none());
+
+        // in range but not sorted, so lookups would give wrong answers
+        closure.ids.swap(0, 1);
+        assert!(decode(&encode(&closure, 1), 1).is_none());
+    }
+}
@@ -0,0 +1,395 @@
+use std::{collections::{BTreeMap, BTreeSet}, io::{Error, Write}};
+
+use itertools::Itertools;
+use pombase_gocam::{GoCamActivity, GoCamEnabledBy, GoCamModel, GoCamNodeType};
+use pombase_gocam_process::get_stats;
+
+/// The numbers from get_stats() for a model, or summed for a taxon
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) struct ModelStats {
+    pub total_genes: usize,
+    pub total_complexes: usize,
+    pub connected_genes: usize,
+    pub max_connected_activities: usize,
+    pub total_connected_activities: usize,
+    pub number_of_holes: usize,
+}
+
+const STAT_NAMES: [&str; 6] = ["total_genes", "total_complexes", "connected_genes",
+                               "max_connected_activities", "total_connected_activities",
+                               "number_of_holes"];
+
+impl ModelStats {
+    fn values(&self) -> [usize; 6] {
+        [self.total_genes, self.total_complexes, self.connected_genes,
+         self.max_connected_activities, self.total_connected_activities,
+         self.number_of_holes]
+    }
+
+    // max_connected_activities is the largest of the models, the rest
+    // are totals
+    fn add(&mut self, other: &ModelStats) {
+        self.total_genes += other.total_genes;
+        self.total_complexes += other.total_complexes;
+        self.connected_genes += other.connected_genes;
+        self.max_connected_activities =
+            self.max_connected_activities.max(other.max_connected_activities);
+        self.total_connected_activities += other.total_connected_activities;
+        self.number_of_holes += other.number_of_holes;
+    }
+}
+
+/// The parts of a model that are compared between snapshots
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct ModelSummary {
+    pub id: String,
+    pub title: String,
+    pub taxon: String,
+    // only gocam-py models have a status
+    pub status: Option<String>,
+    // genes enabling activities, directly or as part of a complex
+    pub genes: BTreeSet<String>,
+    pub stats: ModelStats,
+}
+
+impl ModelSummary {
+    pub fn from_model(model: &GoCamModel, status: Option<String>) -> ModelSummary {
+        let mut genes = BTreeSet::new();
+
+        for (_, node) in model.node_iterator() {
+            if let GoCamNodeType::Activity(GoCamActivity { ref enabler, .. }) = node.node_type {
+                match enabler {
+                    GoCamEnabledBy::Gene(gene) => {
+                        genes.insert(gene.id().to_owned());
+                    },
+                    GoCamEnabledBy::Complex(complex) => {
+                        genes.extend(complex.has_part_genes.iter().cloned());
+                    },
+                    _ => (),
+                }
+            }
+        }
+
+        let stats = get_stats(model);
+
+        ModelSummary {
+            id: model.id().to_owned(),
+            title: model.title().to_owned(),
+            taxon: model.taxon().to_owned(),
+            status,
+            genes,
+            stats: ModelStats {
+                total_genes: stats.total_genes,
+                total_complexes: stats.total_complexes,
+                connected_genes: stats.connected_genes,
+                max_connected_activities: stats.max_connected_activities,
+                total_connected_activities: stats.total_connected_activities,
+                number_of_holes: stats.number_of_holes,
+            },
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct ValueChange {
+    pub model_id: String,
+    pub old: String,
+    pub new: String,
+}
+
+// a statistic of a model or taxon that changed; a model that was added
+// or removed has stats of zero on the other side
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct StatChange {
+    pub stat_name: &'static str,
+    pub old: usize,
+    pub new: usize,
+}
+
+/// The changes between two snapshots of the models
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub(crate) struct CorpusDiff {
+    pub added_models: Vec<ModelSummary>,
+    pub removed_models: Vec<ModelSummary>,
+    pub retitled_models: Vec<ValueChange>,
+    pub status_changes: Vec<ValueChange>,
+    // (taxon, gene) pairs that are newly in or no longer in any model
+    pub added_genes: BTreeSet<(String, String)>,
+    pub removed_genes: BTreeSet<(String, String)>,
+    // keyed by (model ID, taxon)
+    pub model_stat_changes: BTreeMap<(String, String), Vec<StatChange>>,
+    pub taxon_stat_changes: BTreeMap<String, Vec<StatChange>>,
+}
+
+fn stat_changes(old: &ModelStats, new: &ModelStats) -> Vec<StatChange> {
+    STAT_NAMES.iter()
+        .zip(old.values().into_iter().zip(new.values()))
+        .filter(|(_, (old, new))| old != new)
+        .map(|(stat_name, (old, new))| StatChange { stat_name, old, new })
+        .collect()
+}
+
+fn taxon_genes(models: &BTreeMap<&str, &ModelSummary>) -> BTreeSet<(String, String)> {
+    models.values()
+        .flat_map(|model| {
+            model.genes.iter().map(|gene| (model.taxon.clone(), gene.clone()))
+        })
+        .collect()
+}
+
+fn taxon_stats(models: &BTreeMap<&str, &ModelSummary>) -> BTreeMap<String, ModelStats> {
+    let mut ret: BTreeMap<String, ModelStats> = BTreeMap::new();
+
+    for model in models.values() {
+        ret.entry(model.taxon.clone()).or_default().add(&model.stats);
+    }
+
+    ret
+}
+
+/// Compare two snapshots, matching models by ID
+pub(crate) fn diff_corpora(old_models: &[ModelSummary], new_models: &[ModelSummary])
+    -> CorpusDiff
+{
+    let old_by_id: BTreeMap<&str, &ModelSummary> =
+        old_models.iter().map(|model| (model.id.as_str(), model)).collect();
+    let new_by_id: BTreeMap<&str, &ModelSummary> =
+        new_models.iter().map(|model| (model.id.as_str(), model)).collect();
+
+    let mut diff = CorpusDiff::default();
+
+    let no_stats = ModelStats::default();
+
+    for (model_id, old_model) in &old_by_id {
+        let Some(new_model) = new_by_id.get(model_id)
+        else {
+            diff.removed_models.push((*old_model).clone());
+            diff.model_stat_changes.insert((model_id.to_string(), old_model.taxon.clone()),
+                                           stat_changes(&old_model.stats, &no_stats));
+            continue;
+        };
+
+        if old_model.title != new_model.title {
+            diff.retitled_models.push(ValueChange {
+                model_id: model_id.to_string(),
+                old: old_model.title.clone(),
+                new: new_model.title.clone(),
+            });
+        }
+
+        if old_model.status != new_model.status {
+            diff.status_changes.push(ValueChange {
+                model_id: model_id.to_string(),
+                old: old_model.status.clone().unwrap_or_default(),
+                new: new_model.status.clone().unwrap_or_default(),
+            });
+        }
+
+        let changes = stat_changes(&old_model.stats, &new_model.stats);
+        if !changes.is_empty() {
+            diff.model_stat_changes.insert((model_id.to_string(), new_model.taxon.clone()),
+                                           changes);
+        }
+    }
+
+    for (model_id, new_model) in &new_by_id {
+        if !old_by_id.contains_key(model_id) {
+            diff.added_models.push((*new_model).clone());
+            diff.model_stat_changes.insert((model_id.to_string(), new_model.taxon.clone()),
+                                           stat_changes(&no_stats, &new_model.stats));
+        }
+    }
+
+    let old_genes = taxon_genes(&old_by_id);
+    let new_genes = taxon_genes(&new_by_id);
+
+    diff.added_genes = new_genes.difference(&old_genes).cloned().collect();
+    diff.removed_genes = old_genes.difference(&new_genes).cloned().collect();
+
+    let old_taxon_stats = taxon_stats(&old_by_id);
+    let new_taxon_stats = taxon_stats(&new_by_id);
+
+    let taxa: BTreeSet<&String> = old_taxon_stats.keys().chain(new_taxon_stats.keys()).collect();
+
+    for taxon in taxa {
+        let old_stats = old_taxon_stats.get(taxon).unwrap_or(&no_stats);
+        let new_stats = new_taxon_stats.get(taxon).unwrap_or(&no_stats);
+        let changes = stat_changes(old_stats, new_stats);
+        if !changes.is_empty() {
+            diff.taxon_stat_changes.insert(taxon.clone(), changes);
+        }
+    }
+
+    diff
+}
+
+/// Write the changes as Markdown for release notes
+pub(crate) fn write_markdown(writer: &mut dyn Write, diff: &CorpusDiff) -> Result<(), Error> {
+    writeln!(writer, "# GO-CAM model changes")?;
+
+    let model_line = |model: &ModelSummary| {
+        format!("- {} {} ({})", model.id, model.title, model.taxon)
+    };
+
+    writeln!(writer, "\n## Added models ({})\n", diff.added_models.len())?;
+    for model in &diff.added_models {
+        writeln!(writer, "{}", model_line(model))?;
+    }
+
+    writeln!(writer, "\n## Removed models ({})\n", diff.removed_models.len())?;
+    for model in &diff.removed_models {
+        writeln!(writer, "{}", model_line(model))?;
+    }
+
+    writeln!(writer, "\n## Retitled models ({})\n", diff.retitled_models.len())?;
+    for change in &diff.retitled_models {
+        writeln!(writer, "- {}: \"{}\" → \"{}\"", change.model_id, change.old, change.new)?;
+    }
+
+    writeln!(writer, "\n## Status changes ({})\n", diff.status_changes.len())?;
+    for change in &diff.status_changes {
+        writeln!(writer, "- {}: {} → {}", change.model_id, change.old, change.new)?;
+    }
+
+    for (heading, genes) in [("Genes added to GO-CAM", &diff.added_genes),
+                             ("Genes no longer in GO-CAM", &diff.removed_genes)] {
+        writeln!(writer, "\n## {} ({})\n", heading, genes.len())?;
+        for (taxon, genes_of_taxon) in &genes.iter().chunk_by(|(taxon, _)| taxon) {
+            writeln!(writer, "- {}: {}", taxon, genes_of_taxon.map(|(_, gene)| gene).join(", "))?;
+        }
+    }
+
+    writeln!(writer, "\n## Statistics by taxon\n")?;
+    writeln!(writer, "| taxon | statistic | old | new |")?;
+    writeln!(writer, "|---|---|---|---|")?;
+    for (taxon, changes) in &diff.taxon_stat_changes {
+        for change in changes {
+            writeln!(writer, "| {} | {} | {} | {} |", taxon, change.stat_name, change.old,
+                     change.new)?;
+        }
+    }
+
+    writeln!(writer, "\n## Statistics by model\n")?;
+    writeln!(writer, "| model | taxon | statistic | old | new |")?;
+    writeln!(writer, "|---|---|---|---|---|")?;
+    for ((model_id, taxon), changes) in &diff.model_stat_changes {
+        for change in changes {
+            writeln!(writer, "| {} | {} | {} | {} | {} |", model_id, taxon, change.stat_name,
+                     change.old, change.new)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Write one TSV row per change
+pub(crate) fn write_tsv(writer: &mut dyn Write, diff: &CorpusDiff) -> Result<(), Error> {
+    writeln!(writer, "change\tmodel_id\ttaxon\tfield\told_value\tnew_value")?;
+
+    for model in &diff.added_models {
+        writeln!(writer, "model_added\t{}\t{}\ttitle\t\t{}", model.id, model.taxon, model.title)?;
+    }
+    for model in &diff.removed_models {
+        writeln!(writer, "model_removed\t{}\t{}\ttitle\t{}\t", model.id, model.taxon,
+                 model.title)?;
+    }
+    for change in &diff.retitled_models {
+        writeln!(writer, "model_retitled\t{}\t\ttitle\t{}\t{}", change.model_id, change.old,
+                 change.new)?;
+    }
+    for change in &diff.status_changes {
+        writeln!(writer, "status_changed\t{}\t\tstatus\t{}\t{}", change.model_id, change.old,
+                 change.new)?;
+    }
+    for (taxon, gene) in &diff.added_genes {
+        writeln!(writer, "gene_added\t\t{}\tgene\t\t{}", taxon, gene)?;
+    }
+    for (taxon, gene) in &diff.removed_genes {
+        writeln!(writer, "gene_removed\t\t{}\tgene\t{}\t", taxon, gene)?;
+    }
+    for (taxon, changes) in &diff.taxon_stat_changes {
+        for change in changes {
+            writeln!(writer, "taxon_stat\t\t{}\t{}\t{}\t{}", taxon, change.stat_name,
+                     change.old, change.new)?;
+        }
+    }
+    for ((model_id, taxon), changes) in &diff.model_stat_changes {
+        for change in changes {
+            writeln!(writer, "model_stat\t{}\t{}\t{}\t{}\t{}", model_id, taxon,
+                     change.stat_name, change.old, change.new)?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use pombase_gocam::{gocam_py::gocam_py_parse, parse_gocam_py_model};
+
+    use super::{ModelSummary, diff_corpora, write_markdown, write_tsv};
+
+    const MODEL_ID: &str = "gomodel:67ae98b500000055";
+
+    fn summary(yaml: &str) -> ModelSummary {
+        let model = parse_gocam_py_model(&mut yaml.as_bytes()).unwrap();
+        let py_model = gocam_py_parse(&mut yaml.as_bytes()).unwrap();
+        ModelSummary::from_model(&model, py_model.status.clone())
+    }
+
+    #[test]
+    fn corpus_diff_test() {
+        let yaml = fs::read_to_string("tests/data/67ae98b500000055.yaml").unwrap();
+
+        let changed_yaml = yaml
+            .replace("title: iron import", "title: Iron import")
+            .replace("status: production", "status: development")
+            .replace("PomBase:SPAC1F7.07c", "PomBase:SPAC1F7.99");
+
+        let removed_yaml = yaml.replace(MODEL_ID, "gomodel:1111111100000000");
+        let added_yaml = changed_yaml.replace(MODEL_ID, "gomodel:2222222200000000");
+
+        let old_models = vec![summary(&yaml), summary(&removed_yaml)];
+        let new_models = vec![summary(&changed_yaml), summary(&added_yaml)];
+
+        assert!(old_models[0].genes.contains("PomBase:SPAC1F7.07c"));
+
+        let diff = diff_corpora(&old_models, &new_models);
+
+        assert_eq!(diff.added_models.len(), 1);
+        assert_eq!(diff.added_models[0].id, "gomodel:2222222200000000");
+        assert_eq!(diff.removed_models.len(), 1);
+        assert_eq!(diff.removed_models[0].id, "gomodel:1111111100000000");
+
+        assert_eq!(diff.retitled_models.len(), 1);
+        assert_eq!(diff.retitled_models[0].model_id, MODEL_ID);
+        assert!(diff.retitled_models[0].new.starts_with("Iron import"));
+
+        assert_eq!(diff.status_changes.len(), 1);
+        assert_eq!(diff.status_changes[0].old, "production");
+        assert_eq!(diff.status_changes[0].new, "development");
+
+        let gene_pairs = |genes: &std::collections::BTreeSet<(String, String)>| {
+            genes.iter().map(|(_, gene)| gene.clone()).collect::<Vec<_>>()
+        };
+        assert_eq!(gene_pairs(&diff.added_genes), vec!["PomBase:SPAC1F7.99"]);
+        assert_eq!(gene_pairs(&diff.removed_genes), vec!["PomBase:SPAC1F7.07c"]);
+
+        // the added and removed models have the same stats
+        assert!(diff.taxon_stat_changes.is_empty());
+
+        let mut markdown = vec![];
+        write_markdown(&mut markdown, &diff).unwrap();
+        let markdown = String::from_utf8(markdown).unwrap();
+        assert!(markdown.contains("\n## Added models (1)\n\n- gomodel:2222222200000000 "));
+        assert!(markdown.contains("- gomodel:67ae98b500000055: production → development\n"));
+
+        let mut tsv = vec![];
+        write_tsv(&mut tsv, &diff).unwrap();
+        let tsv = String::from_utf8(tsv).unwrap();
+        assert!(tsv.lines().all(|line| line.split('\t').count() == 6));
+        assert!(tsv.contains("\ngene_removed\t\tNCBITaxon:4896\tgene\tPomBase:SPAC1F7.07c\t\n"));
+    }
+}
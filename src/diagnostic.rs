@@ -0,0 +1,217 @@
+use std::{collections::HashMap, fmt::{self, Display}, hash::Hash};
+
+use serde_derive::Serialize;
+
+pub type TermId = String;
+pub type TermName = String;
+pub type RelId = String;
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum DiagnosticKind {
+    DisallowedRelation,
+    DisallowedObjectRelation,
+    DisallowedPairRelation,
+    MissingTermParents,
+    ObsoleteTerm,
+    UnknownTerm,
+    WrongAspect,
+    TaxonConstraintViolation,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct DiagnosticTerm {
+    pub id: TermId,
+    pub name: TermName,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct DiagnosticRelation {
+    pub id: RelId,
+    pub name: String,
+}
+
+/// A problem found in a model by one of the term or relation checks.
+/// The fields that don't apply to a particular kind of problem are None
+/// or empty.
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub kind: DiagnosticKind,
+    pub severity: Severity,
+    pub model_id: String,
+    pub individual_ids: Vec<String>,
+    pub subject_term: Option<DiagnosticTerm>,
+    pub object_term: Option<DiagnosticTerm>,
+    pub relation: Option<DiagnosticRelation>,
+    // the term in the config (or the ontology) that caused the problem,
+    // or "subject_term -> object_term" for pair rules
+    pub rule_source: Option<String>,
+    // the part of the model containing subject_term, eg. "occurs_in"
+    pub field: Option<String>,
+    pub replaced_by: Option<TermId>,
+    pub consider: Vec<TermId>,
+    // for WrongAspect, the namespace that the field needs, eg.
+    // "biological_process"
+    pub expected_aspect: Option<String>,
+    pub actual_aspect: Option<String>,
+    // the taxon of the model, for TaxonConstraintViolation
+    pub taxon: Option<String>,
+}
+
+impl Diagnostic {
+    pub fn new(kind: DiagnosticKind, severity: Severity, model_id: &str) -> Diagnostic {
+        Diagnostic {
+            kind,
+            severity,
+            model_id: model_id.to_owned(),
+            individual_ids: vec![],
+            subject_term: None,
+            object_term: None,
+            relation: None,
+            rule_source: None,
+            field: None,
+            replaced_by: None,
+            consider: vec![],
+            expected_aspect: None,
+            actual_aspect: None,
+            taxon: None,
+        }
+    }
+
+    pub fn subject_term_id(&self) -> &str {
+        self.subject_term.as_ref().map(|term| term.id.as_str()).unwrap_or("")
+    }
+
+    pub fn subject_term_name(&self) -> &str {
+        self.subject_term.as_ref().map(|term| term.name.as_str()).unwrap_or("")
+    }
+
+    pub fn object_term_id(&self) -> &str {
+        self.object_term.as_ref().map(|term| term.id.as_str()).unwrap_or("")
+    }
+
+    fn relation_id_and_name(&self) -> (&str, &str) {
+        if let Some(ref relation) = self.relation {
+            (relation.id.as_str(), relation.name.as_str())
+        } else {
+            ("", "")
+        }
+    }
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.kind {
+            DiagnosticKind::DisallowedRelation => {
+                let (rel_id, rel_name) = self.relation_id_and_name();
+                write!(f, "relation {} ({}) not allowed for {} because of config for {}",
+                       rel_name, rel_id, self.subject_term_id(),
+                       self.rule_source.as_deref().unwrap_or(""))
+            },
+            DiagnosticKind::DisallowedObjectRelation => {
+                let (rel_id, rel_name) = self.relation_id_and_name();
+                write!(f, "relation {} ({}) not allowed to {} because of object config for {}",
+                       rel_name, rel_id, self.object_term_id(),
+                       self.rule_source.as_deref().unwrap_or(""))
+            },
+            DiagnosticKind::DisallowedPairRelation => {
+                let (rel_id, rel_name) = self.relation_id_and_name();
+                write!(f, "relation {} ({}) not allowed from {} to {} because of pair config for {}",
+                       rel_name, rel_id, self.subject_term_id(), self.object_term_id(),
+                       self.rule_source.as_deref().unwrap_or(""))
+            },
+            DiagnosticKind::MissingTermParents => {
+                write!(f, "can't find parents of {}", self.subject_term_id())
+            },
+            DiagnosticKind::ObsoleteTerm => {
+                write!(f, "{} {} {}", self.field.as_deref().unwrap_or(""),
+                       self.subject_term_id(), self.subject_term_name())?;
+                if let Some(ref replaced_by) = self.replaced_by {
+                    write!(f, " (replaced by {})", replaced_by)?;
+                } else if !self.consider.is_empty() {
+                    write!(f, " (consider {})", self.consider.join(", "))?;
+                }
+                Ok(())
+            },
+            DiagnosticKind::UnknownTerm => {
+                write!(f, "{} {} {}: unknown term", self.field.as_deref().unwrap_or(""),
+                       self.subject_term_id(), self.subject_term_name())
+            },
+            DiagnosticKind::WrongAspect => {
+                write!(f, "{} {} {} is a {} term, expected {}",
+                       self.field.as_deref().unwrap_or(""),
+                       self.subject_term_id(), self.subject_term_name(),
+                       self.actual_aspect.as_deref().unwrap_or(""),
+                       self.expected_aspect.as_deref().unwrap_or(""))
+            },
+            DiagnosticKind::TaxonConstraintViolation => {
+                let (_, rel_name) = self.relation_id_and_name();
+                write!(f, "{} {} {} not valid for {}: {} {}",
+                       self.field.as_deref().unwrap_or(""),
+                       self.subject_term_id(), self.subject_term_name(),
+                       self.taxon.as_deref().unwrap_or(""), rel_name, self.object_term_id())?;
+                if let Some(ref rule_source) = self.rule_source &&
+                    rule_source != self.subject_term_id()
+                {
+                    write!(f, " (from {})", rule_source)?;
+                }
+                Ok(())
+            },
+        }
+    }
+}
+
+/// Makes one Diagnostic per key, merging the individual IDs of the nodes
+/// that have the problem
+pub(crate) struct DiagnosticCollector<K> {
+    seen: HashMap<K, usize>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl<K> Default for DiagnosticCollector<K> {
+    fn default() -> Self {
+        DiagnosticCollector {
+            seen: HashMap::new(),
+            diagnostics: vec![],
+        }
+    }
+}
+
+impl<K: Eq + Hash> DiagnosticCollector<K> {
+    /// Add a Diagnostic made by make_diagnostic, unless there is already
+    /// one for the key
+    pub fn add(&mut self, key: K, individual_id: Option<&str>,
+               make_diagnostic: impl FnOnce() -> Diagnostic) {
+        let idx = *self.seen.entry(key).or_insert_with(|| {
+            self.diagnostics.push(make_diagnostic());
+            self.diagnostics.len() - 1
+        });
+
+        if let Some(individual_id) = individual_id {
+            let individual_ids = &mut self.diagnostics[idx].individual_ids;
+            if !individual_ids.iter().any(|id| id == individual_id) {
+                individual_ids.push(individual_id.to_owned());
+            }
+        }
+    }
+
+    pub fn into_diagnostics(self) -> Vec<Diagnostic> {
+        self.diagnostics
+    }
+}
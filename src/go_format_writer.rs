@@ -1,180 +1,1523 @@
-use std::{collections::HashMap, io::{BufWriter, Error, Write}};
+use std::{collections::{BTreeMap, HashMap, HashSet}, fmt::Display,
+          io::{BufRead, BufWriter, Error, Write}, str::FromStr};
 
 use chrono::{DateTime, Local};
+use clap::ValueEnum;
 use itertools::Itertools;
-use pombase_gocam::gocam_py::{BiologicalProcessAssociation, EvidenceItem, GoCamPyModel};
+use pombase_gocam::{REL_NAMES,
+                    gocam_py::{Activity, BiologicalProcessAssociation, EvidenceItem,
+                               GoCamPyModel}};
+
+use crate::ontology_info::OntologyInfo;
 
 const ND_ECO_TERM_ID: &str = "ECO:0000307";
 
-#[allow(clippy::too_many_arguments)]
-fn make_annotation_line(db_name: &str, gene_uniquename: &str,
-                        term_id: &str, aspect: &str,
-                        reference: &str, ev_code: &str, with_from: &str,
-                        taxon_id: &str, date: &str)
-    -> String
+const PROTEIN_CONTAINING_COMPLEX_TERM_ID: &str = "GO:0032991";
+const PROTEIN_TYPE_ID: &str = "PR:000000001";
+
+const COMPLEX_PORTAL_DB: &str = "ComplexPortal";
+
+/// The type of the DB object of an annotation (GAF column 12)
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub(crate) enum ObjectType {
+    Protein,
+    Complex,
+}
+
+impl ObjectType {
+    fn gaf_name(&self) -> &'static str {
+        match self {
+            ObjectType::Protein => "protein",
+            ObjectType::Complex => "protein-containing complex",
+        }
+    }
+
+    fn type_id(&self) -> &'static str {
+        match self {
+            ObjectType::Protein => PROTEIN_TYPE_ID,
+            ObjectType::Complex => PROTEIN_CONTAINING_COMPLEX_TERM_ID,
+        }
+    }
+}
+
+/// How activities enabled by complexes are exported
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub(crate) enum ComplexAnnotationMode {
+    /// A contributes_to annotation (for MF) for each member gene
+    #[default]
+    Members,
+    /// Annotate the complex itself, if it has a Complex Portal ID
+    Complex,
+}
+
+/// The relation between a gene product and a GO term
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub(crate) enum AnnotationRelation {
+    Enables,
+    ContributesTo,
+    InvolvedIn,
+    IsActiveIn,
+    PartOf,
+}
+
+impl AnnotationRelation {
+    pub fn id(&self) -> &'static str {
+        match self {
+            AnnotationRelation::Enables => "RO:0002327",
+            AnnotationRelation::ContributesTo => "RO:0002326",
+            AnnotationRelation::InvolvedIn => "RO:0002331",
+            AnnotationRelation::IsActiveIn => "RO:0002432",
+            AnnotationRelation::PartOf => "BFO:0000050",
+        }
+    }
+
+    // the GAF 2.2 qualifier
+    pub fn label(&self) -> &'static str {
+        match self {
+            AnnotationRelation::Enables => "enables",
+            AnnotationRelation::ContributesTo => "contributes_to",
+            AnnotationRelation::InvolvedIn => "involved_in",
+            AnnotationRelation::IsActiveIn => "is_active_in",
+            AnnotationRelation::PartOf => "part_of",
+        }
+    }
+}
+
+/// The parts of an activity that can become annotation extensions
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub(crate) enum ExtensionSlot {
+    OccursIn,
+    HasInput,
+    HasOutput,
+    PartOf,
+    HappensDuring,
+    // the MF of the activity, for BP annotations
+    HasPart,
+    // the genes enabling downstream activities
+    Causal,
+}
+
+impl FromStr for ExtensionSlot {
+    type Err = String;
+
+    fn from_str(slot_name: &str) -> Result<Self, Self::Err> {
+        match slot_name {
+            "occurs_in" => Ok(ExtensionSlot::OccursIn),
+            "has_input" => Ok(ExtensionSlot::HasInput),
+            "has_output" => Ok(ExtensionSlot::HasOutput),
+            "part_of" => Ok(ExtensionSlot::PartOf),
+            "happens_during" => Ok(ExtensionSlot::HappensDuring),
+            "has_part" => Ok(ExtensionSlot::HasPart),
+            "causal" => Ok(ExtensionSlot::Causal),
+            _ => Err(format!("unknown annotation extension slot: {}", slot_name)),
+        }
+    }
+}
+
+/// Which extensions to add to the annotations of each aspect.  The
+/// default is no extensions.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct ExtensionConfig {
+    // keyed by aspect: "F", "P" or "C"
+    pub slots: HashMap<String, Vec<ExtensionSlot>>,
+    // if set, only causal associations with these relations are used
+    pub causal_relations: Option<HashSet<String>>,
+}
+
+/// One relation(target) part of GAF column 16 or GPAD column 11
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub(crate) struct AnnotationExtension {
+    pub relation_id: String,
+    pub relation_name: String,
+    pub target: String,
+}
+
+impl AnnotationExtension {
+    fn new(relation_id: &str, relation_name: &str, target: &str) -> AnnotationExtension {
+        AnnotationExtension {
+            relation_id: relation_id.to_owned(),
+            relation_name: relation_name.to_owned(),
+            target: target.to_owned(),
+        }
+    }
+}
+
+fn gaf_extensions(extensions: &[AnnotationExtension]) -> String {
+    extensions.iter()
+        .map(|extension| format!("{}({})", extension.relation_name, extension.target))
+        .join(",")
+}
+
+fn gpad_extensions(extensions: &[AnnotationExtension]) -> String {
+    extensions.iter()
+        .map(|extension| format!("{}({})", extension.relation_id, extension.target))
+        .join(",")
+}
+
+/// The symbol, name and synonyms of a gene, for the GAF and GPI columns
+/// that aren't in the model
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub(crate) struct GeneMetadata {
+    pub symbol: Option<String>,
+    pub name: Option<String>,
+    pub synonyms: Vec<String>,
+}
+
+pub(crate) type GeneMetadataMap = HashMap<String, GeneMetadata>;
+
+fn non_empty(value: Option<&str>) -> Option<String> {
+    value.map(str::trim)
+        .filter(|value| !value.is_empty())
+        .map(str::to_owned)
+}
+
+/// Read a TSV file of: systematic ID, symbol, name and synonyms (separated
+/// by "|" or ",").  Lines starting with "#" are ignored.
+pub(crate) fn parse_gene_metadata(buf_reader: &mut dyn BufRead)
+    -> Result<GeneMetadataMap, Error>
+{
+    let mut ret = HashMap::new();
+
+    for line_result in buf_reader.lines() {
+        let line = line_result?;
+
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let mut bits = line.split('\t');
+
+        let Some(uniquename) = non_empty(bits.next())
+        else {
+            continue;
+        };
+
+        let symbol = non_empty(bits.next());
+        let name = non_empty(bits.next());
+        let synonyms = bits.next().unwrap_or_default()
+            .split(['|', ','])
+            .filter_map(|synonym| non_empty(Some(synonym)))
+            .collect();
+
+        ret.insert(uniquename, GeneMetadata {
+            symbol,
+            name,
+            synonyms,
+        });
+    }
+
+    Ok(ret)
+}
+
+/// PR ID of a modified protein to the systematic ID of its gene
+pub(crate) type ProteinFormMap = HashMap<String, String>;
+
+/// Read a TSV file of PR IDs and gene systematic IDs.  Lines starting
+/// with "#" are ignored.
+pub(crate) fn parse_protein_forms(reader: &mut dyn BufRead) -> Result<ProteinFormMap, Error> {
+    let mut ret = HashMap::new();
+
+    for line_result in reader.lines() {
+        let line = line_result?;
+
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let mut bits = line.split('\t').map(str::trim);
+
+        if let (Some(pr_id), Some(gene_uniquename)) = (bits.next(), bits.next()) &&
+            !pr_id.is_empty() && !gene_uniquename.is_empty()
+        {
+            ret.insert(pr_id.to_owned(), gene_uniquename.to_owned());
+        }
+    }
+
+    Ok(ret)
+}
+
+/// One gene product to GO term annotation derived from a GO-CAM model,
+/// independent of the output format
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct GoAnnotation {
+    pub model_id: String,
+    // the DB of gene_uniquename: the db_name, or "ComplexPortal" for
+    // complex level annotations
+    pub object_db: String,
+    pub object_type: ObjectType,
+    // the gene systematic ID, or the complex ID without the DB prefix
+    pub gene_uniquename: String,
+    // the label of the gene in the model, eg. "frp1 Spom"
+    pub gene_label: Option<String>,
+    // the PR ID if the activity is enabled by a modified protein
+    pub gene_product_form: Option<String>,
+    pub term_id: String,
+    // "F", "P" or "C"
+    pub aspect: &'static str,
+    pub relation: AnnotationRelation,
+    pub eco_code: String,
+    pub go_ev_code: String,
+    pub reference: String,
+    pub with_from: String,
+    // eg. "NCBITaxon:4896"
+    pub taxon_id: String,
+    // YYYY-MM-DD
+    pub date: String,
+    pub extensions: Vec<AnnotationExtension>,
+}
+
+/// The settings used when turning GO-CAM models into annotations
+pub(crate) struct AnnotationConfig<'a> {
+    pub db_name: &'a str,
+    // ECO ID to GO evidence code
+    pub go_ev_code_map: &'a HashMap<String, String>,
+    // if available, used to annotate to complexes with part_of rather
+    // than is_active_in
+    pub ontology_info: Option<&'a OntologyInfo>,
+    pub extension_config: &'a ExtensionConfig,
+    pub export_policy: &'a ExportPolicy,
+    // record which rule accepted or rejected each evidence item
+    pub explain: bool,
+    pub complex_mode: ComplexAnnotationMode,
+    // needed to export activities enabled by modified proteins
+    pub protein_forms: Option<&'a ProteinFormMap>,
+}
+
+// the 1-based GAF 2.2 columns that must have a value
+const MANDATORY_GAF_COLUMNS: [usize; 12] = [1, 2, 3, 4, 5, 6, 7, 9, 12, 13, 14, 15];
+
+fn gene_symbol<'a>(annotation: &'a GoAnnotation, gene_metadata: Option<&'a GeneMetadata>)
+    -> &'a str
 {
-    format!("{}\t{}\t\t\t{}\t{}\t{}\t{}\t{}\t\t\tprotein\t{}\t{}\t{}\t\t",
-            db_name, gene_uniquename, term_id, reference, ev_code, with_from, aspect, taxon_id, date, db_name)
+    if annotation.object_type == ObjectType::Complex {
+        return annotation.gene_label.as_deref().unwrap_or(&annotation.gene_uniquename);
+    }
+
+    gene_metadata.and_then(|metadata| metadata.symbol.as_deref())
+        .or(annotation.gene_label.as_deref().map(symbol_from_label))
+        .unwrap_or(&annotation.gene_uniquename)
+}
+
+fn make_annotation_columns(db_name: &str, annotation: &GoAnnotation,
+                           gene_metadata: Option<&GeneMetadata>)
+    -> Vec<String>
+{
+    let (name, synonyms) =
+        if let Some(gene_metadata) = gene_metadata {
+            (gene_metadata.name.clone().unwrap_or_default(), gene_metadata.synonyms.join("|"))
+        } else {
+            (String::new(), String::new())
+        };
+
+    vec![
+        annotation.object_db.clone(),
+        annotation.gene_uniquename.clone(),
+        gene_symbol(annotation, gene_metadata).to_owned(),
+        annotation.relation.label().to_owned(),
+        annotation.term_id.clone(),
+        annotation.reference.clone(),
+        annotation.go_ev_code.clone(),
+        annotation.with_from.clone(),
+        annotation.aspect.to_owned(),
+        name,
+        synonyms,
+        annotation.object_type.gaf_name().to_owned(),
+        annotation.taxon_id.replace("NCBITaxon:", "taxon:"),
+        annotation.date.replace('-', ""),
+        db_name.to_owned(),
+        gaf_extensions(&annotation.extensions),
+        annotation.gene_product_form.clone().unwrap_or_default(),
+    ]
+}
+
+// return the numbers of the mandatory columns that are empty
+fn empty_mandatory_columns(columns: &[String]) -> Vec<usize> {
+    MANDATORY_GAF_COLUMNS.iter()
+        .filter(|column_number| {
+            columns.get(**column_number - 1).is_none_or(|value| value.is_empty())
+        })
+        .cloned()
+        .collect()
+}
+
+#[derive(Clone)]
+struct EvidenceDetails {
+    eco_code: String,
+    go_ev_code: String,
+    reference: String,
+    with_from: String,
+}
+
+/// Which evidence items are exported, by ECO code and reference prefix.
+/// Deny lists are checked first.  If both allow lists are empty every
+/// item that isn't denied is exported.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct EvidenceRules {
+    pub allow_eco_codes: Vec<String>,
+    pub deny_eco_codes: Vec<String>,
+    pub allow_reference_prefixes: Vec<String>,
+    pub deny_reference_prefixes: Vec<String>,
+}
+
+/// The rule that accepted or rejected an evidence item
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum ExportRule {
+    NoReference,
+    UnknownEvidenceCode,
+    DenyEcoCode(String),
+    DenyReferencePrefix(String),
+    AllowEcoCode(String),
+    AllowReferencePrefix(String),
+    // there are no allow lists
+    AllowAll,
+    // not matched by either allow list
+    NotAllowed,
+}
+
+impl ExportRule {
+    pub fn is_accepted(&self) -> bool {
+        self.skip_reason().is_none()
+    }
+
+    fn skip_reason(&self) -> Option<SkipReason> {
+        match self {
+            ExportRule::NoReference => Some(SkipReason::NoReference),
+            ExportRule::UnknownEvidenceCode => Some(SkipReason::UnknownEvidenceCode),
+            ExportRule::DenyEcoCode(_) | ExportRule::DenyReferencePrefix(_) =>
+                Some(SkipReason::Denied),
+            ExportRule::NotAllowed => Some(SkipReason::NotAllowed),
+            ExportRule::AllowEcoCode(_) | ExportRule::AllowReferencePrefix(_) |
+            ExportRule::AllowAll => None,
+        }
+    }
+}
+
+impl Display for ExportRule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExportRule::NoReference => write!(f, "no reference"),
+            ExportRule::UnknownEvidenceCode => write!(f, "unknown evidence code"),
+            ExportRule::DenyEcoCode(code) => write!(f, "deny_eco_codes: {}", code),
+            ExportRule::DenyReferencePrefix(prefix) =>
+                write!(f, "deny_reference_prefixes: {}", prefix),
+            ExportRule::AllowEcoCode(code) => write!(f, "allow_eco_codes: {}", code),
+            ExportRule::AllowReferencePrefix(prefix) =>
+                write!(f, "allow_reference_prefixes: {}", prefix),
+            ExportRule::AllowAll => write!(f, "no allow lists"),
+            ExportRule::NotAllowed =>
+                write!(f, "not in allow_eco_codes or allow_reference_prefixes"),
+        }
+    }
+}
+
+impl EvidenceRules {
+    fn check(&self, go_evidence_code_map: &HashMap<String, String>,
+             evidence_item: &EvidenceItem)
+        -> ExportRule
+    {
+        let Some(reference) = &evidence_item.reference
+        else {
+            return ExportRule::NoReference;
+        };
+
+        let ev_code = &evidence_item.term;
+
+        if self.deny_eco_codes.contains(ev_code) {
+            return ExportRule::DenyEcoCode(ev_code.to_owned());
+        }
+
+        if !go_evidence_code_map.contains_key(ev_code) {
+            return ExportRule::UnknownEvidenceCode;
+        }
+
+        if let Some(prefix) = self.deny_reference_prefixes.iter()
+            .find(|prefix| reference.starts_with(prefix.as_str()))
+        {
+            return ExportRule::DenyReferencePrefix(prefix.to_owned());
+        }
+
+        if self.allow_eco_codes.contains(ev_code) {
+            return ExportRule::AllowEcoCode(ev_code.to_owned());
+        }
+
+        if let Some(prefix) = self.allow_reference_prefixes.iter()
+            .find(|prefix| reference.starts_with(prefix.as_str()))
+        {
+            return ExportRule::AllowReferencePrefix(prefix.to_owned());
+        }
+
+        if self.allow_eco_codes.is_empty() && self.allow_reference_prefixes.is_empty() {
+            ExportRule::AllowAll
+        } else {
+            ExportRule::NotAllowed
+        }
+    }
+}
+
+/// The EvidenceRules for each aspect ("F", "P" and "C")
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct ExportPolicy {
+    pub default_rules: EvidenceRules,
+    pub aspect_rules: HashMap<String, EvidenceRules>,
+}
+
+impl ExportPolicy {
+    fn rules(&self, aspect: &str) -> &EvidenceRules {
+        self.aspect_rules.get(aspect).unwrap_or(&self.default_rules)
+    }
+}
+
+// used if the config file has no evidence_export section: skip ND and
+// export only GO_REF references and TAS
+impl Default for ExportPolicy {
+    fn default() -> Self {
+        ExportPolicy {
+            default_rules: EvidenceRules {
+                allow_eco_codes: vec!["ECO:0000304".to_owned()],
+                deny_eco_codes: vec![ND_ECO_TERM_ID.to_owned()],
+                allow_reference_prefixes: vec!["GO_REF:".to_owned()],
+                deny_reference_prefixes: vec![],
+            },
+            aspect_rules: HashMap::new(),
+        }
+    }
+}
+
+/// Why an evidence item didn't become an annotation
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub(crate) enum SkipReason {
+    NoReference,
+    UnknownEvidenceCode,
+    Denied,
+    NotAllowed,
+    // the same term, reference, evidence code and with/from as an
+    // annotation already made from the model
+    Duplicate,
+}
+
+impl Display for SkipReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let description = match self {
+            SkipReason::NoReference => "no reference",
+            SkipReason::UnknownEvidenceCode => "unknown evidence code",
+            SkipReason::Denied => "denied",
+            SkipReason::NotAllowed => "not allowed",
+            SkipReason::Duplicate => "duplicate",
+        };
+        write!(f, "{}", description)
+    }
 }
 
 fn details_from_item(go_evidence_code_map: &HashMap<String, String>,
-                     evidence_item: &EvidenceItem)
-    -> Result<Option<(String, String, String)>, String>
+                     rules: &EvidenceRules, evidence_item: &EvidenceItem)
+    -> Result<(EvidenceDetails, ExportRule), ExportRule>
 {
+    let rule = rules.check(go_evidence_code_map, evidence_item);
+
+    if !rule.is_accepted() {
+        return Err(rule);
+    }
+
     let Some(reference) = &evidence_item.reference
     else {
-        return Ok(None);
+        return Err(ExportRule::NoReference);
     };
 
-    let ev_code = &evidence_item.term;
+    let Some(go_ev_code) = go_evidence_code_map.get(&evidence_item.term)
+    else {
+        return Err(ExportRule::UnknownEvidenceCode);
+    };
 
-    if ev_code == ND_ECO_TERM_ID {
-        return Ok(None);
-    }
+    let with_from = evidence_item.with_objects.iter().join(",");
 
-    let Some(go_ev_code) = go_evidence_code_map.get(ev_code)
-    else {
-        return Err(format!("unknown evidence code: {}", ev_code));
+    let details = EvidenceDetails {
+        eco_code: evidence_item.term.to_owned(),
+        go_ev_code: go_ev_code.to_owned(),
+        reference: reference.to_owned(),
+        with_from,
     };
 
-    if !reference.starts_with("GO_REF:") && ev_code != "ECO:0000304" {
-        return Ok(None);
+    Ok((details, rule))
+}
+
+/// Which rule accepted or rejected an evidence item, for --explain
+#[derive(Clone, Debug)]
+pub(crate) struct EvidenceExplanation {
+    // the DB objects annotated from the evidence, comma separated because
+    // the members of a complex share the evidence of the complex
+    pub enablers: String,
+    pub term_id: String,
+    pub aspect: &'static str,
+    pub eco_code: String,
+    pub reference: Option<String>,
+    pub rule: ExportRule,
+}
+
+impl Display for EvidenceExplanation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let decision = if self.rule.is_accepted() { "accepted" } else { "rejected" };
+        write!(f, "{}\t{}\t{}\t{}\t{}\t{}\t{}", self.enablers, self.term_id,
+               self.aspect, self.eco_code, self.reference.as_deref().unwrap_or_default(),
+               decision, self.rule)
     }
+}
 
-    let with_from = evidence_item.with_objects.iter().join(",");
+/// The annotations made from one model and the number of evidence
+/// items that were skipped for each reason
+#[derive(Debug, Default)]
+pub(crate) struct ModelAnnotations {
+    pub annotations: Vec<GoAnnotation>,
+    pub skipped_evidence: BTreeMap<SkipReason, usize>,
+    // only filled if AnnotationConfig.explain is set
+    pub explanations: Vec<EvidenceExplanation>,
+}
 
-    Ok(Some((go_ev_code.to_owned(), reference.to_owned(), with_from)))
+impl ModelAnnotations {
+    pub fn skipped_count(&self) -> usize {
+        self.skipped_evidence.values().sum()
+    }
 }
 
+type AnnotationKey = (String, String, Option<String>, String, AnnotationRelation, String,
+                      String, String, Vec<AnnotationExtension>);
 
-fn bp_association_lines(go_ev_code_map: &HashMap<String, String>,
-                        db_name: &str, taxon_id: &str,
-                        gene_uniquename: &str, date_modified: &str,
-                        bp_association: &BiologicalProcessAssociation)
-    -> Result<Vec<String>, String>
-{
-    let mut ret = vec![];
+fn annotation_key(annotation: &GoAnnotation) -> AnnotationKey {
+    (annotation.object_db.clone(), annotation.gene_uniquename.clone(),
+     annotation.gene_product_form.clone(), annotation.term_id.clone(), annotation.relation,
+     annotation.eco_code.clone(), annotation.reference.clone(), annotation.with_from.clone(),
+     annotation.extensions.clone())
+}
+
+// makes one annotation per distinct evidence item and DB object and
+// counts the evidence items that aren't exported
+struct AnnotationCollector<'a> {
+    go_ev_code_map: &'a HashMap<String, String>,
+    export_policy: &'a ExportPolicy,
+    explain: bool,
+    model_id: &'a str,
+    seen: HashSet<AnnotationKey>,
+    result: ModelAnnotations,
+}
+
+impl AnnotationCollector<'_> {
+    // Whether each evidence item is exported is decided, explained and
+    // counted once, then it is annotated to every context (eg. to each
+    // member of a complex).  An item is a duplicate if all of its
+    // annotations were already made.
+    fn add(&mut self, contexts: &[ActivityContext], term_id: &str, aspect: &'static str,
+           relation: impl Fn(&ActivityContext) -> AnnotationRelation,
+           evidence: &[EvidenceItem]) {
+        let rules = self.export_policy.rules(aspect);
+
+        let enablers = || contexts.iter().map(|context| context.gene_uniquename).join(",");
+
+        for evidence_item in evidence {
+            let result = details_from_item(self.go_ev_code_map, rules, evidence_item);
+
+            if self.explain {
+                let rule = match result {
+                    Ok((_, ref rule)) | Err(ref rule) => rule.clone(),
+                };
+                self.result.explanations.push(EvidenceExplanation {
+                    enablers: enablers(),
+                    term_id: term_id.to_owned(),
+                    aspect,
+                    eco_code: evidence_item.term.clone(),
+                    reference: evidence_item.reference.clone(),
+                    rule,
+                });
+            }
+
+            let details = match result {
+                Ok((details, _)) => details,
+                Err(rule) => {
+                    if rule == ExportRule::UnknownEvidenceCode {
+                        eprintln!("warning in {} for {} {}: unknown evidence code: {}",
+                                  self.model_id, term_id, enablers(), evidence_item.term);
+                    }
+                    if let Some(reason) = rule.skip_reason() {
+                        self.skip(reason);
+                    }
+                    continue;
+                }
+            };
+
+            let mut is_duplicate = true;
+
+            for context in contexts {
+                let annotation = context.make_annotation(term_id, aspect, relation(context),
+                                                         details.clone());
+
+                if self.seen.insert(annotation_key(&annotation)) {
+                    self.result.annotations.push(annotation);
+                    is_duplicate = false;
+                }
+            }
+
+            if is_duplicate {
+                self.skip(SkipReason::Duplicate);
+            }
+        }
+    }
+
+    fn skip(&mut self, reason: SkipReason) {
+        *self.result.skipped_evidence.entry(reason).or_default() += 1;
+    }
+}
 
-    if let Some(evidence_item) = bp_association.evidence.first()
+// the fields that are the same for every annotation of an activity and
+// DB object
+struct ActivityContext<'a> {
+    model_id: &'a str,
+    object_db: &'a str,
+    object_type: ObjectType,
+    gene_uniquename: &'a str,
+    gene_label: Option<&'a str>,
+    gene_product_form: Option<&'a str>,
+    mf_relation: AnnotationRelation,
+    taxon_id: &'a str,
+    date: &'a str,
+    // keyed by aspect
+    extensions: &'a HashMap<&'static str, Vec<AnnotationExtension>>,
+}
+
+impl ActivityContext<'_> {
+    fn make_annotation(&self, term_id: &str, aspect: &'static str,
+                       relation: AnnotationRelation, details: EvidenceDetails)
+        -> GoAnnotation
     {
+        GoAnnotation {
+            model_id: self.model_id.to_owned(),
+            object_db: self.object_db.to_owned(),
+            object_type: self.object_type,
+            gene_uniquename: self.gene_uniquename.to_owned(),
+            gene_label: self.gene_label.map(str::to_owned),
+            gene_product_form: self.gene_product_form.map(str::to_owned),
+            term_id: term_id.to_owned(),
+            aspect,
+            relation,
+            eco_code: details.eco_code,
+            go_ev_code: details.go_ev_code,
+            reference: details.reference,
+            with_from: details.with_from,
+            taxon_id: self.taxon_id.to_owned(),
+            date: self.date.to_owned(),
+            extensions: self.extensions.get(aspect).cloned().unwrap_or_default(),
+        }
+    }
+}
 
-        let bp_term_id = &bp_association.term;
+fn add_bp_association_annotations(collector: &mut AnnotationCollector,
+                                  contexts: &[ActivityContext],
+                                  bp_association: &BiologicalProcessAssociation) {
+    // a process without evidence has no annotations but the processes it
+    // is part of may have
+    collector.add(contexts, &bp_association.term, "P", |_| AnnotationRelation::InvolvedIn,
+                  &bp_association.evidence);
 
-        let details = details_from_item(go_ev_code_map, evidence_item)?;
+    if let Some(ref bp_association) = bp_association.part_of {
+        add_bp_association_annotations(collector, contexts, bp_association);
+    }
+}
+
+// without the closure complexes can't be told apart from other
+// components, so every CC annotation is is_active_in
+fn cc_relation(ontology_info: Option<&OntologyInfo>, cc_term_id: &str) -> AnnotationRelation {
+    if ontology_info.is_some_and(|ontology_info| {
+        ontology_info.is_a(cc_term_id, PROTEIN_CONTAINING_COMPLEX_TERM_ID)
+    }) {
+        AnnotationRelation::PartOf
+    } else {
+        AnnotationRelation::IsActiveIn
+    }
+}
 
-        if let Some((go_ev_code, reference, with_from)) = details {
-            let line = make_annotation_line(db_name, gene_uniquename, bp_term_id,
-                                            "P", &reference,
-                                            &go_ev_code, &with_from, taxon_id, date_modified);
+// the IDs of a model's molecules and activities mapped to the molecule
+// terms and to the terms of the activity enablers
+struct ModelIndex<'a> {
+    molecule_terms: HashMap<&'a str, &'a str>,
+    activity_enablers: HashMap<&'a str, &'a str>,
+}
 
-            ret.push(line)
+impl<'a> ModelIndex<'a> {
+    fn new(model: &'a GoCamPyModel) -> ModelIndex<'a> {
+        ModelIndex {
+            molecule_terms: model.molecules.iter()
+                .map(|molecule| (molecule.id.as_str(), molecule.term.as_str()))
+                .collect(),
+            activity_enablers: model.activities.iter()
+                .map(|activity| (activity.id.as_str(), activity.enabled_by.term.as_str()))
+                .collect(),
         }
+    }
+}
+
+fn slot_extensions(config: &ExtensionConfig, index: &ModelIndex, activity: &Activity,
+                   slot: ExtensionSlot)
+    -> Vec<AnnotationExtension>
+{
+    let mut ret = vec![];
 
-        if let Some(ref bp_association) = bp_association.part_of {
-            let lines =
-                bp_association_lines(go_ev_code_map, db_name, taxon_id,
-                                     gene_uniquename, date_modified, bp_association)?;
-            ret.extend_from_slice(&lines);
+    match slot {
+        ExtensionSlot::OccursIn => {
+            if let Some(ref occurs_in) = activity.occurs_in {
+                ret.push(AnnotationExtension::new("BFO:0000066", "occurs_in", &occurs_in.term));
+            }
+        },
+        ExtensionSlot::HasInput | ExtensionSlot::HasOutput => {
+            let (rel_id, rel_name, associations) =
+                if slot == ExtensionSlot::HasInput {
+                    ("RO:0002233", "has_input", &activity.has_input)
+                } else {
+                    ("RO:0002234", "has_output", &activity.has_output)
+                };
+            for association in associations {
+                if let Some(molecule_term) = index.molecule_terms.get(association.molecule.as_str()) {
+                    ret.push(AnnotationExtension::new(rel_id, rel_name, molecule_term));
+                }
+            }
+        },
+        ExtensionSlot::PartOf => {
+            if let Some(ref bp_association) = activity.part_of {
+                ret.push(AnnotationExtension::new("BFO:0000050", "part_of", &bp_association.term));
+            }
+        },
+        ExtensionSlot::HappensDuring => {
+            if let Some(ref bp_association) = activity.part_of &&
+                let Some(ref happens_during) = bp_association.happens_during
+            {
+                ret.push(AnnotationExtension::new("RO:0002092", "happens_during", happens_during));
+            }
+        },
+        ExtensionSlot::HasPart => {
+            ret.push(AnnotationExtension::new("BFO:0000051", "has_part",
+                                              &activity.molecular_function.term));
+        },
+        ExtensionSlot::Causal => {
+            for causal_association in &activity.causal_associations {
+                let predicate = &causal_association.predicate;
+
+                if config.causal_relations.as_ref()
+                    .is_some_and(|causal_relations| !causal_relations.contains(predicate))
+                {
+                    continue;
+                }
+
+                let Some(downstream_enabler) =
+                    index.activity_enablers.get(causal_association.downstream_activity.as_str())
+                else {
+                    continue;
+                };
+
+                let rel_name = REL_NAMES.get(predicate)
+                    .map(|rel_name| rel_name.replace(' ', "_"))
+                    .unwrap_or_else(|| predicate.to_owned());
+
+                ret.push(AnnotationExtension::new(predicate, &rel_name, downstream_enabler));
+            }
+        },
+    }
+
+    ret
+}
+
+fn activity_extensions(config: &ExtensionConfig, index: &ModelIndex, activity: &Activity)
+    -> HashMap<&'static str, Vec<AnnotationExtension>>
+{
+    let mut ret = HashMap::new();
+
+    for aspect in ["F", "P", "C"] {
+        let Some(slots) = config.slots.get(aspect)
+        else {
+            continue;
+        };
+
+        let extensions: Vec<_> = slots.iter()
+            .flat_map(|slot| slot_extensions(config, index, activity, *slot))
+            .unique()
+            .collect();
+
+        ret.insert(aspect, extensions);
+    }
+
+    ret
+}
+
+// a DB object that the annotations of an activity are made for
+struct Enabler<'a> {
+    object_db: &'a str,
+    object_type: ObjectType,
+    uniquename: &'a str,
+    // the ID in the model, used to find the label
+    term: &'a str,
+    gene_product_form: Option<&'a str>,
+    mf_relation: AnnotationRelation,
+}
+
+impl<'a> Enabler<'a> {
+    fn gene(db_name: &'a str, uniquename: &'a str, term: &'a str,
+            mf_relation: AnnotationRelation)
+        -> Enabler<'a>
+    {
+        Enabler {
+            object_db: db_name,
+            object_type: ObjectType::Protein,
+            uniquename,
+            term,
+            gene_product_form: None,
+            mf_relation,
         }
     }
+}
 
-    Ok(ret)
+// Genes are annotated directly.  Modified proteins are annotated via
+// their gene, with the PR ID as the gene product form.  Complexes give
+// contributes_to annotations for their member genes, or annotations of
+// the complex in ComplexAnnotationMode::Complex.  Anything else (eg. a
+// chemical) has no annotations.
+fn activity_enablers<'a>(config: &AnnotationConfig<'a>, model_id: &str,
+                         activity: &'a Activity)
+    -> Vec<Enabler<'a>>
+{
+    let db_name = config.db_name;
+    let db_name_prefix = db_name.to_owned() + ":";
+
+    let enabled_by = &activity.enabled_by;
+    let term = enabled_by.term.as_str();
+
+    if let Some(uniquename) = term.strip_prefix(&db_name_prefix) {
+        return vec![Enabler::gene(db_name, uniquename, term, AnnotationRelation::Enables)];
+    }
+
+    if term.starts_with("PR:") {
+        let Some(uniquename) = config.protein_forms
+            .and_then(|protein_forms| protein_forms.get(term))
+        else {
+            eprintln!("warning in {}: no gene for modified protein {}", model_id, term);
+            return vec![];
+        };
+
+        let mut enabler = Enabler::gene(db_name, uniquename, term, AnnotationRelation::Enables);
+        enabler.gene_product_form = Some(term);
+        return vec![enabler];
+    }
+
+    let complex_portal_id = term.strip_prefix(COMPLEX_PORTAL_DB)
+        .and_then(|rest| rest.strip_prefix(':'));
+
+    if config.complex_mode == ComplexAnnotationMode::Complex &&
+        let Some(complex_portal_id) = complex_portal_id
+    {
+        return vec![Enabler {
+            object_db: COMPLEX_PORTAL_DB,
+            object_type: ObjectType::Complex,
+            uniquename: complex_portal_id,
+            term,
+            gene_product_form: None,
+            mf_relation: AnnotationRelation::Enables,
+        }];
+    }
+
+    if enabled_by.members.is_empty() && complex_portal_id.is_some() {
+        eprintln!("warning in {}: complex {} has no member genes", model_id, term);
+    }
+
+    enabled_by.members.iter()
+        .filter_map(|member| {
+            let uniquename = member.strip_prefix(&db_name_prefix)?;
+            Some(Enabler::gene(db_name, uniquename, member, AnnotationRelation::ContributesTo))
+        })
+        .collect()
 }
 
-pub fn write_go_annotation_file(writer: &mut dyn Write,
-                                go_ev_code_map: &HashMap<String, String>,
-                                model: &GoCamPyModel, db_name: &str)
-   -> Result<(), Error>
+/// Make the MF, BP and CC annotations of the genes, modified proteins and
+/// complexes enabling the activities of a model, one for each distinct
+/// evidence item.  Unknown evidence codes are reported as warnings.
+pub(crate) fn annotations_from_model(config: &AnnotationConfig, model: &GoCamPyModel)
+    -> ModelAnnotations
 {
-    let mut buf_writer = BufWriter::new(writer);
+    let mut collector = AnnotationCollector {
+        go_ev_code_map: config.go_ev_code_map,
+        export_policy: config.export_policy,
+        explain: config.explain,
+        model_id: &model.id,
+        seen: HashSet::new(),
+        result: ModelAnnotations::default(),
+    };
 
     let Some(ref taxon_id) = model.taxon
     else {
-        return Ok(());
+        return collector.result;
     };
 
-    let taxon_id = taxon_id.replace("NCBITaxon:", "taxon:");
+    let date_modified = model.date_modified.clone().unwrap_or_else(today);
 
-    let db_name_prefix = db_name.to_owned() + ":";
+    let index = ModelIndex::new(model);
 
-    let date_modified = if let Some(ref date_modified) = model.date_modified {
-        date_modified.to_owned()
-    } else {
-        let local: DateTime<Local> = Local::now();
-        local.format("%F").to_string()
-    };
+    let object_labels: HashMap<&str, &str> = model.objects.iter()
+        .filter_map(|object| Some((object.id.as_str(), object.label.as_deref()?)))
+        .collect();
 
     for activity in &model.activities {
-        let Some(gene_uniquename) = &activity.enabled_by.term.strip_prefix(&db_name_prefix)
-        else {
-            // not a gene
+        let enablers = activity_enablers(config, &model.id, activity);
+
+        if enablers.is_empty() {
             continue;
-        };
+        }
 
-        let mf_term_id = &activity.molecular_function.term;
+        let extensions = activity_extensions(config.extension_config, &index, activity);
 
-        if let Some(evidence_item) = activity.enabled_by.evidence.first() {
-            match details_from_item(go_ev_code_map, evidence_item) {
-                Ok(details) => {
-                    if let Some((go_ev_code, reference, with_from)) = details {
-                        let line = make_annotation_line(db_name, gene_uniquename, mf_term_id,
-                                                        "F", &reference,
-                                                        &go_ev_code, &with_from, &taxon_id, &date_modified);
+        let contexts: Vec<_> = enablers.iter()
+            .map(|enabler| ActivityContext {
+                model_id: &model.id,
+                object_db: enabler.object_db,
+                object_type: enabler.object_type,
+                gene_uniquename: enabler.uniquename,
+                gene_label: object_labels.get(enabler.term).cloned(),
+                gene_product_form: enabler.gene_product_form,
+                mf_relation: enabler.mf_relation,
+                taxon_id,
+                date: &date_modified,
+                extensions: &extensions,
+            })
+            .collect();
 
-                        writeln!(buf_writer, "{}", line)?;
-                    }
-                },
-                Err(message) => {
-                    eprintln!("warning in {} for {} {}: {}",
-                              model.id, mf_term_id, gene_uniquename, message);
-                }
-            };
-        }
+        collector.add(&contexts, &activity.molecular_function.term, "F",
+                      |context| context.mf_relation, &activity.enabled_by.evidence);
 
         if let Some(ref bp_association) = activity.part_of {
-            let bp_lines_result =
-                bp_association_lines(go_ev_code_map, db_name, &taxon_id,
-                                     gene_uniquename, &date_modified, bp_association);
-
-            match bp_lines_result {
-                Ok(bp_lines) => {
-                    for line in bp_lines {
-                        writeln!(buf_writer, "{}", line)?;
-                    }
-                },
-                Err(message) => {
-                    eprintln!("warning in {} for {} {}: {}",
-                              model.id, mf_term_id, gene_uniquename, message);
-                }
-            };
+            add_bp_association_annotations(&mut collector, &contexts, bp_association);
         }
 
-        if let Some(ref cc_assocation) = activity.occurs_in &&
-            let Some(evidence_item) = cc_assocation.evidence.first() {
-
+        if let Some(ref cc_assocation) = activity.occurs_in {
             let cc_term_id = &cc_assocation.term;
+            let relation = cc_relation(config.ontology_info, cc_term_id);
+            collector.add(&contexts, cc_term_id, "C", |_| relation, &cc_assocation.evidence);
+        }
+    }
 
-            let details = match details_from_item(go_ev_code_map, evidence_item) {
-                Ok(details) => details,
-                Err(message) => {
-                    eprintln!("warning in {} for {} {}: {}",
-                              model.id, mf_term_id, gene_uniquename, message);
-                    continue;
-                }
-            };
+    collector.result
+}
 
-            let Some((go_ev_code, reference, with_from)) = details
-            else {
-                continue;
-            };
+/// Write a GAF 2.2 file.  Lines with an empty mandatory column are
+/// reported and left out.
+pub fn write_go_annotation_file(writer: &mut dyn Write, db_name: &str,
+                                gene_metadata: Option<&GeneMetadataMap>,
+                                annotations: &[GoAnnotation])
+   -> Result<(), Error>
+{
+    let mut buf_writer = BufWriter::new(writer);
+
+    writeln!(buf_writer, "!gaf-version: 2.2")?;
+    writeln!(buf_writer, "!generated-by: {}", db_name)?;
+    writeln!(buf_writer, "!date-generated: {}", today())?;
+
+    for annotation in annotations {
+        let metadata = gene_metadata
+            .and_then(|gene_metadata| gene_metadata.get(&annotation.gene_uniquename));
+
+        let columns = make_annotation_columns(db_name, annotation, metadata);
 
-            let line = make_annotation_line(db_name, gene_uniquename, cc_term_id,
-                                            "C", &reference,
-                                            &go_ev_code, &with_from, &taxon_id, &date_modified);
+        let empty_columns = empty_mandatory_columns(&columns);
 
-            writeln!(buf_writer, "{}", line)?;
+        if !empty_columns.is_empty() {
+            eprintln!("skipping annotation of {} to {} from {}: empty GAF column(s): {}",
+                      annotation.gene_uniquename, annotation.term_id, annotation.model_id,
+                      empty_columns.iter().join(", "));
+            continue;
         }
+
+        writeln!(buf_writer, "{}", columns.join("\t"))?;
     }
 
     Ok(())
 }
+
+fn today() -> String {
+    let local: DateTime<Local> = Local::now();
+    local.format("%F").to_string()
+}
+
+/// Write GPAD 2.0: one line per annotation with the relation ID, the ECO
+/// code and the model ID as an annotation property
+pub(crate) fn write_gpad_file(writer: &mut dyn Write, db_name: &str,
+                              annotations: &[GoAnnotation])
+   -> Result<(), Error>
+{
+    let mut buf_writer = BufWriter::new(writer);
+
+    writeln!(buf_writer, "!gpa-version: 2.0")?;
+    writeln!(buf_writer, "!generated-by: {}", db_name)?;
+    writeln!(buf_writer, "!date-generated: {}", today())?;
+
+    for annotation in annotations {
+        let properties = format!("go_evidence={}|noctua-model-id={}",
+                                 annotation.go_ev_code, annotation.model_id);
+
+        writeln!(buf_writer, "{}:{}\t\t{}\t{}\t{}\t{}\t{}\t\t{}\t{}\t{}\t{}",
+                 annotation.object_db, annotation.gene_uniquename, annotation.relation.id(),
+                 annotation.term_id, annotation.reference, annotation.eco_code,
+                 annotation.with_from, annotation.date, db_name,
+                 gpad_extensions(&annotation.extensions), properties)?;
+    }
+
+    Ok(())
+}
+
+// "frp1 Spom" -> "frp1"
+fn symbol_from_label(label: &str) -> &str {
+    label.split_whitespace().next().unwrap_or(label)
+}
+
+/// Write a GPI 2.0 file with one line for each gene product in the
+/// annotations
+pub(crate) fn write_gpi_file(writer: &mut dyn Write, db_name: &str,
+                             gene_metadata: Option<&GeneMetadataMap>,
+                             annotations: &[GoAnnotation])
+   -> Result<(), Error>
+{
+    let mut buf_writer = BufWriter::new(writer);
+
+    writeln!(buf_writer, "!gpi-version: 2.0")?;
+    writeln!(buf_writer, "!generated-by: {}", db_name)?;
+    writeln!(buf_writer, "!date-generated: {}", today())?;
+
+    let mut gene_products = BTreeMap::new();
+
+    for annotation in annotations {
+        gene_products.entry((&annotation.object_db, &annotation.gene_uniquename))
+            .or_insert(annotation);
+    }
+
+    for ((object_db, gene_uniquename), annotation) in gene_products {
+        let metadata = gene_metadata
+            .filter(|_| annotation.object_type == ObjectType::Protein)
+            .and_then(|gene_metadata| gene_metadata.get(gene_uniquename));
+
+        let symbol = gene_symbol(annotation, metadata);
+
+        let (name, synonyms) =
+            if let Some(metadata) = metadata {
+                (metadata.name.as_deref().unwrap_or_default(), metadata.synonyms.join("|"))
+            } else if annotation.object_type == ObjectType::Complex {
+                (annotation.gene_label.as_deref().unwrap_or_default(), String::new())
+            } else {
+                ("", String::new())
+            };
+
+        writeln!(buf_writer, "{}:{}\t{}\t{}\t{}\t{}\t{}\t\t\t\t\t",
+                 object_db, gene_uniquename, symbol, name, synonyms,
+                 annotation.object_type.type_id(), annotation.taxon_id)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::collections::HashMap;
+    use std::fs::File;
+    use std::io::BufReader;
+    use std::sync::LazyLock;
+
+    use pombase_gocam::gocam_py::gocam_py_parse;
+
+    use crate::ontology_info::{OntologyInfo, parse_closure};
+
+    use super::{AnnotationConfig, AnnotationRelation, ComplexAnnotationMode, EvidenceRules, ExportPolicy, ExportRule,
+                ExtensionConfig, ExtensionSlot, SkipReason, annotations_from_model, parse_gene_metadata, parse_protein_forms,
+                write_go_annotation_file, write_gpad_file, write_gpi_file};
+
+    fn test_ev_code_map() -> HashMap<String, String> {
+        [("ECO:0000304", "TAS"), ("ECO:0000303", "NAS"), ("ECO:0000315", "IMP"),
+         ("ECO:0000314", "IDA"), ("ECO:0000266", "ISO"), ("ECO:0000250", "ISS"),
+         ("ECO:0000316", "IGI"), ("ECO:0000353", "IPI"), ("ECO:0000318", "IBA"), ("ECO:0000269", "EXP")]
+            .iter()
+            .map(|(eco, go)| (eco.to_string(), go.to_string()))
+            .collect()
+    }
+
+    static TEST_EV_CODE_MAP: LazyLock<HashMap<String, String>> = LazyLock::new(test_ev_code_map);
+    static DEFAULT_EXTENSION_CONFIG: LazyLock<ExtensionConfig> =
+        LazyLock::new(ExtensionConfig::default);
+    static DEFAULT_EXPORT_POLICY: LazyLock<ExportPolicy> = LazyLock::new(ExportPolicy::default);
+    static TEST_CLOSURE: LazyLock<OntologyInfo> = LazyLock::new(|| {
+        let file = File::open("tests/data/closure.tsv").unwrap();
+        parse_closure(&mut BufReader::new(file)).unwrap()
+    });
+
+    /// The default settings for the tests to override with struct update
+    /// syntax
+    pub(crate) fn test_config() -> AnnotationConfig<'static> {
+        AnnotationConfig {
+            db_name: "PomBase",
+            go_ev_code_map: &TEST_EV_CODE_MAP,
+            ontology_info: Some(&TEST_CLOSURE),
+            extension_config: &DEFAULT_EXTENSION_CONFIG,
+            export_policy: &DEFAULT_EXPORT_POLICY,
+            explain: false,
+            complex_mode: ComplexAnnotationMode::default(),
+            protein_forms: None,
+        }
+    }
+
+    #[test]
+    fn gpad_test() {
+        let config = test_config();
+
+        let mut source = File::open("tests/data/67ae98b500000055.yaml").unwrap();
+        let model = gocam_py_parse(&mut source).unwrap();
+
+        let annotations = annotations_from_model(&config, &model).annotations;
+        assert!(!annotations.is_empty());
+        assert!(annotations.iter()
+                .filter(|annotation| annotation.aspect == "F")
+                .all(|annotation| annotation.relation == AnnotationRelation::Enables));
+
+        let mut gpad = vec![];
+        write_gpad_file(&mut gpad, "PomBase", &annotations).unwrap();
+        let gpad = String::from_utf8(gpad).unwrap();
+
+        assert!(gpad.starts_with("!gpa-version: 2.0\n"));
+        for line in gpad.lines().filter(|line| !line.starts_with('!')) {
+            let columns: Vec<_> = line.split('\t').collect();
+            assert_eq!(columns.len(), 12);
+            assert!(columns[0].starts_with("PomBase:"));
+            assert!(columns[2].starts_with("RO:") || columns[2].starts_with("BFO:"));
+            assert!(columns[5].starts_with("ECO:"));
+            assert_eq!(columns[8], "2025-09-29");
+            assert_eq!(columns[9], "PomBase");
+            assert!(columns[11].ends_with("noctua-model-id=gomodel:67ae98b500000055"));
+        }
+
+        let mut gpi = vec![];
+        write_gpi_file(&mut gpi, "PomBase", None, &annotations).unwrap();
+        let gpi = String::from_utf8(gpi).unwrap();
+
+        assert!(gpi.starts_with("!gpi-version: 2.0\n"));
+        let gpi_lines: Vec<_> = gpi.lines().filter(|line| !line.starts_with('!')).collect();
+        assert!(gpi_lines.iter().all(|line| line.split('\t').count() == 11));
+        assert!(gpi_lines.contains(&"PomBase:SPAC23G3.02c\tsib1\t\t\tPR:000000001\tNCBITaxon:4896\t\t\t\t\t"));
+    }
+
+    #[test]
+    fn gaf_test() {
+        let config = test_config();
+
+        let mut source = File::open("tests/data/67ae98b500000055.yaml").unwrap();
+        let model = gocam_py_parse(&mut source).unwrap();
+        let annotations = annotations_from_model(&config, &model).annotations;
+        assert!(annotations.iter().any(|annotation| annotation.aspect == "C"));
+
+        // without the closure complexes can't be found so every CC
+        // annotation is is_active_in
+        let no_closure_config = AnnotationConfig {
+            ontology_info: None,
+            ..test_config()
+        };
+        let no_closure_cc_annotations: Vec<_> =
+            annotations_from_model(&no_closure_config, &model).annotations.into_iter()
+            .filter(|annotation| annotation.aspect == "C")
+            .collect();
+        assert_eq!(no_closure_cc_annotations.len(),
+                   annotations.iter().filter(|annotation| annotation.aspect == "C").count());
+        assert!(no_closure_cc_annotations.iter()
+                .all(|annotation| annotation.relation == AnnotationRelation::IsActiveIn));
+
+        let metadata_file = File::open("tests/data/gene_metadata.tsv").unwrap();
+        let mut metadata_reader = BufReader::new(metadata_file);
+        let gene_metadata = parse_gene_metadata(&mut metadata_reader).unwrap();
+        assert_eq!(gene_metadata["SPAC1F7.07c"].synonyms, vec!["fio1", "SPAC1F7.07"]);
+        assert_eq!(gene_metadata["SPBC1683.09c"].synonyms, Vec::<String>::new());
+
+        let mut gaf = vec![];
+        write_go_annotation_file(&mut gaf, "PomBase", Some(&gene_metadata), &annotations)
+            .unwrap();
+        let gaf = String::from_utf8(gaf).unwrap();
+
+        assert!(gaf.starts_with("!gaf-version: 2.2\n!generated-by: PomBase\n!date-generated: "));
+
+        let lines: Vec<Vec<_>> = gaf.lines()
+            .filter(|line| !line.starts_with('!'))
+            .map(|line| line.split('\t').collect())
+            .collect();
+        assert!(!lines.is_empty());
+
+        for columns in &lines {
+            assert_eq!(columns.len(), 17);
+            let expected_qualifier = match columns[8] {
+                "F" => "enables",
+                "P" => "involved_in",
+                _ => "is_active_in",
+            };
+            assert_eq!(columns[3], expected_qualifier);
+            assert_eq!(columns[13], "20250929");
+        }
+
+        let sib1_mf = lines.iter()
+            .find(|columns| columns[1] == "SPAC23G3.02c" && columns[8] == "F")
+            .unwrap();
+        assert_eq!(sib1_mf[2], "sib1");
+        assert_eq!(sib1_mf[9], "ferrichrome synthetase Sib1");
+        assert_eq!(sib1_mf[10], "SPAC23G3.02");
+        assert_eq!(sib1_mf[12], "taxon:4896");
+    }
+
+    #[test]
+    fn extensions_test() {
+        let mut extension_config = ExtensionConfig::default();
+        extension_config.slots.insert("F".to_owned(),
+                                      vec![ExtensionSlot::OccursIn, ExtensionSlot::HasInput,
+                                           ExtensionSlot::Causal]);
+        extension_config.slots.insert("P".to_owned(), vec![ExtensionSlot::HasPart]);
+
+        let config = AnnotationConfig {
+            extension_config: &extension_config,
+            ..test_config()
+        };
+
+        let mut source = File::open("tests/data/67ae98b500000055.yaml").unwrap();
+        let model = gocam_py_parse(&mut source).unwrap();
+        let annotations = annotations_from_model(&config, &model).annotations;
+
+        let mut gaf = vec![];
+        write_go_annotation_file(&mut gaf, "PomBase", None, &annotations).unwrap();
+        let gaf = String::from_utf8(gaf).unwrap();
+
+        let lines: Vec<Vec<_>> = gaf.lines()
+            .filter(|line| !line.starts_with('!'))
+            .map(|line| line.split('\t').collect())
+            .collect();
+
+        let mf_extensions = lines.iter()
+            .find(|columns| columns[1] == "SPBC17G9.06c" && columns[8] == "F")
+            .map(|columns| columns[15])
+            .unwrap();
+        // the input appears twice in the model but only once here
+        assert!(mf_extensions.starts_with("occurs_in(GO:0005829),has_input(CHEBI:78275),"));
+        assert_eq!(mf_extensions.matches("has_input").count(), 1);
+        assert!(mf_extensions.ends_with("(PomBase:SPAC23G3.02c)"));
+
+        let bp_extensions = lines.iter()
+            .find(|columns| columns[1] == "SPBC29A3.11c" && columns[8] == "P")
+            .map(|columns| columns[15])
+            .unwrap();
+        assert_eq!(bp_extensions, "has_part(GO:0000064)");
+
+        assert!(lines.iter()
+                .filter(|columns| columns[8] == "C")
+                .all(|columns| columns[15].is_empty()));
+
+        let mut gpad = vec![];
+        write_gpad_file(&mut gpad, "PomBase", &annotations).unwrap();
+        let gpad = String::from_utf8(gpad).unwrap();
+        assert!(gpad.lines().any(|line| line.contains("\tBFO:0000051(GO:0000064)\t")));
+    }
+
+    #[test]
+    fn all_evidence_test() {
+        let config = test_config();
+
+        let mut source = File::open("tests/data/67ae98b500000055.yaml").unwrap();
+        let mut model = gocam_py_parse(&mut source).unwrap();
+
+        let model_annotations = annotations_from_model(&config, &model);
+        assert_eq!(model_annotations.annotations.len(), 22);
+        assert_eq!(model_annotations.skipped_evidence[&SkipReason::NotAllowed], 36);
+        assert_eq!(model_annotations.skipped_evidence[&SkipReason::UnknownEvidenceCode], 7);
+        assert_eq!(model_annotations.skipped_count(), 43);
+
+        // give sib1's MF a second reference and a repeat of the first
+        let sib1_activity = model.activities.iter_mut()
+            .find(|activity| activity.enabled_by.term == "PomBase:SPAC23G3.02c")
+            .unwrap();
+        let evidence_item = sib1_activity.enabled_by.evidence[0].clone();
+        let mut second_evidence_item = evidence_item.clone();
+        second_evidence_item.reference = Some("GO_REF:0000024".to_owned());
+        sib1_activity.enabled_by.evidence.push(evidence_item);
+        sib1_activity.enabled_by.evidence.push(second_evidence_item);
+
+        let model_annotations = annotations_from_model(&config, &model);
+        assert_eq!(model_annotations.annotations.len(), 23);
+        assert_eq!(model_annotations.skipped_evidence[&SkipReason::Duplicate], 1);
+
+        let sib1_mf_references: Vec<_> = model_annotations.annotations.iter()
+            .filter(|annotation| annotation.gene_uniquename == "SPAC23G3.02c" &&
+                    annotation.aspect == "F")
+            .map(|annotation| annotation.reference.as_str())
+            .collect();
+        assert_eq!(sib1_mf_references, vec!["GO_REF:0000051", "GO_REF:0000024"]);
+    }
+
+    #[test]
+    fn part_of_without_evidence_test() {
+        let mut export_policy = ExportPolicy::default();
+        export_policy.default_rules = EvidenceRules::default();
+        let config = AnnotationConfig {
+            export_policy: &export_policy,
+            ..test_config()
+        };
+
+        let mut source = File::open("tests/data/67ae98b500000055.yaml").unwrap();
+        let mut model = gocam_py_parse(&mut source).unwrap();
+
+        let sib1_activity = model.activities.iter_mut()
+            .find(|activity| activity.enabled_by.term == "PomBase:SPAC23G3.02c")
+            .unwrap();
+        let bp_association = sib1_activity.part_of.as_mut().unwrap();
+        let mut parent_bp_association = bp_association.clone();
+        parent_bp_association.term = "GO:0019290".to_owned();
+        bp_association.evidence.clear();
+        bp_association.part_of = Some(parent_bp_association.into());
+
+        let sib1_bp_terms: Vec<_> = annotations_from_model(&config, &model).annotations.iter()
+            .filter(|annotation| annotation.gene_uniquename == "SPAC23G3.02c" &&
+                    annotation.aspect == "P")
+            .map(|annotation| annotation.term_id.clone())
+            .collect();
+        assert!(sib1_bp_terms.contains(&"GO:0019290".to_owned()));
+    }
+
+    #[test]
+    fn export_policy_test() {
+
+        let default_policy = ExportPolicy::default();
+        let mut bp_rules = default_policy.default_rules.clone();
+        bp_rules.allow_reference_prefixes.push("PMID:".to_owned());
+        bp_rules.deny_eco_codes.push("ECO:0000353".to_owned());
+
+        let mut export_policy = default_policy.clone();
+        export_policy.aspect_rules.insert("P".to_owned(), bp_rules);
+
+        let config = AnnotationConfig {
+            export_policy: &export_policy,
+            explain: true,
+            ..test_config()
+        };
+
+        let mut source = File::open("tests/data/67ae98b500000055.yaml").unwrap();
+        let model = gocam_py_parse(&mut source).unwrap();
+
+        let model_annotations = annotations_from_model(&config, &model);
+        assert_eq!(model_annotations.annotations.len(), 32);
+        assert_eq!(model_annotations.skipped_evidence[&SkipReason::NotAllowed], 25);
+        assert_eq!(model_annotations.skipped_evidence[&SkipReason::Denied], 1);
+        assert_eq!(model_annotations.skipped_evidence[&SkipReason::UnknownEvidenceCode], 7);
+
+        // PMIDs are still only allowed for BP
+        assert!(model_annotations.annotations.iter()
+                .filter(|annotation| annotation.reference.starts_with("PMID:"))
+                .all(|annotation| annotation.aspect == "P" ||
+                     annotation.eco_code == "ECO:0000304"));
+
+        let explanations = &model_annotations.explanations;
+        assert_eq!(explanations.len(), 65);
+        assert!(explanations.iter().any(|explanation| {
+            explanation.to_string() ==
+                "SPAC23G3.02c\tGO:0031169\tP\tECO:0000316\tPMID:20435771\taccepted\tallow_reference_prefixes: PMID:"
+        }));
+        assert!(explanations.iter().any(|explanation| {
+            explanation.rule == ExportRule::DenyEcoCode("ECO:0000353".to_owned()) &&
+                explanation.term_id == "GO:0045944"
+        }));
+
+        let no_allow_lists = EvidenceRules::default();
+        let mut all_policy = ExportPolicy::default();
+        all_policy.default_rules = no_allow_lists;
+        let config = AnnotationConfig {
+            export_policy: &all_policy,
+            explain: false,
+            ..config
+        };
+        let model_annotations = annotations_from_model(&config, &model);
+        assert!(model_annotations.explanations.is_empty());
+        assert_eq!(model_annotations.annotations.len(), 55);
+        assert_eq!(model_annotations.skipped_evidence[&SkipReason::Duplicate], 3);
+        assert_eq!(model_annotations.skipped_count(), 10);
+    }
+
+    #[test]
+    fn complex_and_modified_protein_test() {
+
+        let file = File::open("tests/data/protein_forms.tsv").unwrap();
+        let protein_forms = parse_protein_forms(&mut BufReader::new(file)).unwrap();
+        assert_eq!(protein_forms.len(), 1);
+
+        let mut config = AnnotationConfig {
+            protein_forms: Some(&protein_forms),
+            ..test_config()
+        };
+
+        let mut source = File::open("tests/data/67ae98b500000055.yaml").unwrap();
+        let mut model = gocam_py_parse(&mut source).unwrap();
+
+        for activity in model.activities.iter_mut() {
+            if activity.enabled_by.term == "PomBase:SPAC23G3.02c" {
+                activity.enabled_by.term = "ComplexPortal:CPX-25".to_owned();
+                activity.enabled_by.members = vec!["PomBase:SPAC23G3.02c".to_owned(),
+                                                   "PomBase:SPAC23G3.03".to_owned()];
+            }
+            if activity.enabled_by.term == "PomBase:SPBC17G9.06c" {
+                activity.enabled_by.term = "PR:000050567".to_owned();
+            }
+        }
+
+        let write_gaf = |config: &AnnotationConfig| {
+            let annotations = annotations_from_model(config, &model).annotations;
+            let mut gaf = vec![];
+            write_go_annotation_file(&mut gaf, "PomBase", None, &annotations).unwrap();
+            String::from_utf8(gaf).unwrap().lines()
+                .filter(|line| !line.starts_with('!'))
+                .map(|line| line.split('\t').map(str::to_owned).collect::<Vec<_>>())
+                .collect::<Vec<_>>()
+        };
+
+        let lines = write_gaf(&config);
+
+        let mf_line = |lines: &[Vec<String>], id: &str| {
+            lines.iter()
+                .find(|columns| columns[1] == id && columns[8] == "F")
+                .cloned()
+        };
+
+        for member in ["SPAC23G3.02c", "SPAC23G3.03"] {
+            let columns = mf_line(&lines, member).unwrap();
+            assert_eq!(columns[3], "contributes_to");
+            assert_eq!(columns[4], "GO:1904091");
+        }
+
+        let modified_protein = mf_line(&lines, "SPBC17G9.06c").unwrap();
+        assert_eq!(modified_protein[0], "PomBase");
+        assert_eq!(modified_protein[3], "enables");
+        assert_eq!(modified_protein[16], "PR:000050567");
+
+        // the evidence of a complex is counted and explained once, not once
+        // per member
+        let explain_config = AnnotationConfig {
+            explain: true,
+            ..config
+        };
+        let model_annotations = annotations_from_model(&explain_config, &model);
+        assert_eq!(model_annotations.skipped_count(), 43);
+        assert_eq!(model_annotations.explanations.iter()
+                   .filter(|explanation| {
+                       explanation.enablers == "SPAC23G3.02c,SPAC23G3.03" &&
+                           explanation.term_id == "GO:1904091"
+                   })
+                   .count(), 1);
+
+        config.complex_mode = ComplexAnnotationMode::Complex;
+        let lines = write_gaf(&config);
+
+        assert!(mf_line(&lines, "SPAC23G3.02c").is_none());
+        let complex = mf_line(&lines, "CPX-25").unwrap();
+        assert_eq!(complex[0], "ComplexPortal");
+        assert_eq!(complex[3], "enables");
+        assert_eq!(complex[11], "protein-containing complex");
+
+        // without a mapping the modified protein is skipped
+        config.protein_forms = None;
+        let lines = write_gaf(&config);
+        assert!(mf_line(&lines, "SPBC17G9.06c").is_none());
+    }
+}
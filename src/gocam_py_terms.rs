@@ -0,0 +1,197 @@
+use std::collections::{HashMap, HashSet};
+
+use pombase_gocam::gocam_py::{BiologicalProcessAssociation, EvidenceItem, GoCamPyModel};
+
+/// What a term slot of a gocam-py model holds
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum SlotKind {
+    Enabler,
+    ComplexMember,
+    Evidence,
+    MolecularFunction,
+    BiologicalProcess,
+    // a process or a phase
+    HappensDuring,
+    CellularComponent,
+    Molecule,
+}
+
+/// A term used in a gocam-py model
+#[derive(Clone, Debug)]
+pub(crate) struct TermSlot<'a> {
+    pub term_id: &'a str,
+    // the path to the slot, eg. "activities[3].has_input[0].molecule"
+    pub field: String,
+    pub individual_id: &'a str,
+    pub kind: SlotKind,
+}
+
+#[derive(Default)]
+struct SlotWalker<'a> {
+    slots: Vec<TermSlot<'a>>,
+}
+
+impl<'a> SlotWalker<'a> {
+    fn add(&mut self, term_id: &'a str, field: String, individual_id: &'a str, kind: SlotKind) {
+        self.slots.push(TermSlot {
+            term_id,
+            field,
+            individual_id,
+            kind,
+        });
+    }
+
+    fn add_evidence(&mut self, path: &str, individual_id: &'a str,
+                    evidence: &'a [EvidenceItem]) {
+        for (idx, evidence_item) in evidence.iter().enumerate() {
+            self.add(&evidence_item.term, format!("{}.evidence[{}].term", path, idx),
+                     individual_id, SlotKind::Evidence);
+        }
+    }
+
+    fn add_bp_association(&mut self, path: &str, individual_id: &'a str,
+                          bp_association: &'a BiologicalProcessAssociation) {
+        self.add(&bp_association.term, format!("{}.term", path), individual_id,
+                 SlotKind::BiologicalProcess);
+
+        if let Some(ref happens_during) = bp_association.happens_during {
+            self.add(happens_during, format!("{}.happens_during.term", path), individual_id,
+                     SlotKind::HappensDuring);
+        }
+
+        self.add_evidence(path, individual_id, &bp_association.evidence);
+
+        if let Some(ref parent_bp_association) = bp_association.part_of {
+            self.add_bp_association(&format!("{}.part_of", path), individual_id,
+                                    parent_bp_association);
+        }
+    }
+}
+
+/// Every term slot of a gocam-py model, in the order of the model.  A
+/// molecule's term is returned for each has_input and has_output that
+/// refers to the molecule, or as "molecules[N].term" if nothing does.
+pub(crate) fn term_slots(model: &GoCamPyModel) -> Vec<TermSlot<'_>> {
+    let mut walker = SlotWalker::default();
+
+    let molecule_terms: HashMap<&str, &str> = model.molecules.iter()
+        .map(|molecule| (molecule.id.as_str(), molecule.term.as_str()))
+        .collect();
+
+    let mut referenced_molecules = HashSet::new();
+
+    for (idx, activity) in model.activities.iter().enumerate() {
+        let path = format!("activities[{}]", idx);
+        let individual_id = activity.id.as_str();
+
+        walker.add(&activity.enabled_by.term, format!("{}.enabled_by.term", path),
+                   individual_id, SlotKind::Enabler);
+
+        for (member_idx, member) in activity.enabled_by.members.iter().enumerate() {
+            walker.add(member, format!("{}.enabled_by.members[{}]", path, member_idx),
+                       individual_id, SlotKind::ComplexMember);
+        }
+
+        walker.add_evidence(&format!("{}.enabled_by", path), individual_id,
+                            &activity.enabled_by.evidence);
+
+        walker.add(&activity.molecular_function.term,
+                   format!("{}.molecular_function.term", path), individual_id,
+                   SlotKind::MolecularFunction);
+
+        if let Some(ref occurs_in) = activity.occurs_in {
+            walker.add(&occurs_in.term, format!("{}.occurs_in.term", path), individual_id,
+                       SlotKind::CellularComponent);
+            walker.add_evidence(&format!("{}.occurs_in", path), individual_id,
+                                &occurs_in.evidence);
+        }
+
+        if let Some(ref bp_association) = activity.part_of {
+            walker.add_bp_association(&format!("{}.part_of", path), individual_id,
+                                      bp_association);
+        }
+
+        for (slot_name, molecule_associations) in [("has_input", &activity.has_input),
+                                                   ("has_output", &activity.has_output)] {
+            for (molecule_idx, molecule_association) in molecule_associations.iter().enumerate() {
+                let molecule_path = format!("{}.{}[{}]", path, slot_name, molecule_idx);
+
+                walker.add_evidence(&molecule_path, individual_id,
+                                    &molecule_association.evidence);
+
+                let molecule_id = molecule_association.molecule.as_str();
+                referenced_molecules.insert(molecule_id);
+
+                if let Some(molecule_term) = molecule_terms.get(molecule_id) {
+                    walker.add(*molecule_term, format!("{}.molecule", molecule_path),
+                               molecule_id, SlotKind::Molecule);
+                }
+            }
+        }
+
+        for (causal_idx, causal_association) in activity.causal_associations.iter().enumerate() {
+            walker.add_evidence(&format!("{}.causal_associations[{}]", path, causal_idx),
+                                individual_id, &causal_association.evidence);
+        }
+    }
+
+    for (idx, molecule) in model.molecules.iter().enumerate() {
+        let individual_id = molecule.id.as_str();
+
+        if !referenced_molecules.contains(individual_id) {
+            walker.add(&molecule.term, format!("molecules[{}].term", idx), individual_id,
+                       SlotKind::Molecule);
+        }
+
+        if let Some(ref located_in) = molecule.located_in {
+            walker.add(&located_in.term, format!("molecules[{}].located_in.term", idx),
+                       individual_id, SlotKind::CellularComponent);
+        }
+    }
+
+    walker.slots
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+
+    use pombase_gocam::gocam_py::gocam_py_parse;
+
+    use super::{SlotKind, term_slots};
+
+    #[test]
+    fn term_slots_test() {
+        let mut source = File::open("tests/data/67ae98b500000055.yaml").unwrap();
+        let mut model = gocam_py_parse(&mut source).unwrap();
+
+        model.activities[0].part_of.as_mut().unwrap().happens_during =
+            Some("GO:0000084".to_owned());
+        model.activities[0].enabled_by.members = vec!["PomBase:SPAC23G3.02c".to_owned()];
+
+        let slots = term_slots(&model);
+
+        let first_activity_fields: Vec<_> = slots.iter()
+            .filter(|slot| slot.field.starts_with("activities[0]."))
+            .map(|slot| (slot.field.as_str(), slot.kind))
+            .collect();
+
+        assert_eq!(&first_activity_fields[..8],
+                   &[("activities[0].enabled_by.term", SlotKind::Enabler),
+                     ("activities[0].enabled_by.members[0]", SlotKind::ComplexMember),
+                     ("activities[0].enabled_by.evidence[0].term", SlotKind::Evidence),
+                     ("activities[0].molecular_function.term", SlotKind::MolecularFunction),
+                     ("activities[0].occurs_in.term", SlotKind::CellularComponent),
+                     ("activities[0].occurs_in.evidence[0].term", SlotKind::Evidence),
+                     ("activities[0].part_of.term", SlotKind::BiologicalProcess),
+                     ("activities[0].part_of.happens_during.term", SlotKind::HappensDuring)]);
+
+        let input_slot = slots.iter()
+            .find(|slot| slot.field == "activities[0].has_input[0].molecule")
+            .unwrap();
+        assert_eq!(input_slot.individual_id, "gomodel:67ae98b500000055/67ae98b500000089");
+
+        assert!(slots.iter().any(|slot| slot.field.contains(".causal_associations[") &&
+                                 slot.kind == SlotKind::Evidence));
+    }
+}
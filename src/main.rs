@@ -1,40 +1,98 @@
-use std::{collections::{BTreeSet, HashMap, HashSet}, fs::File, io::{BufRead, stdout}, path::PathBuf, process::exit};
+use std::{collections::{BTreeMap, BTreeSet, HashMap, HashSet}, fs::File, io::{BufRead, stdout}, path::PathBuf, process::exit};
 
 use std::io::BufReader;
 use std::io::Read;
 
-use clap::{Parser, Subcommand};
+use clap::{Parser, Subcommand, ValueEnum};
 use itertools::Itertools;
 use petgraph::dot::{Dot, Config};
 use yaml_rust2::{Yaml, YamlLoader};
 
 use pombase_gocam::{GoCamActivity, GoCamEnabledBy, GoCamMergeAlgorithm,
                     GoCamModel, GoCamModelId, GoCamNode, GoCamNodeType,
-                    RemoveType, gocam_py::{GoCamPyModel, gocam_py_parse},
+                    RemoveType, gocam_py::GoCamPyModel,
                     overlaps::{GoCamNodeOverlap, find_activity_overlaps, find_chemical_overlaps},
-                    parse_gocam_py_model, parse_raw_gocam_model,
-                    raw::{GoCamRawModel, gocam_parse_raw}};
+                    raw::GoCamRawModel};
 use pombase_gocam_process::*;
 
 mod ontology_info;
+mod closure_cache;
+mod ontology_file;
 mod allowed_relation_config;
 mod allowed_relation_check;
 mod obsolete_terms;
+mod aspect_check;
+mod taxon_check;
+mod gocam_py_terms;
 mod go_format_writer;
-
-use ontology_info::parse_closure;
+mod annotation_compare;
+mod model_diff;
+mod model_loader;
+mod model_source;
+mod model_cache;
+mod corpus_diff;
+mod diagnostic;
+mod validate;
+mod replace_terms;
+
+use ontology_info::OntologyInfo;
+use closure_cache::read_closure_file;
+use ontology_file::{parse_obo, parse_obographs_json};
 use allowed_relation_config::parse_allowed_relations_config;
 use allowed_relation_check::check_relations;
-use obsolete_terms::find_obsolete_terms;
-use go_format_writer::write_go_annotation_file;
+use annotation_compare::{COMPARISON_TSV_HEADER, ComparisonStatus, compare_annotations,
+                         parse_annotation_file};
+use model_diff::{activity_evidence, diff_models};
+use model_loader::{InputFormat, LOAD_FAILURE_EXIT_CODE, ModelLoader, is_compressed,
+                   read_gocam_py_file, read_raw_model_file, sniff_format};
+use model_source::ModelSource;
+use model_cache::ModelCache;
+use corpus_diff::{ModelSummary, diff_corpora, write_markdown, write_tsv};
+use go_format_writer::{AnnotationConfig, ComplexAnnotationMode, EvidenceRules, ExportPolicy,
+                       ExtensionConfig, ExtensionSlot, GoAnnotation, annotations_from_model,
+                       parse_gene_metadata, parse_protein_forms,
+                       write_go_annotation_file, write_gpad_file, write_gpi_file};
+use replace_terms::{TermReplacements, parse_term_mapping, replace_terms_in_model,
+                    replacements_from_ontology, unified_diff};
+use validate::{ValidationConfig, find_aspect_mismatches_in_loaded_model,
+               find_obsolete_terms_in_loaded_model, find_taxon_violations_in_loaded_model,
+               load_model, validate_model};
 
 #[derive(Parser)]
 #[command(version, about, long_about = None)]
 struct Args {
+    /// Skip model files that fail to load, then print a summary of the
+    /// failures and exit with status 3
+    #[arg(long, global = true)]
+    keep_going: bool,
+    /// The format of the model files.  By default it is found from the
+    /// contents of each file.  gzip and zstd compressed files are always
+    /// read.
+    #[arg(long, global = true, value_enum)]
+    input_format: Option<InputFormat>,
+    /// The number of threads for loading and checking models, by default
+    /// one per CPU.  Output is in the same order whatever the number.
+    #[arg(long, global = true)]
+    jobs: Option<usize>,
+    /// Store parsed models in this directory and reuse them when the same
+    /// file is read again
+    #[arg(long, global = true)]
+    cache_dir: Option<PathBuf>,
     #[command(subcommand)]
     action: Action,
 }
 
+#[derive(Subcommand)]
+enum CacheAction {
+    /// Remove the entries made by other versions of the tool and those
+    /// that haven't been used recently
+    Gc {
+        /// Remove entries that haven't been used for this many days
+        #[arg(long, default_value_t = 30)]
+        max_age_days: u64,
+    },
+}
+
 #[derive(Subcommand)]
 enum Action {
     #[command(arg_required_else_help = true)]
@@ -148,6 +206,12 @@ enum Action {
         orcid_map_file: PathBuf,
     },
     #[command(arg_required_else_help = true)]
+    /// Manage the --cache-dir of parsed models
+    Cache {
+        #[command(subcommand)]
+        action: CacheAction,
+    },
+    #[command(arg_required_else_help = true)]
     Serialize {
         #[arg(required = true)]
         paths: Vec<PathBuf>,
@@ -203,21 +267,173 @@ enum Action {
         paths: Vec<PathBuf>,
     },
     #[command(arg_required_else_help = true)]
+    /// Find obsolete and unknown terms, using a closure file and/or OBO or
+    /// obographs JSON files
     FindObsoleteTerms {
         #[arg(long)]
-        closure_file: PathBuf,
+        closure_file: Option<PathBuf>,
+        #[arg(long)]
+        ontology_file: Vec<PathBuf>,
+        #[arg(long)]
+        orcid_map_file: PathBuf,
+        paths: Vec<PathBuf>,
+    },
+    #[command(arg_required_else_help = true)]
+    /// Find MF, BP and CC terms in the wrong slot, eg. a BP term in
+    /// occurs_in, using the namespaces from OBO or obographs JSON files
+    CheckAspects {
+        #[arg(long, required = true)]
+        ontology_file: Vec<PathBuf>,
+        #[arg(long)]
+        orcid_map_file: PathBuf,
+        paths: Vec<PathBuf>,
+    },
+    #[command(arg_required_else_help = true)]
+    /// Find terms that are invalid for the taxon of the model because of
+    /// only_in_taxon or never_in_taxon constraints in the ontology
+    CheckTaxonConstraints {
+        #[arg(long)]
+        closure_file: Option<PathBuf>,
+        #[arg(long, required = true)]
+        ontology_file: Vec<PathBuf>,
+        /// An NCBITaxon closure file in the same format as --closure-file
+        #[arg(long)]
+        taxonomy_closure_file: PathBuf,
         #[arg(long)]
         orcid_map_file: PathBuf,
         paths: Vec<PathBuf>,
     },
     #[command(arg_required_else_help = true)]
     WriteAnnotation {
+        #[command(flatten)]
+        annotation_options: AnnotationOptions,
+        /// Output format
+        #[arg(long, value_enum, default_value_t = AnnotationFormat::Gaf)]
+        format: AnnotationFormat,
+        /// Where to write the GPI file of the gene products in the GPAD
+        /// output
+        #[arg(long, required_if_eq("format", "gpad"))]
+        gpi_file: Option<PathBuf>,
+        /// TSV file of gene systematic ID, symbol, name and synonyms
+        #[arg(long)]
+        gene_metadata_file: Option<PathBuf>,
+    },
+    #[command(arg_required_else_help = true)]
+    /// Show the changes to activities, edges, evidence and contributors
+    /// between two versions of a model.  Activities are matched by
+    /// individual ID, or by enabler and MF if the ID changed.
+    Diff {
+        /// Output format
+        #[arg(long, value_enum, default_value_t = DiffFormat::Text)]
+        format: DiffFormat,
+        old_path: PathBuf,
+        new_path: PathBuf,
+    },
+    #[command(arg_required_else_help = true)]
+    /// Write a Markdown changelog of the differences between two
+    /// directories of models: added, removed and retitled models, status
+    /// changes, genes entering or leaving GO-CAM and the changes to the
+    /// model statistics per model and per taxon
+    CorpusDiff {
+        /// Also write the changes to this file as TSV
+        #[arg(long)]
+        tsv_file: Option<PathBuf>,
+        old_dir: PathBuf,
+        new_dir: PathBuf,
+    },
+    #[command(arg_required_else_help = true)]
+    /// Compare the annotations that write-annotation would make with an
+    /// existing GAF or GPAD file and report the missing, duplicate and
+    /// conflicting (NOT) annotations as TSV
+    CompareAnnotations {
+        #[command(flatten)]
+        annotation_options: AnnotationOptions,
+        /// The existing GAF or GPAD 2.0 file
+        #[arg(long)]
+        annotation_file: PathBuf,
+        /// Write the missing annotations to this file as GAF
         #[arg(long)]
-        db_name: String,
+        missing_gaf_file: Option<PathBuf>,
+    },
+    #[command(arg_required_else_help = true)]
+    /// Replace terms in gocam-py YAML files using a TSV file of old ID to
+    /// new ID and/or the replaced_by tags of ontology files
+    ReplaceTerms {
         #[arg(long)]
-        config_file_name: String,
+        mapping_file: Option<PathBuf>,
+        #[arg(long)]
+        ontology_file: Vec<PathBuf>,
+        /// Print a unified diff instead of changing the files
+        #[arg(long)]
+        dry_run: bool,
         paths: Vec<PathBuf>,
-    }
+    },
+    #[command(arg_required_else_help = true)]
+    /// Run all QC checks and write a single report
+    Validate {
+        #[arg(long)]
+        closure_file: Option<PathBuf>,
+        #[arg(long)]
+        ontology_file: Vec<PathBuf>,
+        #[arg(long)]
+        allowed_relations_config_file: Option<PathBuf>,
+        /// An NCBITaxon closure file for checking only_in_taxon and
+        /// never_in_taxon constraints
+        #[arg(long)]
+        taxonomy_closure_file: Option<PathBuf>,
+        /// Output format
+        #[arg(long, value_enum, default_value_t = ValidationFormat::Text)]
+        format: ValidationFormat,
+        paths: Vec<PathBuf>,
+    },
+}
+
+/// The output formats of validate
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum ValidationFormat {
+    Text,
+    Tsv,
+    Json,
+}
+
+/// The output formats of diff
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum DiffFormat {
+    Text,
+    Json,
+}
+
+/// The output formats of write-annotation
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum AnnotationFormat {
+    Gaf,
+    /// GPAD 2.0, with a GPI file of the gene products
+    Gpad,
+}
+
+/// The options for making GO annotations from gocam-py YAML models
+#[derive(clap::Args)]
+struct AnnotationOptions {
+    #[arg(long)]
+    db_name: String,
+    #[arg(long)]
+    config_file_name: String,
+    /// Used to find protein-containing complex terms, which are
+    /// annotated with part_of.  Without it every CC annotation is
+    /// is_active_in.
+    #[arg(long)]
+    closure_file: Option<PathBuf>,
+    /// Report on standard error which rule accepted or rejected each
+    /// evidence item
+    #[arg(long)]
+    explain: bool,
+    /// How to export complex-enabled activities
+    #[arg(long, value_enum, default_value_t = ComplexAnnotationMode::Members)]
+    complex_annotations: ComplexAnnotationMode,
+    /// TSV file of PR IDs of modified proteins and gene systematic IDs
+    #[arg(long)]
+    protein_form_file: Option<PathBuf>,
+    paths: Vec<PathBuf>,
 }
 
 type OrcidNameMap = HashMap<String, String>;
@@ -242,6 +458,30 @@ fn parse_orcid_map(path: &PathBuf) -> Result<OrcidNameMap, std::io::Error> {
     Ok(ret)
 }
 
+fn read_ontology_info(closure_file: Option<&PathBuf>, ontology_files: &[PathBuf])
+    -> Result<OntologyInfo, Box<dyn std::error::Error>>
+{
+    let mut ontology_info = if let Some(closure_file) = closure_file {
+        read_closure_file(closure_file)?
+    } else {
+        OntologyInfo::default()
+    };
+
+    for ontology_file in ontology_files {
+        let terms = if ontology_file.extension().is_some_and(|ext| ext == "json") {
+            let mut file = File::open(ontology_file)?;
+            parse_obographs_json(&mut file)?
+        } else {
+            let file = File::open(ontology_file)?;
+            let mut reader = BufReader::new(file);
+            parse_obo(&mut reader)?
+        };
+        ontology_info.add_terms(terms);
+    }
+
+    Ok(ontology_info)
+}
+
 fn print_tuples(model: &GoCamRawModel) {
     for fact in model.facts() {
         let subject = model.fact_subject(fact);
@@ -413,38 +653,41 @@ fn filter_models_by_org(models: &[GoCamModel], taxon: &str)
         .collect()
 }
 
-fn model_from_path(path: &PathBuf) -> GoCamModel {
-    let mut source = File::open(path).unwrap();
-    if path.extension().unwrap() == "json" {
-        parse_raw_gocam_model(&mut source).unwrap()
-    } else {
-        let res = parse_gocam_py_model(&mut source);
+// the models are merged if there is more than one path, separated by
+// "+"; None if none of them could be loaded and --keep-going is set
+fn model_from_paths(loader: &mut ModelLoader, paths_string: &str)
+    -> Result<Option<GoCamModel>, Box<dyn std::error::Error>>
+{
+    let paths: Vec<PathBuf> = paths_string.split('+').map(PathBuf::from).collect();
+    let models = loader.load_all(&paths)?;
 
-        match res {
-            Ok(model) => model,
-            Err(err) => panic!("{}", err),
-        }
+    if models.len() > 1 {
+        Ok(Some(GoCamModel::merge_models("merged", "merged models", &models,
+                                         GoCamMergeAlgorithm::Activity)?))
+    } else {
+        Ok(models.into_iter().next())
     }
 }
 
-fn models_from_paths(paths: &[PathBuf])
-    -> Vec<GoCamModel>
+// summarise the models in a directory or archive
+fn model_summaries(loader: &mut ModelLoader, dir: &PathBuf)
+    -> Result<Vec<ModelSummary>, Box<dyn std::error::Error>>
 {
-    paths.iter().map(model_from_path).collect()
-}
+    let input_format = loader.input_format();
+    let cache = loader.cache().cloned();
 
-fn model_from_paths(paths_string: &str)
-    -> GoCamModel
-{
-    let paths: Vec<PathBuf> = paths_string.split('+').map(PathBuf::from).collect();
-    let models = models_from_paths(&paths);
+    let sources = loader.sources(std::slice::from_ref(dir))?;
+    let summaries = loader.map_sources(&sources, |source| {
+        let loaded_model = load_model(source, input_format, cache.as_ref())?;
 
-    if models.len() > 1 {
-        GoCamModel::merge_models("merged", "merged models", &models,
-                                 GoCamMergeAlgorithm::Activity).unwrap()
-    } else {
-        models.into_iter().next().unwrap()
-    }
+        // only gocam-py files have a status
+        let status = loaded_model.gocam_py_model
+            .and_then(|gocam_py_model| gocam_py_model.status);
+
+        Ok(ModelSummary::from_model(&loaded_model.model, status))
+    })?;
+
+    Ok(summaries)
 }
 
 fn print_edges(model: &GoCamModel) {
@@ -510,19 +753,23 @@ fn get_contributor_names(model: &GoCamModel, orcid_map: &OrcidNameMap) -> String
 
 type EvidenceConfig = HashMap<String, String>;
 
-fn parse_evidence_config(config_file_name: &str) -> EvidenceConfig {
+fn read_annotation_config(config_file_name: &str) -> Yaml {
     let mut config_file = File::open(config_file_name).expect("can't open config file");
     let mut config_yaml = String::new();
     config_file.read_to_string(&mut config_yaml).expect("can't read config file");
 
     let docs = YamlLoader::load_from_str(&config_yaml).expect("can't read config file");
 
-    let Some(config) = docs.first()
+    let Some(config) = docs.into_iter().next()
     else {
        eprintln!("no config in config file");
        exit(1);
     };
 
+    config
+}
+
+fn parse_evidence_config(config: &Yaml) -> EvidenceConfig {
     let Yaml::Hash(ref evidence_types) = config["evidence_types"]
     else {
         eprintln!("evidence_types is not a map");
@@ -542,91 +789,302 @@ fn parse_evidence_config(config_file_name: &str) -> EvidenceConfig {
     ret
 }
 
+// section_name is only used in error messages
+fn yaml_string_list(section_name: &str, config: &Yaml, key: &str) -> Vec<String> {
+    match config[key] {
+        Yaml::Array(ref values) => {
+            values.iter()
+                .map(|value| {
+                    let Some(value) = value.as_str()
+                    else {
+                        eprintln!("{}.{} should be a list of strings", section_name, key);
+                        exit(1);
+                    };
+                    value.to_owned()
+                })
+                .collect()
+        },
+        Yaml::BadValue | Yaml::Null => vec![],
+        _ => {
+            eprintln!("{}.{} is not a list", section_name, key);
+            exit(1);
+        }
+    }
+}
+
+// the optional annotation_extensions section, eg.
+//   annotation_extensions:
+//     molecular_function: [occurs_in, has_input, causal]
+//     causal_relations: [RO:0002629]
+fn parse_extension_config(config: &Yaml) -> ExtensionConfig {
+    let extensions_config = &config["annotation_extensions"];
+
+    let mut ret = ExtensionConfig::default();
+
+    if extensions_config.is_badvalue() || extensions_config.is_null() {
+        return ret;
+    }
+
+    for (aspect_name, aspect) in [("molecular_function", "F"), ("biological_process", "P"),
+                                  ("cellular_component", "C")] {
+        let slot_names = yaml_string_list("annotation_extensions", extensions_config, aspect_name);
+        let slots = slot_names.iter()
+            .map(|slot_name| {
+                slot_name.parse::<ExtensionSlot>()
+                    .unwrap_or_else(|err| {
+                        eprintln!("{}", err);
+                        exit(1);
+                    })
+            })
+            .collect();
+        ret.slots.insert(aspect.to_owned(), slots);
+    }
+
+    if !extensions_config["causal_relations"].is_badvalue() {
+        let causal_relations = yaml_string_list("annotation_extensions", extensions_config,
+                                                "causal_relations");
+        ret.causal_relations = Some(causal_relations.into_iter().collect());
+    }
+
+    ret
+}
+
+// lists missing from rules_config are copied from defaults
+fn parse_evidence_rules(section_name: &str, rules_config: &Yaml, defaults: &EvidenceRules)
+    -> EvidenceRules
+{
+    let list_or_default = |key: &str, default: &[String]| {
+        if rules_config[key].is_badvalue() {
+            default.to_vec()
+        } else {
+            yaml_string_list(section_name, rules_config, key)
+        }
+    };
+
+    EvidenceRules {
+        allow_eco_codes: list_or_default("allow_eco_codes", &defaults.allow_eco_codes),
+        deny_eco_codes: list_or_default("deny_eco_codes", &defaults.deny_eco_codes),
+        allow_reference_prefixes: list_or_default("allow_reference_prefixes",
+                                                  &defaults.allow_reference_prefixes),
+        deny_reference_prefixes: list_or_default("deny_reference_prefixes",
+                                                 &defaults.deny_reference_prefixes),
+    }
+}
+
+// the optional evidence_export section, eg.
+//   evidence_export:
+//     deny_eco_codes: [ECO:0000307]
+//     allow_reference_prefixes: ["GO_REF:"]
+//     aspects:
+//       biological_process:
+//         allow_reference_prefixes: ["GO_REF:", "PMID:"]
+// Without it, or for lists that it doesn't have, ExportPolicy::default()
+// is used.
+fn parse_export_policy(config: &Yaml) -> ExportPolicy {
+    let export_config = &config["evidence_export"];
+
+    if export_config.is_badvalue() || export_config.is_null() {
+        return ExportPolicy::default();
+    }
+
+    // lists that aren't in the section keep their defaults
+    let default_rules = parse_evidence_rules("evidence_export", export_config,
+                                             &ExportPolicy::default().default_rules);
+
+    let mut aspect_rules = HashMap::new();
+
+    for (aspect_name, aspect) in [("molecular_function", "F"), ("biological_process", "P"),
+                                  ("cellular_component", "C")] {
+        let rules_config = &export_config["aspects"][aspect_name];
+        if rules_config.is_badvalue() {
+            continue;
+        }
+        let section_name = format!("evidence_export.aspects.{}", aspect_name);
+        aspect_rules.insert(aspect.to_owned(),
+                            parse_evidence_rules(&section_name, rules_config, &default_rules));
+    }
+
+    ExportPolicy {
+        default_rules,
+        aspect_rules,
+    }
+}
+
+fn make_annotations(loader: &mut ModelLoader, options: &AnnotationOptions,
+                    ontology_info: Option<&OntologyInfo>)
+    -> Result<Vec<GoAnnotation>, Box<dyn std::error::Error>>
+{
+    if ontology_info.is_none() {
+        eprintln!("no --closure-file: all CC annotations will use is_active_in");
+    }
+
+    let config = read_annotation_config(&options.config_file_name);
+    let evidence_code_map = parse_evidence_config(&config);
+    let extension_config = parse_extension_config(&config);
+    let export_policy = parse_export_policy(&config);
+
+    let protein_forms = if let Some(ref protein_form_file) = options.protein_form_file {
+        let file = File::open(protein_form_file)?;
+        let mut reader = BufReader::new(file);
+        Some(parse_protein_forms(&mut reader)?)
+    } else {
+        None
+    };
+
+    let annotation_config = AnnotationConfig {
+        db_name: &options.db_name,
+        go_ev_code_map: &evidence_code_map,
+        ontology_info,
+        extension_config: &extension_config,
+        export_policy: &export_policy,
+        explain: options.explain,
+        complex_mode: options.complex_annotations,
+        protein_forms: protein_forms.as_ref(),
+    };
+
+    let input_format = loader.input_format();
+    let cache = loader.cache().cloned();
+    let sources = loader.sources(&options.paths)?;
+    let all_model_annotations = loader.map_sources(&sources, |source| {
+        let gocam_py_model = read_gocam_py_file(source, input_format, cache.as_ref())?;
+        let model_annotations = annotations_from_model(&annotation_config, &gocam_py_model);
+        Ok((gocam_py_model.id, model_annotations))
+    })?;
+
+    let mut annotations = vec![];
+
+    for (model_id, model_annotations) in all_model_annotations {
+        for explanation in &model_annotations.explanations {
+            eprintln!("{}\t{}", model_id, explanation);
+        }
+
+        if model_annotations.skipped_count() > 0 {
+            let reasons = model_annotations.skipped_evidence.iter()
+                .map(|(reason, count)| format!("{} {}", count, reason))
+                .join(", ");
+            eprintln!("{}: skipped {} evidence item(s): {}", model_id,
+                      model_annotations.skipped_count(), reasons);
+        }
+
+        annotations.extend(model_annotations.annotations);
+    }
+
+    Ok(annotations)
+}
+
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let args = Args::parse();
 
+    if let Some(jobs) = args.jobs {
+        rayon::ThreadPoolBuilder::new().num_threads(jobs).build_global()?;
+    }
+
+    let model_cache = args.cache_dir.as_ref().map(|cache_dir| ModelCache::new(cache_dir));
+
+    let mut loader = ModelLoader::new(args.keep_going, args.input_format, model_cache.clone());
+
     match args.action {
         Action::Stats { paths } => {
             println!("model_id\ttaxon\ttotal_genes\ttotal_complexes\t\
                       connected_genes\tmax_connected_activities\t\
                       total_connected_activities\tnumber_of_holes");
-            for path in paths {
-                let model = model_from_path(&path);
-
+            let lines = loader.map_models(&paths, |model| {
                 let stats = get_stats(&model);
 
-                println!("{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}", model.id(), model.taxon(),
-                         stats.total_genes, stats.total_complexes, stats.connected_genes,
-                         stats.max_connected_activities,
-                         stats.total_connected_activities, stats.number_of_holes);
+                format!("{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}", model.id(), model.taxon(),
+                        stats.total_genes, stats.total_complexes, stats.connected_genes,
+                        stats.max_connected_activities,
+                        stats.total_connected_activities, stats.number_of_holes)
+            })?;
+
+            for line in lines {
+                println!("{}", line);
             }
         }
         Action::ConnectedGenes { paths } => {
             println!("taxon\tgene");
             let mut seen_genes = HashSet::new();
 
-            for path in paths {
-                let model = model_from_path(&path);
-
+            let model_genes = loader.map_models(&paths, |model| {
                 let connected_genes_by_activity_count =
                     get_connected_genes(&model);
-                if let Some(connected_genes) = connected_genes_by_activity_count.get(&2) {
-                    for gene in connected_genes {
-                        if seen_genes.contains(&(model.taxon().to_owned(), gene.to_owned())) {
-                            continue;
-                        } else {
-                            seen_genes.insert((model.taxon().to_owned(), gene.to_owned()));
-                        }
-                        println!("{}\t{gene}", model.taxon());
+                let connected_genes = connected_genes_by_activity_count.get(&2)
+                    .map(|genes| genes.iter().map(|gene| gene.to_string()).collect())
+                    .unwrap_or_else(Vec::new);
+                (model.taxon().to_owned(), connected_genes)
+            })?;
+
+            for (taxon, connected_genes) in model_genes {
+                for gene in connected_genes {
+                    if seen_genes.contains(&(taxon.clone(), gene.clone())) {
+                        continue;
+                    } else {
+                        seen_genes.insert((taxon.clone(), gene.clone()));
                     }
+                    println!("{}\t{gene}", taxon);
                 }
             }
         }
         Action::AllGenes { paths } => {
             println!("taxon\tgene");
-            for path in paths {
-                let model = model_from_path(&path);
-
+            let lines = loader.map_models(&paths, |model| {
                 let connected_genes_by_activity_count =
                     get_connected_genes(&model);
-                if let Some(connected_genes) = connected_genes_by_activity_count.get(&1) {
-                    for gene in connected_genes {
-                        println!("{}\t{gene}", model.taxon());
-                    }
-                }
+                connected_genes_by_activity_count.get(&1)
+                    .map(|connected_genes| {
+                        connected_genes.iter()
+                            .map(|gene| format!("{}\t{gene}", model.taxon()))
+                            .collect()
+                    })
+                    .unwrap_or_else(Vec::new)
+            })?;
+
+            for line in lines.into_iter().flatten() {
+                println!("{}", line);
             }
         }
         Action::GenesEnablingActivities { paths } => {
-            for path in paths {
-                let model = model_from_path(&path);
-
+            let model_gene_ids = loader.map_models(&paths, |model| {
                 let genes = model.genes_enabling_activities();
+                genes.keys().map(|gene_id| gene_id.to_string()).collect::<Vec<_>>()
+            })?;
 
-                for gene_id in genes.keys() {
-                    println!("{gene_id}");
-                }
+            for gene_id in model_gene_ids.into_iter().flatten() {
+                println!("{gene_id}");
             }
         }
         Action::PrintTuples { paths } => {
             println!("model_id\tmodel_title\ttaxon\tsubject_id\tsubject_label\tsubject_type_id\trelation\tobject_id\tobject_label\tobject_type_id");
 
-            for path in paths {
-                let mut source = File::open(path).unwrap();
-                let model = gocam_parse_raw(&mut source)?;
+            let input_format = loader.input_format();
+            let sources = loader.sources(&paths)?;
+            let models = loader.map_sources(&sources, |source| {
+                read_raw_model_file(source, input_format)
+            })?;
+
+            for model in models {
                 print_tuples(&model);
             }
         },
         Action::PrintIndividuals { paths } => {
-            for path in paths {
-                let mut source = File::open(path).unwrap();
-                let model = gocam_parse_raw(&mut source)?;
+            let input_format = loader.input_format();
+            let sources = loader.sources(&paths)?;
+            let models = loader.map_sources(&sources, |source| {
+                read_raw_model_file(source, input_format)
+            })?;
+
+            for model in models {
                 print_individuals(&model);
             }
         },
         Action::PrintUnconnectedIndividuals { paths } => {
-            for path in paths {
-                let mut source = File::open(path).unwrap();
-                let model = gocam_parse_raw(&mut source)?;
+            let input_format = loader.input_format();
+            let sources = loader.sources(&paths)?;
+            let models = loader.map_sources(&sources, |source| {
+                read_raw_model_file(source, input_format)
+            })?;
+
+            for model in models {
                 print_unconnected_individuals(&model);
             }
         }
@@ -652,7 +1110,9 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             for arg in args {
 
                 let model = {
-                    let model = model_from_paths(&arg);
+                    let Some(model) = model_from_paths(&mut loader, &arg)? else {
+                        continue;
+                    };
 
                     let mut remove_types = HashSet::new();
 
@@ -702,7 +1162,9 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
             for arg in args {
 
-                let model = model_from_paths(&arg);
+                let Some(model) = model_from_paths(&mut loader, &arg)? else {
+                    continue;
+                };
 
                 print_edges(&model);
             }
@@ -710,25 +1172,28 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
         Action::FindHoles { paths } => {
             println!("model_id\tmodel_title\ttaxon\toriginal_model_id\tindividual_gocam_id\tnode_id\tnode_label\tnode_type\tenabled_by_type\tenabled_by_id\tenabled_by_label\tprocess\tinput\toutput\toccurs_in\tlocated_in\thappens_during\tparts");
-            for path in paths {
-                let model = model_from_path(&path);
-
+            let lines = loader.map_models(&paths, |model| {
                 let model_id = model.id();
                 let model_title = model.title();
                 let model_taxon = model.taxon();
 
                 let hole_nodes = find_holes(&model);
 
-                for hole_node in hole_nodes {
-                    println!("{}\t{}\t{}\t{}", model_id, model_title, model_taxon,
-                             node_as_tsv(&hole_node, true));
+                hole_nodes.iter()
+                    .map(|hole_node| {
+                        format!("{}\t{}\t{}\t{}", model_id, model_title, model_taxon,
+                                node_as_tsv(hole_node, true))
+                    })
+                    .collect::<Vec<_>>()
+            })?;
 
-                }
+            for line in lines.into_iter().flatten() {
+                println!("{}", line);
             }
         },
         Action::Cytoscape { path } => {
-            let mut source = File::open(path).unwrap();
-            let model = gocam_parse_raw(&mut source)?;
+            let model = read_raw_model_file(&ModelSource::from(path.as_path()),
+                                            loader.input_format())?;
 
             let elements = model_to_cytoscape(&model);
             let elements_string = serde_json::to_string(&elements).unwrap();
@@ -736,7 +1201,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             println!("{}", elements_string);
         }
         Action::CytoscapeSimple { path } => {
-            let model = model_from_path(&path);
+            let model = loader.read(&path)?;
 
             let elements = model_to_cytoscape_simple(&model, &vec![],
                                                      GoCamCytoscapeStyle::IncludeParents);
@@ -748,9 +1213,9 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             let models: Vec<_> =
                 if let Some(taxon_id) = taxon_id {
                     let taxon_id = taxon_id.strip_prefix("NCBITaxon:").unwrap_or(&taxon_id);
-                    filter_models_by_org(&models_from_paths(&paths), taxon_id)
+                    filter_models_by_org(&loader.load_all(&paths)?, taxon_id)
                 } else {
-                    models_from_paths(&paths)
+                    loader.load_all(&paths)?
                 }
                 .into_iter().filter(has_connected_genes).collect();
             let merged = GoCamModel::merge_models("merged", "merged models", &models,
@@ -762,13 +1227,13 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             println!("{}", elements_string);
         },
         Action::CytoscapeModelConnections { taxon_id, paths } => {
-            let all_models = models_from_paths(&paths);
+            let all_models = loader.load_all(&paths)?;
             let models: Vec<_> =
                 if let Some(taxon_id) = taxon_id {
                     let taxon_id = taxon_id.strip_prefix("NCBITaxon:").unwrap_or(&taxon_id);
                     filter_models_by_org(&all_models, taxon_id)
                 } else {
-                    models_from_paths(&paths)
+                    all_models.clone()
                 }
                 .into_iter().filter(has_connected_genes).collect();
 
@@ -785,7 +1250,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             println!("{}", elements_string);
         },
         Action::CytoscapeModelConnectionsWithRelNodes { paths } => {
-            let models = models_from_paths(&paths);
+            let models = loader.load_all(&paths)?;
 
             let elements = model_pathways_to_cytoscope_test(&models);
 
@@ -794,7 +1259,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             println!("{}", elements_string);
         },
         Action::GraphVizDot { path } => {
-            let model = model_from_path(&path);
+            let model = loader.read(&path)?;
 
             let dag_graphviz = Dot::with_attr_getters(
                 model.graph(),
@@ -817,9 +1282,10 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
             println!("model_id\tgene_internal_id\tgene_systematic_id\tgene_name");
 
-            for path in paths {
-                let mut source = File::open(path).unwrap();
-                let model = gocam_parse_raw(&mut source)?;
+            let input_format = loader.input_format();
+            let sources = loader.sources(&paths)?;
+            let lines = loader.map_sources(&sources, |source| {
+                let model = read_raw_model_file(source, input_format)?;
                 let parsed_model = GoCamModel::new_from_raw(model.clone());
 
                 let contributor_names = get_contributor_names(&parsed_model, &orcid_map);
@@ -827,9 +1293,15 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
                 let detached_genes = find_detached_genes(&model);
 
-                for (id, gene_id, gene_label) in detached_genes {
-                    println!("{}\t{}\t{}\t{}", model_id, id, gene_id, gene_label);
-                }
+                Ok(detached_genes.into_iter()
+                   .map(|(id, gene_id, gene_label)| {
+                       format!("{}\t{}\t{}\t{}", model_id, id, gene_id, gene_label)
+                   })
+                   .collect::<Vec<_>>())
+            })?;
+
+            for line in lines.into_iter().flatten() {
+                println!("{}", line);
             }
         },
         Action::DetachedChemicals { paths, orcid_map_file } => {
@@ -837,7 +1309,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
             println!("model_id\tmodel_title\tchebi_id\tname");
 
-            let models = models_from_paths(&paths);
+            let models = loader.load_all(&paths)?;
 
             for model in models {
                 let contributor_names = get_contributor_names(&model, &orcid_map);
@@ -851,8 +1323,21 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 }
             }
         },
+        Action::Cache { action: CacheAction::Gc { max_age_days } } => {
+            let Some(model_cache) = model_cache
+            else {
+                eprintln!("cache gc needs --cache-dir");
+                exit(1);
+            };
+
+            let max_age = std::time::Duration::from_secs(max_age_days * 24 * 60 * 60);
+            let stats = model_cache::gc(&model_cache, max_age)?;
+
+            println!("removed {} cache entries ({} bytes), kept {}", stats.removed_entries,
+                     stats.removed_bytes, stats.kept_entries);
+        },
         Action::Serialize { paths } => {
-            let models = models_from_paths(&paths);
+            let models = loader.load_all(&paths)?;
 
             let models_string = serde_json::to_string(&models).unwrap();
 
@@ -867,7 +1352,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 remove_types.insert(RemoveType::Targets);
             }
 
-            let models: Vec<_> = models_from_paths(&paths).into_iter()
+            let models: Vec<_> = loader.load_all(&paths)?.into_iter()
                 .map(|m|
                      if remove_types.is_empty() {
                          m
@@ -930,7 +1415,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             }
         },
         Action::MakeChadoData { paths } => {
-            let models = models_from_paths(&paths);
+            let models = loader.load_all(&paths)?;
 
             let data_for_chado = make_chado_data(&models);
 
@@ -939,14 +1424,20 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             println!("{}", chado_string);
         },
         Action::GocamPyParseTest { paths } => {
-            for path in paths {
-                let mut source = File::open(path).unwrap();
-                let gocam_py_model = gocam_py_parse(&mut source)?;
-                println!("id: {}", gocam_py_model.id);
+            let input_format = loader.input_format();
+            let cache = loader.cache().cloned();
+            let sources = loader.sources(&paths)?;
+            let model_ids = loader.map_sources(&sources, |source| {
+                read_gocam_py_file(source, input_format, cache.as_ref())
+                    .map(|gocam_py_model| gocam_py_model.id)
+            })?;
+
+            for model_id in model_ids {
+                println!("id: {}", model_id);
             }
         },
         Action::JoiningChemicals { paths } => {
-            let models = models_from_paths(&paths);
+            let models = loader.load_all(&paths)?;
 
             let overlaps = find_chemical_overlaps(&models);
 
@@ -1008,10 +1499,16 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                       activity_term_id\tactivity_term_name\t\
                       part_of_term_id\tpart_of_term_name\t\
                       occurs_id_term_id\toccurs_id_term_name");
-            for path in paths {
-                let mut source = File::open(path)?;
-                let gocam_py_model = gocam_py_parse(&mut source)?;
+            let input_format = loader.input_format();
+            let cache = loader.cache().cloned();
+            let sources = loader.sources(&paths)?;
+            let models_and_missing = loader.map_sources(&sources, |source| {
+                let gocam_py_model = read_gocam_py_file(source, input_format, cache.as_ref())?;
                 let missing = find_missing_evidence(missing_type, &gocam_py_model);
+                Ok((gocam_py_model, missing))
+            })?;
+
+            for (gocam_py_model, missing) in models_and_missing {
                 print_missing(&gocam_py_model, &orcid_map, &missing);
             }
         },
@@ -1034,18 +1531,22 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                       activity_term_id\tactivity_term_name\t\
                       part_of_term_id\tpart_of_term_name\t\
                       occurs_id_term_id\toccurs_id_term_name");
-            for path in paths {
-                let mut source = File::open(path)?;
-                let gocam_py_model = gocam_py_parse(&mut source)?;
+            let input_format = loader.input_format();
+            let cache = loader.cache().cloned();
+            let sources = loader.sources(&paths)?;
+            let models_and_missing = loader.map_sources(&sources, |source| {
+                let gocam_py_model = read_gocam_py_file(source, input_format, cache.as_ref())?;
                 let missing = find_missing(missing_type, &gocam_py_model);
+                Ok((gocam_py_model, missing))
+            })?;
+
+            for (gocam_py_model, missing) in models_and_missing {
                 print_missing(&gocam_py_model, &orcid_map, &missing);
             }
         },
         Action::CheckAllowedRelations { closure_file, orcid_map_file,
                                         allowed_relations_config_file, paths } => {
-            let closure_file = File::open(closure_file)?;
-            let mut closure_reader = BufReader::new(closure_file);
-            let ontology_info = parse_closure(&mut closure_reader)?;
+            let ontology_info = read_closure_file(&closure_file)?;
 
             let allowed_relations_config_file = File::open(allowed_relations_config_file)?;
             let mut config_reader = BufReader::new(allowed_relations_config_file);
@@ -1053,49 +1554,388 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
             let orcid_map = parse_orcid_map(&orcid_map_file)?;
 
-            for path in paths {
-                let model = model_from_path(&path);
-
+            let lines = loader.map_models(&paths, |model| {
                 let warnings = check_relations(&model, &config, &ontology_info);
 
                 let contributor_names = get_contributor_names(&model, &orcid_map);
 
-                for warning in warnings {
-                    println!("{} ({}): {}", model.id(), contributor_names, warning);
-                }
+                warnings.iter()
+                    .map(|warning| format!("{} ({}): {}", model.id(), contributor_names, warning))
+                    .collect::<Vec<_>>()
+            })?;
+
+            for line in lines.into_iter().flatten() {
+                println!("{}", line);
+            }
+        },
+        Action::FindObsoleteTerms { closure_file, ontology_file, orcid_map_file, paths } => {
+            if closure_file.is_none() && ontology_file.is_empty() {
+                eprintln!("needs --closure-file or at least one --ontology-file");
+                exit(1);
+            }
+
+            let ontology_info = read_ontology_info(closure_file.as_ref(), &ontology_file)?;
+
+            let orcid_map = parse_orcid_map(&orcid_map_file)?;
+
+            let input_format = loader.input_format();
+            let cache = loader.cache().cloned();
+            let sources = loader.sources(&paths)?;
+            let lines = loader.map_sources(&sources, |source| {
+                let loaded_model = load_model(source, input_format, cache.as_ref())?;
+                let model = &loaded_model.model;
+                let contributor_names = get_contributor_names(model, &orcid_map);
+
+                let warnings = find_obsolete_terms_in_loaded_model(&loaded_model, &ontology_info);
+
+                Ok(warnings.iter()
+                   .map(|warning| format!("{} ({}): {}", model.id(), contributor_names, warning))
+                   .collect::<Vec<_>>())
+            })?;
+
+            for line in lines.into_iter().flatten() {
+                println!("{}", line);
             }
         },
-        Action::FindObsoleteTerms { closure_file, orcid_map_file, paths } => {
-            let closure_file = File::open(closure_file)?;
-            let mut closure_reader = BufReader::new(closure_file);
-            let ontology_info = parse_closure(&mut closure_reader)?;
+        Action::CheckAspects { ontology_file, orcid_map_file, paths } => {
+            let ontology_info = read_ontology_info(None, &ontology_file)?;
 
             let orcid_map = parse_orcid_map(&orcid_map_file)?;
 
-            for path in paths {
-                let model = model_from_path(&path);
-                let contributor_names = get_contributor_names(&model, &orcid_map);
+            let input_format = loader.input_format();
+            let cache = loader.cache().cloned();
+            let sources = loader.sources(&paths)?;
+            let lines = loader.map_sources(&sources, |source| {
+                let loaded_model = load_model(source, input_format, cache.as_ref())?;
+                let model = &loaded_model.model;
+                let contributor_names = get_contributor_names(model, &orcid_map);
+
+                let mismatches = find_aspect_mismatches_in_loaded_model(&loaded_model, &ontology_info);
+
+                Ok(mismatches.iter()
+                   .map(|mismatch| {
+                       format!("{} ({}): {}: {}", model.id(), contributor_names,
+                               mismatch.individual_ids.join(","), mismatch)
+                   })
+                   .collect::<Vec<_>>())
+            })?;
+
+            for line in lines.into_iter().flatten() {
+                println!("{}", line);
+            }
+        },
+        Action::CheckTaxonConstraints { closure_file, ontology_file, taxonomy_closure_file,
+                                        orcid_map_file, paths } => {
+            let ontology_info = read_ontology_info(closure_file.as_ref(), &ontology_file)?;
+            let taxonomy = read_ontology_info(Some(&taxonomy_closure_file), &[])?;
+
+            let orcid_map = parse_orcid_map(&orcid_map_file)?;
+
+            let input_format = loader.input_format();
+            let cache = loader.cache().cloned();
+            let sources = loader.sources(&paths)?;
+            let lines = loader.map_sources(&sources, |source| {
+                let loaded_model = load_model(source, input_format, cache.as_ref())?;
+                let model = &loaded_model.model;
+                let contributor_names = get_contributor_names(model, &orcid_map);
+
+                let violations =
+                    find_taxon_violations_in_loaded_model(&loaded_model, &ontology_info, &taxonomy);
+
+                Ok(violations.iter()
+                   .map(|violation| {
+                       format!("{} ({}): {}: {}", model.id(), contributor_names,
+                               violation.individual_ids.join(","), violation)
+                   })
+                   .collect::<Vec<_>>())
+            })?;
+
+            for line in lines.into_iter().flatten() {
+                println!("{}", line);
+            }
+        },
+        Action::WriteAnnotation { annotation_options, format, gpi_file,
+                                  gene_metadata_file } => {
+            let db_name = &annotation_options.db_name;
+
+            let gene_metadata = if let Some(gene_metadata_file) = gene_metadata_file {
+                let file = File::open(gene_metadata_file)?;
+                let mut reader = BufReader::new(file);
+                Some(parse_gene_metadata(&mut reader)?)
+            } else {
+                None
+            };
+
+            let ontology_info = annotation_options.closure_file.as_ref()
+                .map(|closure_file| read_closure_file(closure_file))
+                .transpose()?;
+
+            let annotations =
+                make_annotations(&mut loader, &annotation_options, ontology_info.as_ref())?;
+
+            match format {
+                AnnotationFormat::Gaf =>
+                    write_go_annotation_file(&mut stdout(), db_name, gene_metadata.as_ref(),
+                                             &annotations)?,
+                AnnotationFormat::Gpad => {
+                    write_gpad_file(&mut stdout(), db_name, &annotations)?;
+                    // clap makes --gpi-file required with --format gpad
+                    if let Some(gpi_file) = gpi_file {
+                        let mut gpi_writer = File::create(gpi_file)?;
+                        write_gpi_file(&mut gpi_writer, db_name, gene_metadata.as_ref(),
+                                       &annotations)?;
+                    }
+                },
+            }
+        },
+        Action::Diff { format, old_path, new_path } => {
+            // each file is read and parsed once.  Only gocam-py files have
+            // evidence, so it's only compared if both files are gocam-py.
+            let read_diff_model = |path: &PathBuf| {
+                let source = ModelSource::from(path.as_path());
+                load_model(&source, loader.input_format(), loader.cache())
+                    .map(|loaded_model| {
+                        let evidence = loaded_model.gocam_py_model.as_ref().map(activity_evidence);
+                        (loaded_model.model, evidence)
+                    })
+            };
+
+            let (old_model, old_evidence) = read_diff_model(&old_path)?;
+            let (new_model, new_evidence) = read_diff_model(&new_path)?;
+
+            let diff = diff_models(&old_model, old_evidence.as_ref(),
+                                   &new_model, new_evidence.as_ref());
+
+            match format {
+                DiffFormat::Text => print!("{}", diff),
+                DiffFormat::Json => println!("{}", serde_json::to_string_pretty(&diff)?),
+            }
+        },
+        Action::CorpusDiff { tsv_file, old_dir, new_dir } => {
+            // a model that failed to load would be reported as added or
+            // removed
+            if args.keep_going {
+                eprintln!("--keep-going can't be used with corpus-diff");
+                exit(1);
+            }
+
+            let old_models = model_summaries(&mut loader, &old_dir)?;
+            let new_models = model_summaries(&mut loader, &new_dir)?;
+
+            let diff = diff_corpora(&old_models, &new_models);
+
+            write_markdown(&mut stdout(), &diff)?;
 
-                let warnings = find_obsolete_terms(&model, &ontology_info);
+            if let Some(tsv_file) = tsv_file {
+                write_tsv(&mut File::create(tsv_file)?, &diff)?;
+            }
+        },
+        Action::CompareAnnotations { annotation_options, annotation_file,
+                                     missing_gaf_file } => {
+            let ontology_info = annotation_options.closure_file.as_ref()
+                .map(|closure_file| read_closure_file(closure_file))
+                .transpose()?;
+
+            let annotations = make_annotations(&mut loader, &annotation_options, ontology_info.as_ref())?;
+
+            let file = File::open(annotation_file)?;
+            let existing_annotations = parse_annotation_file(&mut BufReader::new(file))?;
+
+            let comparisons = compare_annotations(&annotations, &existing_annotations,
+                                                  ontology_info.as_ref());
+
+            println!("{}", COMPARISON_TSV_HEADER);
+
+            let mut status_counts = BTreeMap::new();
+
+            for comparison in &comparisons {
+                println!("{}", comparison.to_tsv());
+                *status_counts.entry(comparison.status).or_insert(0) += 1;
+            }
+
+            eprintln!("{}", status_counts.iter()
+                      .map(|(status, count)| format!("{}: {}", status, count))
+                      .join(", "));
+
+            if let Some(missing_gaf_file) = missing_gaf_file {
+                let missing: Vec<_> = comparisons.iter()
+                    .filter(|comparison| comparison.status == ComparisonStatus::Missing)
+                    .map(|comparison| comparison.annotation.clone())
+                    .collect();
+                let mut writer = File::create(missing_gaf_file)?;
+                write_go_annotation_file(&mut writer, &annotation_options.db_name, None,
+                                         &missing)?;
+            }
+        },
+        Action::ReplaceTerms { mapping_file, ontology_file, dry_run, paths } => {
+            if mapping_file.is_none() && ontology_file.is_empty() {
+                eprintln!("needs --mapping-file or at least one --ontology-file");
+                exit(1);
+            }
+
+            let ontology_info = read_ontology_info(None, &ontology_file)?;
+
+            let mut replacements: TermReplacements = replacements_from_ontology(&ontology_info);
+
+            if let Some(mapping_file) = mapping_file {
+                let mapping_file = File::open(mapping_file)?;
+                let mut mapping_reader = BufReader::new(mapping_file);
+                // the mapping file takes priority over the ontology
+                replacements.extend(parse_term_mapping(&mut mapping_reader)?);
+            }
+
+            let new_term_names: HashMap<_, _> = ontology_info.terms.values()
+                .filter_map(|term_info| {
+                    term_info.name.as_ref()
+                        .map(|name| (term_info.id.clone(), name.clone()))
+                })
+                .collect();
+
+            for source in loader.sources(&paths)? {
+                // the files are rewritten in place
+                let Some(path) = source.file_path()
+                else {
+                    eprintln!("{}: skipping, only files can be rewritten", source.name().display());
+                    continue;
+                };
+
+                let contents = match source.read() {
+                    Ok(contents) => contents,
+                    Err(err) => {
+                        eprintln!("skipping {}", err);
+                        continue;
+                    }
+                };
+
+                if is_compressed(&contents) {
+                    eprintln!("{}: skipping, compressed files can't be rewritten",
+                              path.display());
+                    continue;
+                }
+
+                let format = loader.input_format().unwrap_or_else(|| sniff_format(&contents));
+
+                if format != InputFormat::GocamPyYaml {
+                    eprintln!("{}: skipping, only gocam-py YAML files are supported, not {}",
+                              path.display(), format);
+                    continue;
+                }
+
+                let text = match String::from_utf8(contents.into_owned()) {
+                    Ok(text) => text,
+                    Err(err) => {
+                        eprintln!("{}: skipping, {}", path.display(), err);
+                        continue;
+                    }
+                };
+
+                let (new_text, changes) =
+                    match replace_terms_in_model(&text, &replacements, &new_term_names) {
+                        Ok(result) => result,
+                        Err(err) => {
+                            eprintln!("{}: {}", path.display(), err);
+                            continue;
+                        }
+                    };
+
+                if changes.is_empty() {
+                    continue;
+                }
+
+                for change in &changes {
+                    eprintln!("{}:{}: {} -> {}", path.display(), change.line_number,
+                              change.old_term_id, change.new_term_id);
+                }
 
-                for (term_id, term_name, cv_name, _detail) in warnings {
-                    println!("{} ({}): {} {} {}", model.id(), contributor_names,
-                             cv_name, term_id, term_name);
+                if dry_run {
+                    print!("{}", unified_diff(&path.display().to_string(), &text, &new_text));
+                } else if let Err(err) = std::fs::write(path, new_text) {
+                    eprintln!("{}: {}", path.display(), err);
                 }
             }
         },
-        Action::WriteAnnotation { db_name, config_file_name, paths } => {
-            let evidence_code_map = parse_evidence_config(&config_file_name);
+        Action::Validate { closure_file, ontology_file, allowed_relations_config_file,
+                           taxonomy_closure_file, format, paths } => {
+            if closure_file.is_none() {
+                eprintln!("no --closure-file: skipping allowed relation check");
+            }
 
-            for path in paths {
-                let mut source = File::open(path)?;
-                let gocam_py_model = gocam_py_parse(&mut source)?;
+            let ontology_info = if closure_file.is_some() || !ontology_file.is_empty() {
+                Some(read_ontology_info(closure_file.as_ref(), &ontology_file)?)
+            } else {
+                eprintln!("no --closure-file or --ontology-file: skipping obsolete term check");
+                None
+            };
+
+            let allowed_rel_config = if let Some(config_file) = allowed_relations_config_file {
+                let config_file = File::open(config_file)?;
+                let mut config_reader = BufReader::new(config_file);
+                Some(parse_allowed_relations_config(&mut config_reader)?)
+            } else {
+                eprintln!("no --allowed-relations-config-file: skipping allowed relation check");
+                None
+            };
 
-                write_go_annotation_file(&mut stdout(), &evidence_code_map, &gocam_py_model, &db_name)?
+            let taxonomy = if let Some(taxonomy_closure_file) = taxonomy_closure_file {
+                Some(read_ontology_info(Some(&taxonomy_closure_file), &[])?)
+            } else {
+                eprintln!("no --taxonomy-closure-file: skipping taxon constraint check");
+                None
+            };
+
+            let validation_config = ValidationConfig {
+                ontology_info: ontology_info.as_ref(),
+                allowed_rel_config: allowed_rel_config.as_ref(),
+                taxonomy: taxonomy.as_ref(),
+            };
+
+            let input_format = loader.input_format();
+            let cache = loader.cache().cloned();
+            let sources = loader.sources(&paths)?;
+            let issues: Vec<_> = loader.map_sources(&sources, |source| {
+                let loaded_model = load_model(source, input_format, cache.as_ref())?;
+                Ok(validate_model(&loaded_model, &validation_config))
+            })?
+            .into_iter().flatten().collect();
+
+            match format {
+                ValidationFormat::Text => validate::write_text(&mut stdout(), &issues)?,
+                ValidationFormat::Tsv => validate::write_tsv(&mut stdout(), &issues)?,
+                ValidationFormat::Json => validate::write_json(&mut stdout(), &issues)?,
             }
-        }
+        },
+    }
+
+    if !loader.failures().is_empty() {
+        loader.write_summary(&mut std::io::stderr())?;
+        exit(LOAD_FAILURE_EXIT_CODE);
     }
 
     Ok(())
 }
 
+#[cfg(test)]
+mod tests {
+    use yaml_rust2::YamlLoader;
+
+    use crate::go_format_writer::ExportPolicy;
+
+    use super::parse_export_policy;
+
+    #[test]
+    fn parse_export_policy_test() {
+        let config_text = "evidence_export:\n  aspects:\n    biological_process:\n      allow_reference_prefixes: [\"GO_REF:\", \"PMID:\"]\n";
+        let config = YamlLoader::load_from_str(config_text).unwrap().remove(0);
+        let policy = parse_export_policy(&config);
+
+        // the top level lists that aren't in the config keep their defaults
+        let defaults = ExportPolicy::default().default_rules;
+        assert_eq!(policy.default_rules, defaults);
+        assert!(!policy.aspect_rules.contains_key("F"));
+
+        let bp_rules = &policy.aspect_rules["P"];
+        assert_eq!(bp_rules.allow_reference_prefixes, vec!["GO_REF:", "PMID:"]);
+        assert_eq!(bp_rules.deny_eco_codes, vec!["ECO:0000307"]);
+        assert_eq!(bp_rules.allow_eco_codes, defaults.allow_eco_codes);
+    }
+}
@@ -0,0 +1,241 @@
+use std::{fs::{self, File, FileTimes}, io::{Read, Write}, path::{Path, PathBuf},
+          sync::atomic::{AtomicUsize, Ordering}, time::{Duration, SystemTime}};
+
+use pombase_gocam::{GoCamModel, gocam_py::GoCamPyModel};
+use serde::{Serialize, de::DeserializeOwned};
+use sha2::{Digest, Sha256};
+
+use crate::model_loader::InputFormat;
+
+// entries from other versions of the tool are never read because the
+// parsing code may have changed
+const CACHE_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+static TEMP_FILE_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// A representation of a model file that can be stored in the cache
+pub(crate) trait CachedModel: Serialize + DeserializeOwned {
+    // keeps the entries for the representations of one file apart
+    const ENTRY_KIND: &'static str;
+}
+
+impl CachedModel for GoCamModel {
+    const ENTRY_KIND: &'static str = "model";
+}
+
+impl CachedModel for GoCamPyModel {
+    const ENTRY_KIND: &'static str = "gocam-py";
+}
+
+/// A directory of parsed models stored as JSON, keyed by the contents of
+/// the model file, the --input-format, the representation and the version
+/// of this tool.  Without an --input-format the format is found from the
+/// contents, so it only needs to be sniffed when the entry is missing.
+#[derive(Clone, Debug)]
+pub(crate) struct ModelCache {
+    dir: PathBuf,
+}
+
+impl ModelCache {
+    pub fn new(dir: &Path) -> ModelCache {
+        ModelCache {
+            dir: dir.to_owned(),
+        }
+    }
+
+    fn version_dir(&self) -> PathBuf {
+        self.dir.join(CACHE_VERSION)
+    }
+
+    // a SHA-256 of the contents so that two different files never share an
+    // entry
+    fn entry_path<T: CachedModel>(&self, input_format: Option<InputFormat>, contents: &[u8])
+        -> PathBuf
+    {
+        let format_name = input_format.map(|format| format.to_string())
+            .unwrap_or_else(|| "auto".to_owned());
+        let file_name = format!("{:x}-{}-{}.json", Sha256::digest(contents), format_name,
+                                T::ENTRY_KIND);
+        self.version_dir().join(file_name)
+    }
+
+    /// The parsed model if this file has been parsed by this version
+    /// before.  A hit updates the modification time of the entry so that
+    /// gc() keeps entries that are in use.
+    pub fn get<T: CachedModel>(&self, input_format: Option<InputFormat>, contents: &[u8])
+        -> Option<T>
+    {
+        let entry_path = self.entry_path::<T>(input_format, contents);
+
+        let mut entry_contents = vec![];
+        File::open(&entry_path).ok()?.read_to_end(&mut entry_contents).ok()?;
+
+        // a truncated or corrupt entry is a miss and will be rewritten
+        let model = serde_json::from_slice(&entry_contents).ok()?;
+
+        // the cache may be read-only
+        let _ = File::options().write(true).open(&entry_path)
+            .and_then(|file| file.set_times(FileTimes::new().set_modified(SystemTime::now())));
+
+        Some(model)
+    }
+
+    /// Store a parsed model.  The entry is written to a temporary file
+    /// and renamed so that other threads and processes never see a
+    /// partial entry.
+    pub fn put<T: CachedModel>(&self, input_format: Option<InputFormat>, contents: &[u8],
+                               model: &T) {
+        let entry_path = self.entry_path::<T>(input_format, contents);
+
+        let temp_count = TEMP_FILE_COUNTER.fetch_add(1, Ordering::Relaxed);
+        let temp_path =
+            entry_path.with_extension(format!("tmp.{}.{}", std::process::id(), temp_count));
+
+        // a read-only cache directory shouldn't stop the run
+        let write_result = fs::create_dir_all(self.version_dir())
+            .and_then(|_| serde_json::to_vec(model).map_err(std::io::Error::other))
+            .and_then(|model_json| File::create(&temp_path)?.write_all(&model_json))
+            .and_then(|_| fs::rename(&temp_path, &entry_path));
+
+        if let Err(err) = write_result {
+            let _ = fs::remove_file(&temp_path);
+            eprintln!("failed to write model cache entry {}: {}", entry_path.display(), err);
+        }
+    }
+}
+
+/// What gc() removed
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) struct CacheGcStats {
+    pub removed_entries: usize,
+    pub removed_bytes: u64,
+    pub kept_entries: usize,
+}
+
+fn remove_dir_with_stats(dir: &Path, stats: &mut CacheGcStats) -> Result<(), std::io::Error> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            remove_dir_with_stats(&entry.path(), stats)?;
+        } else {
+            stats.removed_entries += 1;
+            stats.removed_bytes += entry.metadata()?.len();
+        }
+    }
+
+    fs::remove_dir_all(dir)
+}
+
+/// Remove the entries made by other versions of the tool and the entries
+/// that haven't been used for max_age
+pub(crate) fn gc(cache: &ModelCache, max_age: Duration) -> Result<CacheGcStats, std::io::Error> {
+    let mut stats = CacheGcStats::default();
+
+    if !cache.dir.exists() {
+        return Ok(stats);
+    }
+
+    let current_version_dir = cache.version_dir();
+
+    for entry in fs::read_dir(&cache.dir)? {
+        let path = entry?.path();
+        if path.is_dir() && path != current_version_dir {
+            remove_dir_with_stats(&path, &mut stats)?;
+        }
+    }
+
+    if !current_version_dir.exists() {
+        return Ok(stats);
+    }
+
+    let now = SystemTime::now();
+
+    for entry in fs::read_dir(&current_version_dir)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+
+        let age = now.duration_since(metadata.modified()?).unwrap_or_default();
+
+        if age > max_age {
+            fs::remove_file(entry.path())?;
+            stats.removed_entries += 1;
+            stats.removed_bytes += metadata.len();
+        } else {
+            stats.kept_entries += 1;
+        }
+    }
+
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs::{self, File, FileTimes}, path::PathBuf,
+              time::{Duration, SystemTime}};
+
+    use pombase_gocam::{GoCamModel, gocam_py::{GoCamPyModel, gocam_py_parse}};
+
+    use crate::{model_loader::{InputFormat, read_model_file},
+                model_source::ModelSource};
+
+    use super::{CACHE_VERSION, ModelCache, gc};
+
+    #[test]
+    fn model_cache_test() {
+        let dir = std::env::temp_dir().join(format!("model_cache_test_{}", std::process::id()));
+        let cache = ModelCache::new(&dir);
+
+        let contents = fs::read("tests/data/67ae98b500000055.yaml").unwrap();
+        let format = Some(InputFormat::GocamPyYaml);
+
+        assert!(cache.get::<GoCamModel>(format, &contents).is_none());
+
+        let source = ModelSource::File(PathBuf::from("tests/data/67ae98b500000055.yaml"));
+        let model = read_model_file(&source, None, None).unwrap();
+        cache.put(format, &contents, &model);
+
+        let cached = cache.get::<GoCamModel>(format, &contents).unwrap();
+        assert_eq!(cached.id(), model.id());
+        assert_eq!(cached.node_iterator().count(), model.node_iterator().count());
+
+        // the gocam-py representation has its own entry
+        assert!(cache.get::<GoCamPyModel>(format, &contents).is_none());
+        let py_model = gocam_py_parse(&mut contents.as_slice()).unwrap();
+        cache.put(format, &contents, &py_model);
+        let cached_py_model = cache.get::<GoCamPyModel>(format, &contents).unwrap();
+        assert_eq!(cached_py_model.id, py_model.id);
+        assert_eq!(cached_py_model.activities.len(), py_model.activities.len());
+
+        // different contents or format is a miss
+        let mut changed = contents.clone();
+        changed.push(b'\n');
+        assert!(cache.get::<GoCamModel>(format, &changed).is_none());
+        assert!(cache.get::<GoCamModel>(Some(InputFormat::GocamPyJson), &contents).is_none());
+        assert!(cache.get::<GoCamModel>(None, &contents).is_none());
+
+        // a corrupt entry is a miss
+        let entry_path = cache.entry_path::<GoCamModel>(format, &contents);
+        fs::write(&entry_path, "{").unwrap();
+        assert!(cache.get::<GoCamModel>(format, &contents).is_none());
+        cache.put(format, &contents, &model);
+
+        let old_version_dir = dir.join("0.0.1");
+        fs::create_dir_all(&old_version_dir).unwrap();
+        fs::write(old_version_dir.join("old.json"), "{}").unwrap();
+
+        let unused_path = dir.join(CACHE_VERSION).join("unused.json");
+        fs::write(&unused_path, "{}").unwrap();
+        let long_ago = SystemTime::now() - Duration::from_secs(100 * 24 * 60 * 60);
+        File::options().write(true).open(&unused_path).unwrap()
+            .set_times(FileTimes::new().set_modified(long_ago)).unwrap();
+
+        let stats = gc(&cache, Duration::from_secs(30 * 24 * 60 * 60)).unwrap();
+        assert_eq!(stats.removed_entries, 2);
+        assert_eq!(stats.kept_entries, 2);
+        assert!(!old_version_dir.exists());
+        assert!(!unused_path.exists());
+        assert!(cache.get::<GoCamModel>(format, &contents).is_some());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
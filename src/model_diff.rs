@@ -0,0 +1,530 @@
+use std::{collections::{BTreeMap, BTreeSet, HashMap, HashSet}, fmt::{self, Display}};
+
+use serde_derive::Serialize;
+
+use pombase_gocam::{GoCamActivity, GoCamEnabledBy, GoCamModel, GoCamNode, GoCamNodeType,
+                    gocam_py::{BiologicalProcessAssociation, EvidenceItem, GoCamPyModel}};
+
+/// Evidence strings (eg. "part_of ECO:0000315 PMID:8995275") by activity
+/// individual ID.  GoCamModel doesn't keep evidence, so this comes from
+/// the gocam-py version of a model.
+pub(crate) type ActivityEvidence = HashMap<String, BTreeSet<String>>;
+
+fn evidence_strings<'a>(slot: &'a str, evidence: &'a [EvidenceItem])
+    -> impl Iterator<Item = String> + 'a
+{
+    evidence.iter()
+        .map(move |evidence_item| {
+            format!("{} {} {}", slot, evidence_item.term,
+                    evidence_item.reference.as_deref().unwrap_or_default())
+        })
+}
+
+// The evidence of a process and of the processes it is part of.
+// happens_during has no evidence of its own so it gets the evidence of its
+// process, which makes a change to happens_during show up in the diff.
+fn bp_association_evidence(slot: &str, bp_association: &BiologicalProcessAssociation,
+                           evidence: &mut BTreeSet<String>)
+{
+    evidence.extend(evidence_strings(slot, &bp_association.evidence));
+
+    if let Some(ref happens_during) = bp_association.happens_during {
+        let happens_during_slot = format!("{}.happens_during {}", slot, happens_during);
+        evidence.extend(evidence_strings(&happens_during_slot, &bp_association.evidence));
+    }
+
+    if let Some(ref parent_bp_association) = bp_association.part_of {
+        bp_association_evidence(&format!("{}.part_of", slot), parent_bp_association, evidence);
+    }
+}
+
+pub(crate) fn activity_evidence(model: &GoCamPyModel) -> ActivityEvidence {
+    let mut ret = HashMap::new();
+
+    // molecules are identified by their term because the individual IDs
+    // can change between versions
+    let molecule_terms: HashMap<&str, &str> = model.molecules.iter()
+        .map(|molecule| (molecule.id.as_str(), molecule.term.as_str()))
+        .collect();
+
+    for activity in &model.activities {
+        let mut evidence: BTreeSet<String> =
+            evidence_strings("enabled_by", &activity.enabled_by.evidence).collect();
+
+        if let Some(ref occurs_in) = activity.occurs_in {
+            evidence.extend(evidence_strings("occurs_in", &occurs_in.evidence));
+        }
+
+        if let Some(ref part_of) = activity.part_of {
+            bp_association_evidence("part_of", part_of, &mut evidence);
+        }
+
+        for (slot_name, molecule_associations) in [("has_input", &activity.has_input),
+                                                   ("has_output", &activity.has_output)] {
+            for molecule_association in molecule_associations {
+                let molecule_id = molecule_association.molecule.as_str();
+                let molecule_term =
+                    molecule_terms.get(molecule_id).copied().unwrap_or(molecule_id);
+                let slot = format!("{} {}", slot_name, molecule_term);
+                evidence.extend(evidence_strings(&slot, &molecule_association.evidence));
+            }
+        }
+
+        for causal_association in &activity.causal_associations {
+            let slot = format!("{} {}", causal_association.predicate,
+                               causal_association.downstream_activity);
+            evidence.extend(evidence_strings(&slot, &causal_association.evidence));
+        }
+
+        ret.insert(activity.id.clone(), evidence);
+    }
+
+    ret
+}
+
+fn enabler_id(enabler: &GoCamEnabledBy) -> &str {
+    match enabler {
+        GoCamEnabledBy::Chemical(chem) => chem.id(),
+        GoCamEnabledBy::Gene(gene) => gene.id(),
+        GoCamEnabledBy::ModifiedProtein(prot) => prot.id(),
+        GoCamEnabledBy::Complex(complex) => complex.id(),
+    }
+}
+
+// the parts of an activity that are compared
+struct ActivitySnapshot {
+    individual_id: String,
+    label: String,
+    enabler_id: String,
+    mf_id: String,
+    slots: BTreeMap<&'static str, BTreeSet<String>>,
+}
+
+impl ActivitySnapshot {
+    fn from_node(node: &GoCamNode, evidence: Option<&ActivityEvidence>) -> Option<Self> {
+        let GoCamNodeType::Activity(GoCamActivity { ref enabler, ref inputs, ref outputs, .. }) =
+            node.node_type
+        else {
+            return None;
+        };
+
+        let individual_id = node.individual_gocam_id.to_string();
+        let enabler_id = enabler_id(enabler).to_owned();
+
+        let mut slots = BTreeMap::new();
+
+        slots.insert("enabled_by", BTreeSet::from([enabler_id.clone()]));
+        slots.insert("molecular_function", BTreeSet::from([node.node_id.clone()]));
+        slots.insert("occurs_in",
+                     node.occurs_in.iter().map(|occurs_in| occurs_in.id().to_owned()).collect());
+        slots.insert("part_of_process",
+                     node.part_of_process.iter()
+                     .map(|part_of_process| part_of_process.id().to_owned()).collect());
+        slots.insert("happens_during",
+                     node.happens_during.iter()
+                     .map(|happens_during| happens_during.id().to_owned()).collect());
+        slots.insert("has_input", inputs.iter().map(|input| input.to_string()).collect());
+        slots.insert("has_output", outputs.iter().map(|output| output.to_string()).collect());
+
+        if let Some(evidence) = evidence {
+            slots.insert("evidence",
+                         evidence.get(&individual_id).cloned().unwrap_or_default());
+        }
+
+        Some(ActivitySnapshot {
+            individual_id,
+            label: node.label.clone(),
+            enabler_id,
+            mf_id: node.node_id.clone(),
+            slots,
+        })
+    }
+
+    fn fallback_key(&self) -> (&str, &str) {
+        (&self.enabler_id, &self.mf_id)
+    }
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+pub(crate) struct ActivityRef {
+    pub individual_id: String,
+    pub label: String,
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum MatchedBy {
+    IndividualId,
+    // the individual ID changed but the enabler and MF didn't
+    EnablerAndFunction,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+pub(crate) struct SlotChange {
+    pub slot: String,
+    pub removed: Vec<String>,
+    pub added: Vec<String>,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+pub(crate) struct ActivityChange {
+    pub old_individual_id: String,
+    pub new_individual_id: String,
+    pub label: String,
+    pub matched_by: MatchedBy,
+    pub changes: Vec<SlotChange>,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) struct EdgeSummary {
+    // the individual IDs from the new model where the activity is in both
+    pub subject: String,
+    pub relation: String,
+    pub object: String,
+}
+
+/// The differences between two versions of a model
+#[derive(Serialize, Clone, Debug, Default, PartialEq, Eq)]
+pub(crate) struct ModelDiff {
+    pub old_model_id: String,
+    pub new_model_id: String,
+    pub added_activities: Vec<ActivityRef>,
+    pub removed_activities: Vec<ActivityRef>,
+    pub changed_activities: Vec<ActivityChange>,
+    pub added_edges: Vec<EdgeSummary>,
+    pub removed_edges: Vec<EdgeSummary>,
+    pub added_contributors: Vec<String>,
+    pub removed_contributors: Vec<String>,
+}
+
+impl ModelDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added_activities.is_empty() && self.removed_activities.is_empty() &&
+            self.changed_activities.is_empty() && self.added_edges.is_empty() &&
+            self.removed_edges.is_empty() && self.added_contributors.is_empty() &&
+            self.removed_contributors.is_empty()
+    }
+}
+
+fn set_difference<T: Ord + Clone>(a: &BTreeSet<T>, b: &BTreeSet<T>) -> Vec<T> {
+    a.difference(b).cloned().collect()
+}
+
+fn slot_changes(old: &ActivitySnapshot, new: &ActivitySnapshot) -> Vec<SlotChange> {
+    let empty = BTreeSet::new();
+
+    let slot_names: BTreeSet<_> = old.slots.keys().chain(new.slots.keys()).collect();
+
+    slot_names.into_iter()
+        .filter_map(|slot| {
+            // evidence is only compared if it's available for both models
+            if *slot == "evidence" &&
+                (!old.slots.contains_key(slot) || !new.slots.contains_key(slot))
+            {
+                return None;
+            }
+
+            let old_values = old.slots.get(slot).unwrap_or(&empty);
+            let new_values = new.slots.get(slot).unwrap_or(&empty);
+
+            if old_values == new_values {
+                return None;
+            }
+
+            Some(SlotChange {
+                slot: slot.to_string(),
+                removed: set_difference(old_values, new_values),
+                added: set_difference(new_values, old_values),
+            })
+        })
+        .collect()
+}
+
+fn activity_snapshots(model: &GoCamModel, evidence: Option<&ActivityEvidence>)
+    -> Vec<ActivitySnapshot>
+{
+    model.node_iterator()
+        .filter_map(|(_, node)| ActivitySnapshot::from_node(node, evidence))
+        .collect()
+}
+
+// old individual ID to new individual ID, by ID and then by (enabler, MF)
+// if that pair is unique in the unmatched activities of both models
+fn match_activities(old_activities: &[ActivitySnapshot], new_activities: &[ActivitySnapshot])
+    -> HashMap<String, (String, MatchedBy)>
+{
+    let new_ids: HashSet<&str> = new_activities.iter()
+        .map(|activity| activity.individual_id.as_str())
+        .collect();
+
+    let mut ret = HashMap::new();
+
+    for old_activity in old_activities {
+        if new_ids.contains(old_activity.individual_id.as_str()) {
+            ret.insert(old_activity.individual_id.clone(),
+                       (old_activity.individual_id.clone(), MatchedBy::IndividualId));
+        }
+    }
+
+    let matched_new_ids: HashSet<String> = ret.values().map(|(id, _)| id.clone()).collect();
+
+    let unmatched_by_key = |activities: &[ActivitySnapshot], matched: &dyn Fn(&str) -> bool| {
+        let mut by_key: HashMap<(String, String), Vec<String>> = HashMap::new();
+        for activity in activities {
+            if matched(&activity.individual_id) {
+                continue;
+            }
+            let (enabler_id, mf_id) = activity.fallback_key();
+            by_key.entry((enabler_id.to_owned(), mf_id.to_owned()))
+                .or_default()
+                .push(activity.individual_id.clone());
+        }
+        by_key
+    };
+
+    let old_unmatched = unmatched_by_key(old_activities, &|id| ret.contains_key(id));
+    let new_unmatched = unmatched_by_key(new_activities, &|id| matched_new_ids.contains(id));
+
+    for (key, old_ids) in old_unmatched {
+        if let Some(new_ids) = new_unmatched.get(&key) &&
+            old_ids.len() == 1 && new_ids.len() == 1
+        {
+            ret.insert(old_ids[0].clone(), (new_ids[0].clone(), MatchedBy::EnablerAndFunction));
+        }
+    }
+
+    ret
+}
+
+fn activity_edges(model: &GoCamModel, id_map: &dyn Fn(&str) -> String) -> BTreeSet<EdgeSummary> {
+    model.edge_iterator()
+        .filter_map(|(_, subject_idx, edge, object_idx)| {
+            let subject_node = model.graph().node_weight(subject_idx)?;
+            let object_node = model.graph().node_weight(object_idx)?;
+
+            if !subject_node.is_activity() || !object_node.is_activity() {
+                return None;
+            }
+
+            Some(EdgeSummary {
+                subject: id_map(&subject_node.individual_gocam_id),
+                relation: edge.id.clone(),
+                object: id_map(&object_node.individual_gocam_id),
+            })
+        })
+        .collect()
+}
+
+/// Compare two versions of a model.  Evidence is compared if it's given
+/// for both versions.
+pub(crate) fn diff_models(old_model: &GoCamModel, old_evidence: Option<&ActivityEvidence>,
+                          new_model: &GoCamModel, new_evidence: Option<&ActivityEvidence>)
+    -> ModelDiff
+{
+    let old_activities = activity_snapshots(old_model, old_evidence);
+    let new_activities = activity_snapshots(new_model, new_evidence);
+
+    let matches = match_activities(&old_activities, &new_activities);
+    let matched_new_ids: HashSet<&str> = matches.values().map(|(id, _)| id.as_str()).collect();
+
+    let new_by_id: HashMap<&str, &ActivitySnapshot> = new_activities.iter()
+        .map(|activity| (activity.individual_id.as_str(), activity))
+        .collect();
+
+    let mut diff = ModelDiff {
+        old_model_id: old_model.id().to_owned(),
+        new_model_id: new_model.id().to_owned(),
+        ..Default::default()
+    };
+
+    for old_activity in &old_activities {
+        let Some((new_id, matched_by)) = matches.get(&old_activity.individual_id)
+        else {
+            diff.removed_activities.push(ActivityRef {
+                individual_id: old_activity.individual_id.clone(),
+                label: old_activity.label.clone(),
+            });
+            continue;
+        };
+
+        let new_activity = new_by_id[new_id.as_str()];
+        let changes = slot_changes(old_activity, new_activity);
+
+        if !changes.is_empty() || *matched_by == MatchedBy::EnablerAndFunction {
+            diff.changed_activities.push(ActivityChange {
+                old_individual_id: old_activity.individual_id.clone(),
+                new_individual_id: new_id.clone(),
+                label: new_activity.label.clone(),
+                matched_by: *matched_by,
+                changes,
+            });
+        }
+    }
+
+    for new_activity in &new_activities {
+        if !matched_new_ids.contains(new_activity.individual_id.as_str()) {
+            diff.added_activities.push(ActivityRef {
+                individual_id: new_activity.individual_id.clone(),
+                label: new_activity.label.clone(),
+            });
+        }
+    }
+
+    // edges of the old model use the IDs of the matching new activities
+    let old_edges = activity_edges(old_model, &|id: &str| {
+        matches.get(id).map(|(new_id, _)| new_id.clone()).unwrap_or_else(|| id.to_owned())
+    });
+    let new_edges = activity_edges(new_model, &|id: &str| id.to_owned());
+
+    diff.added_edges = set_difference(&new_edges, &old_edges);
+    diff.removed_edges = set_difference(&old_edges, &new_edges);
+
+    let old_contributors: BTreeSet<String> = old_model.contributors().iter().cloned().collect();
+    let new_contributors: BTreeSet<String> = new_model.contributors().iter().cloned().collect();
+
+    diff.added_contributors = set_difference(&new_contributors, &old_contributors);
+    diff.removed_contributors = set_difference(&old_contributors, &new_contributors);
+
+    diff
+}
+
+impl Display for ModelDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.old_model_id == self.new_model_id {
+            writeln!(f, "{}:", self.new_model_id)?;
+        } else {
+            writeln!(f, "{} -> {}:", self.old_model_id, self.new_model_id)?;
+        }
+
+        if self.is_empty() {
+            return writeln!(f, "  no changes");
+        }
+
+        for activity in &self.removed_activities {
+            writeln!(f, "  - activity {} {}", activity.individual_id, activity.label)?;
+        }
+
+        for activity in &self.added_activities {
+            writeln!(f, "  + activity {} {}", activity.individual_id, activity.label)?;
+        }
+
+        for activity in &self.changed_activities {
+            if activity.matched_by == MatchedBy::EnablerAndFunction {
+                writeln!(f, "  ~ activity {} {} (was {})", activity.new_individual_id,
+                         activity.label, activity.old_individual_id)?;
+            } else {
+                writeln!(f, "  ~ activity {} {}", activity.new_individual_id, activity.label)?;
+            }
+
+            for change in &activity.changes {
+                for value in &change.removed {
+                    writeln!(f, "      - {}: {}", change.slot, value)?;
+                }
+                for value in &change.added {
+                    writeln!(f, "      + {}: {}", change.slot, value)?;
+                }
+            }
+        }
+
+        for edge in &self.removed_edges {
+            writeln!(f, "  - edge {} {} {}", edge.subject, edge.relation, edge.object)?;
+        }
+
+        for edge in &self.added_edges {
+            writeln!(f, "  + edge {} {} {}", edge.subject, edge.relation, edge.object)?;
+        }
+
+        for contributor in &self.removed_contributors {
+            writeln!(f, "  - contributor {}", contributor)?;
+        }
+
+        for contributor in &self.added_contributors {
+            writeln!(f, "  + contributor {}", contributor)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use pombase_gocam::{gocam_py::gocam_py_parse, parse_gocam_py_model};
+
+    use super::{MatchedBy, activity_evidence, diff_models};
+
+    const MODEL_PATH: &str = "tests/data/67ae98b500000055.yaml";
+    const SIB1_ACTIVITY_ID: &str = "gomodel:67ae98b500000055/67ae98b500000090";
+
+    #[test]
+    fn diff_test() {
+        let old_yaml = fs::read_to_string(MODEL_PATH).unwrap();
+
+        let new_yaml = old_yaml
+            // a new individual ID with the same enabler and MF
+            .replace("gomodel:67ae98b500000055/67b1629100000017",
+                     "gomodel:67ae98b500000055/67b1629199999999")
+            // change the MF of sib1
+            .replace("term: GO:1904091", "term: GO:0016410")
+            .replace("https://orcid.org/0009-0003-9059-1333",
+                     "https://orcid.org/0000-0002-0000-0000");
+
+        let old_model = parse_gocam_py_model(&mut old_yaml.as_bytes()).unwrap();
+        let new_model = parse_gocam_py_model(&mut new_yaml.as_bytes()).unwrap();
+
+        let old_py_model = gocam_py_parse(&mut old_yaml.as_bytes()).unwrap();
+        let mut new_py_model = gocam_py_parse(&mut new_yaml.as_bytes()).unwrap();
+
+        let unchanged = diff_models(&old_model, None, &old_model, None);
+        assert!(unchanged.is_empty());
+
+        let sib1_activity = new_py_model.activities.iter_mut()
+            .find(|activity| activity.id == SIB1_ACTIVITY_ID)
+            .unwrap();
+        sib1_activity.enabled_by.evidence[0].reference = Some("PMID:1".to_owned());
+
+        let old_evidence = activity_evidence(&old_py_model);
+        let new_evidence = activity_evidence(&new_py_model);
+
+        let first_activity_evidence = &old_evidence[&old_py_model.activities[0].id];
+        assert!(first_activity_evidence.iter()
+                .any(|evidence| evidence.starts_with("has_output CHEBI:")));
+        assert!(old_evidence.values().flatten()
+                .any(|evidence| evidence.starts_with(&format!("RO:0002413 {} ", SIB1_ACTIVITY_ID))));
+
+        let diff = diff_models(&old_model, Some(&old_evidence), &new_model, Some(&new_evidence));
+
+        assert!(diff.added_activities.is_empty());
+        assert!(diff.removed_activities.is_empty());
+        assert!(diff.added_edges.is_empty());
+        assert!(diff.removed_edges.is_empty());
+        assert_eq!(diff.changed_activities.len(), 2);
+
+        let moved = diff.changed_activities.iter()
+            .find(|change| change.matched_by == MatchedBy::EnablerAndFunction)
+            .unwrap();
+        assert_eq!(moved.old_individual_id, "gomodel:67ae98b500000055/67b1629100000017");
+        assert!(moved.changes.is_empty());
+
+        let sib1 = diff.changed_activities.iter()
+            .find(|change| change.new_individual_id == SIB1_ACTIVITY_ID)
+            .unwrap();
+        let changed_slots: Vec<_> = sib1.changes.iter()
+            .map(|change| change.slot.as_str())
+            .collect();
+        assert_eq!(changed_slots, vec!["evidence", "molecular_function"]);
+        assert_eq!(sib1.changes[1].removed, vec!["GO:1904091".to_owned()]);
+        assert_eq!(sib1.changes[1].added, vec!["GO:0016410".to_owned()]);
+
+        assert_eq!(diff.added_contributors, vec!["https://orcid.org/0000-0002-0000-0000"]);
+        assert_eq!(diff.removed_contributors, vec!["https://orcid.org/0009-0003-9059-1333"]);
+
+        let text = diff.to_string();
+        assert!(text.contains("(was gomodel:67ae98b500000055/67b1629100000017)"));
+        assert!(text.contains("      + molecular_function: GO:0016410\n"));
+
+        let json = serde_json::to_value(&diff).unwrap();
+        assert_eq!(json["changed_activities"].as_array().unwrap().len(), 2);
+    }
+}
@@ -0,0 +1,658 @@
+use std::{fmt::Display, io::{Read, Write}, path::{Path, PathBuf}};
+
+use clap::ValueEnum;
+use flate2::read::MultiGzDecoder;
+use rayon::prelude::*;
+use serde::{Deserialize, Deserializer, de::{IgnoredAny, MapAccess, Visitor}};
+use thiserror::Error;
+
+use crate::{model_cache::{CachedModel, ModelCache}, model_source::{ModelSource, for_each_archive_entry, model_sources}};
+
+use pombase_gocam::{GoCamModel, gocam_py::{GoCamPyModel, gocam_py_parse}, parse_gocam_py_model,
+                    parse_raw_gocam_model, raw::{GoCamRawModel, gocam_parse_raw}};
+
+/// The exit code when --keep-going skipped files that failed to load
+pub(crate) const LOAD_FAILURE_EXIT_CODE: i32 = 3;
+
+/// The kinds of model file.  gocam-py JSON is read with the YAML parser
+/// because JSON is a subset of YAML.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub(crate) enum InputFormat {
+    MinervaJson,
+    GocamPyJson,
+    GocamPyYaml,
+}
+
+impl InputFormat {
+    pub fn is_gocam_py(&self) -> bool {
+        *self != InputFormat::MinervaJson
+    }
+}
+
+impl Display for InputFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            InputFormat::MinervaJson => "minerva-json",
+            InputFormat::GocamPyJson => "gocam-py-json",
+            InputFormat::GocamPyYaml => "gocam-py-yaml",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum ModelLoadError {
+    #[error("{}: {source}", .path.display())]
+    IOError { path: PathBuf, source: std::io::Error },
+    #[error("{}: expected a {expected} model but the file is {found}", .path.display())]
+    WrongFormat { path: PathBuf, expected: &'static str, found: InputFormat },
+    #[error("{}{}: {message}", .path.display(), location_suffix(.location))]
+    ParseError { path: PathBuf, location: Option<(usize, usize)>, message: String },
+    #[error("{}: no model files found", .path.display())]
+    NoModels { path: PathBuf },
+}
+
+fn location_suffix(location: &Option<(usize, usize)>) -> String {
+    location.map(|(line, column)| format!(":{}:{}", line, column)).unwrap_or_default()
+}
+
+impl ModelLoadError {
+    pub fn path(&self) -> &Path {
+        match self {
+            ModelLoadError::IOError { path, .. } |
+            ModelLoadError::WrongFormat { path, .. } |
+            ModelLoadError::ParseError { path, .. } |
+            ModelLoadError::NoModels { path } => path,
+        }
+    }
+
+    pub fn io_error(path: &Path, source: std::io::Error) -> ModelLoadError {
+        ModelLoadError::IOError {
+            path: path.to_owned(),
+            source,
+        }
+    }
+
+    pub fn parse_error(path: &Path, err: impl ToString) -> ModelLoadError {
+        let message = err.to_string();
+        ModelLoadError::ParseError {
+            path: path.to_owned(),
+            location: find_location(&message),
+            message,
+        }
+    }
+}
+
+// the JSON and YAML parsers report where they failed as "line N column M"
+// in their messages
+fn find_location(message: &str) -> Option<(usize, usize)> {
+    let (_, rest) = message.rsplit_once("line ")?;
+    let mut parts = rest.split_whitespace();
+
+    let line = parts.next()?.trim_end_matches(',').parse().ok()?;
+    if parts.next()? != "column" {
+        return None;
+    }
+    let column = parts.next()?
+        .trim_end_matches(|c: char| !c.is_ascii_digit())
+        .parse().ok()?;
+
+    Some((line, column))
+}
+
+// the number of archive entries held in memory and loaded in parallel
+const ARCHIVE_BATCH_SIZE: usize = 256;
+
+pub(crate) const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+pub(crate) const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+
+/// True if the contents start with the gzip or zstd magic bytes
+pub(crate) fn is_compressed(contents: &[u8]) -> bool {
+    contents.starts_with(GZIP_MAGIC) || contents.starts_with(ZSTD_MAGIC)
+}
+
+pub(crate) fn decompress(path: &Path, contents: Vec<u8>) -> Result<Vec<u8>, ModelLoadError> {
+    let io_error = |err| ModelLoadError::io_error(path, err);
+
+    if contents.starts_with(GZIP_MAGIC) {
+        let mut ret = vec![];
+        MultiGzDecoder::new(contents.as_slice()).read_to_end(&mut ret).map_err(io_error)?;
+        Ok(ret)
+    } else if contents.starts_with(ZSTD_MAGIC) {
+        zstd::decode_all(contents.as_slice()).map_err(io_error)
+    } else {
+        Ok(contents)
+    }
+}
+
+// The keys of a JSON object that tell the formats apart.  The values are
+// skipped without being stored.
+#[derive(Default)]
+struct TopLevelKeys {
+    has_activities: bool,
+    has_individuals: bool,
+}
+
+impl<'de> Deserialize<'de> for TopLevelKeys {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct KeysVisitor;
+
+        impl<'de> Visitor<'de> for KeysVisitor {
+            type Value = TopLevelKeys;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                f.write_str("a JSON object")
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<TopLevelKeys, A::Error> {
+                let mut keys = TopLevelKeys::default();
+
+                while let Some(key) = map.next_key::<String>()? {
+                    match key.as_str() {
+                        "activities" => keys.has_activities = true,
+                        "individuals" => keys.has_individuals = true,
+                        _ => (),
+                    }
+                    map.next_value::<IgnoredAny>()?;
+                }
+
+                Ok(keys)
+            }
+        }
+
+        deserializer.deserialize_map(KeysVisitor)
+    }
+}
+
+// Minerva JSON has "individuals" and "facts" at the top level and
+// gocam-py has "activities".  Invalid JSON is treated as Minerva JSON so
+// that the error comes from the Minerva parser.
+pub(crate) fn sniff_format(contents: &[u8]) -> InputFormat {
+    let is_json = contents.iter()
+        .find(|byte| !byte.is_ascii_whitespace())
+        .is_some_and(|byte| *byte == b'{');
+
+    if !is_json {
+        return InputFormat::GocamPyYaml;
+    }
+
+    match serde_json::from_slice::<TopLevelKeys>(contents) {
+        Ok(keys) if keys.has_activities && !keys.has_individuals => InputFormat::GocamPyJson,
+        _ => InputFormat::MinervaJson,
+    }
+}
+
+/// True for .json, .yaml and .yml files, which may also be compressed
+/// (eg. "model.yaml.gz")
+pub(crate) fn is_model_file_name(path: &Path) -> bool {
+    let path = match path.extension().and_then(|ext| ext.to_str()) {
+        Some("gz") | Some("zst") => path.with_extension(""),
+        _ => path.to_owned(),
+    };
+
+    path.extension().is_some_and(|ext| ext == "json" || ext == "yaml" || ext == "yml")
+}
+
+/// Read a possibly gzip or zstd compressed model file, returning the
+/// uncompressed contents
+pub(crate) fn read_model_contents(source: &ModelSource) -> Result<Vec<u8>, ModelLoadError> {
+    decompress(source.name(), source.read()?.into_owned())
+}
+
+/// Read a model file like read_model_contents(), also returning the
+/// format, which is found from the contents unless input_format is set
+pub(crate) fn read_model_source(source: &ModelSource, input_format: Option<InputFormat>)
+    -> Result<(InputFormat, Vec<u8>), ModelLoadError>
+{
+    let contents = read_model_contents(source)?;
+    let format = input_format.unwrap_or_else(|| sniff_format(&contents));
+
+    Ok((format, contents))
+}
+
+fn parse_model(path: &Path, format: InputFormat, contents: &[u8])
+    -> Result<GoCamModel, ModelLoadError>
+{
+    if format.is_gocam_py() {
+        parse_gocam_py_model(&mut &contents[..])
+            .map_err(|err| ModelLoadError::parse_error(path, err))
+    } else {
+        parse_raw_gocam_model(&mut &contents[..])
+            .map_err(|err| ModelLoadError::parse_error(path, err))
+    }
+}
+
+/// Return the model from the cache if these contents have been parsed
+/// before, otherwise call parse with the format and store the result.
+/// The format is only sniffed if the model isn't in the cache.
+fn parse_with_cache<T, F>(input_format: Option<InputFormat>, contents: &[u8],
+                          cache: Option<&ModelCache>, parse: F)
+    -> Result<T, ModelLoadError>
+where
+    T: CachedModel,
+    F: FnOnce(InputFormat, &[u8]) -> Result<T, ModelLoadError>,
+{
+    if let Some(cache) = cache &&
+        let Some(model) = cache.get(input_format, contents) {
+            return Ok(model);
+        }
+
+    let format = input_format.unwrap_or_else(|| sniff_format(contents));
+    let model = parse(format, contents)?;
+
+    if let Some(cache) = cache {
+        cache.put(input_format, contents, &model);
+    }
+
+    Ok(model)
+}
+
+/// Parse a Minerva JSON, gocam-py JSON or gocam-py YAML file, using the
+/// parsed model from the cache if the file has been parsed before
+pub(crate) fn read_model_file(source: &ModelSource, input_format: Option<InputFormat>,
+                              cache: Option<&ModelCache>)
+    -> Result<GoCamModel, ModelLoadError>
+{
+    let contents = read_model_contents(source)?;
+
+    parse_model_contents(source.name(), input_format, &contents, cache)
+}
+
+/// Parse already read contents like read_model_file()
+fn parse_model_contents(path: &Path, input_format: Option<InputFormat>,
+                        contents: &[u8], cache: Option<&ModelCache>)
+    -> Result<GoCamModel, ModelLoadError>
+{
+    parse_with_cache(input_format, contents, cache, |format, contents| {
+        parse_model(path, format, contents)
+    })
+}
+
+/// Parse the gocam-py representation of already read contents
+pub(crate) fn parse_gocam_py_contents(path: &Path, input_format: Option<InputFormat>,
+                                      contents: &[u8], cache: Option<&ModelCache>)
+    -> Result<GoCamPyModel, ModelLoadError>
+{
+    parse_with_cache(input_format, contents, cache, |format, mut contents| {
+        if !format.is_gocam_py() {
+            return Err(ModelLoadError::WrongFormat {
+                path: path.to_owned(),
+                expected: "gocam-py",
+                found: format,
+            });
+        }
+
+        gocam_py_parse(&mut contents)
+            .map_err(|err| ModelLoadError::parse_error(path, err))
+    })
+}
+
+/// Read a gocam-py model, for the commands that need the gocam-py
+/// representation, using the cache like read_model_file()
+pub(crate) fn read_gocam_py_file(source: &ModelSource, input_format: Option<InputFormat>,
+                                 cache: Option<&ModelCache>)
+    -> Result<GoCamPyModel, ModelLoadError>
+{
+    let contents = read_model_contents(source)?;
+
+    parse_gocam_py_contents(source.name(), input_format, &contents, cache)
+}
+
+/// Read a Minerva JSON model without converting it to a GoCamModel
+pub(crate) fn read_raw_model_file(source: &ModelSource, input_format: Option<InputFormat>)
+    -> Result<GoCamRawModel, ModelLoadError>
+{
+    let path = source.name();
+    let (format, contents) = read_model_source(source, input_format)?;
+
+    if format.is_gocam_py() {
+        return Err(ModelLoadError::WrongFormat {
+            path: path.to_owned(),
+            expected: "Minerva JSON",
+            found: format,
+        });
+    }
+
+    gocam_parse_raw(&mut contents.as_slice())
+        .map_err(|err| ModelLoadError::parse_error(path, err))
+}
+
+/// Loads models, either stopping at the first file that fails or, with
+/// keep_going, reporting and remembering the failure so the rest of the
+/// batch can be processed
+pub(crate) struct ModelLoader {
+    keep_going: bool,
+    // None to find the format of each file from its contents
+    input_format: Option<InputFormat>,
+    cache: Option<ModelCache>,
+    failures: Vec<ModelLoadError>,
+}
+
+impl ModelLoader {
+    pub fn new(keep_going: bool, input_format: Option<InputFormat>, cache: Option<ModelCache>)
+        -> ModelLoader
+    {
+        ModelLoader {
+            keep_going,
+            input_format,
+            cache,
+            failures: vec![],
+        }
+    }
+
+    pub fn input_format(&self) -> Option<InputFormat> {
+        self.input_format
+    }
+
+    pub fn cache(&self) -> Option<&ModelCache> {
+        self.cache.as_ref()
+    }
+
+    /// Read one model, failing even if keep_going is set
+    pub fn read(&self, path: &Path) -> Result<GoCamModel, ModelLoadError> {
+        read_model_file(&ModelSource::from(path), self.input_format, self.cache.as_ref())
+    }
+
+    /// Returns Ok(None) if the load failed and keep_going is set
+    pub fn check<T>(&mut self, result: Result<T, ModelLoadError>)
+        -> Result<Option<T>, ModelLoadError>
+    {
+        match result {
+            Ok(value) => Ok(Some(value)),
+            Err(err) if self.keep_going => {
+                eprintln!("skipping {}", err);
+                self.failures.push(err);
+                Ok(None)
+            },
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Call f for each source on the rayon thread pool.  The results are
+    /// returned in the order of the sources, without the failures if
+    /// keep_going is set.  Archives are read in batches of
+    /// ARCHIVE_BATCH_SIZE entries so only one batch is in memory at a time.
+    pub fn map_sources<T, F>(&mut self, sources: &[ModelSource], f: F)
+        -> Result<Vec<T>, ModelLoadError>
+    where
+        T: Send,
+        F: Fn(&ModelSource) -> Result<T, ModelLoadError> + Sync + Send,
+    {
+        let mut ret = Vec::with_capacity(sources.len());
+
+        let mut rest = sources;
+
+        while !rest.is_empty() {
+            let files_len = rest.iter()
+                .position(|source| matches!(source, ModelSource::Archive(_)))
+                .unwrap_or(rest.len());
+
+            self.map_batch(&rest[..files_len], &f, &mut ret)?;
+
+            if let Some(ModelSource::Archive(archive_path)) = rest.get(files_len) {
+                self.map_archive(archive_path, &f, &mut ret)?;
+                rest = &rest[files_len + 1..];
+            } else {
+                rest = &rest[files_len..];
+            }
+        }
+
+        Ok(ret)
+    }
+
+    fn map_batch<T, F>(&mut self, sources: &[ModelSource], f: &F, ret: &mut Vec<T>)
+        -> Result<(), ModelLoadError>
+    where
+        T: Send,
+        F: Fn(&ModelSource) -> Result<T, ModelLoadError> + Sync + Send,
+    {
+        let results: Vec<_> = sources.par_iter().map(f).collect();
+
+        for result in results {
+            if let Some(value) = self.check(result)? {
+                ret.push(value);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn map_archive<T, F>(&mut self, archive_path: &Path, f: &F, ret: &mut Vec<T>)
+        -> Result<(), ModelLoadError>
+    where
+        T: Send,
+        F: Fn(&ModelSource) -> Result<T, ModelLoadError> + Sync + Send,
+    {
+        let mut batch = Vec::with_capacity(ARCHIVE_BATCH_SIZE);
+        let mut map_result = Ok(());
+
+        let read_result = for_each_archive_entry(archive_path, &mut |source| {
+            batch.push(source);
+            if batch.len() < ARCHIVE_BATCH_SIZE {
+                return true;
+            }
+            map_result = self.map_batch(&batch, f, ret);
+            batch.clear();
+            map_result.is_ok()
+        });
+
+        map_result?;
+        self.map_batch(&batch, f, ret)?;
+        self.check(read_result)?;
+
+        Ok(())
+    }
+
+    /// Expand the paths with model_sources().  A path that can't be
+    /// expanded (eg. a glob that matches nothing) is skipped if
+    /// keep_going is set, like a file that fails to load.
+    pub fn sources(&mut self, paths: &[PathBuf]) -> Result<Vec<ModelSource>, ModelLoadError> {
+        let mut ret = vec![];
+
+        for path in paths {
+            if let Some(sources) = self.check(model_sources(std::slice::from_ref(path)))? {
+                ret.extend(sources);
+            }
+        }
+
+        Ok(ret)
+    }
+
+    /// Load the models from files, directories, archives and standard
+    /// input (see model_sources()) in parallel and call f for each model
+    pub fn map_models<T, F>(&mut self, paths: &[PathBuf], f: F) -> Result<Vec<T>, ModelLoadError>
+    where
+        T: Send,
+        F: Fn(GoCamModel) -> T + Sync,
+    {
+        let input_format = self.input_format;
+        let cache = self.cache.clone();
+        let sources = self.sources(paths)?;
+
+        self.map_sources(&sources, |source| {
+            read_model_file(source, input_format, cache.as_ref()).map(&f)
+        })
+    }
+
+    pub fn load_all(&mut self, paths: &[PathBuf]) -> Result<Vec<GoCamModel>, ModelLoadError> {
+        self.map_models(paths, |model| model)
+    }
+
+    pub fn failures(&self) -> &[ModelLoadError] {
+        &self.failures
+    }
+
+    pub fn write_summary(&self, writer: &mut dyn Write) -> Result<(), std::io::Error> {
+        writeln!(writer, "{} file(s) failed to load:", self.failures.len())?;
+        for failure in &self.failures {
+            writeln!(writer, "  {}", failure)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, io::Write, path::PathBuf};
+
+    use flate2::{Compression, write::GzEncoder};
+
+    use crate::model_source::ModelSource;
+
+    use super::{InputFormat, ModelLoadError, ModelLoader, find_location, is_model_file_name,
+                read_model_file, read_model_source, read_raw_model_file, sniff_format};
+
+    const YAML_PATH: &str = "tests/data/67ae98b500000055.yaml";
+
+    #[test]
+    fn find_location_test() {
+        assert_eq!(find_location("expected value at line 3 column 7"), Some((3, 7)));
+        assert_eq!(find_location("did not find expected key at line 12 column 5, while \
+                                  parsing a block mapping"), Some((12, 5)));
+        assert_eq!(find_location("missing field `id`"), None);
+    }
+
+    #[test]
+    fn keep_going_test() {
+        let dir = std::env::temp_dir().join(format!("model_loader_test_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let bad_path = dir.join("bad.json");
+        fs::write(&bad_path, "{ \"id\": ").unwrap();
+        let no_extension_path = dir.join("model");
+        fs::write(&no_extension_path, "activities: [").unwrap();
+
+        let paths = vec![PathBuf::from(YAML_PATH),
+                         bad_path.clone(), no_extension_path.clone(),
+                         dir.join("missing.yaml")];
+
+        let err = ModelLoader::new(false, None, None).load_all(&paths).unwrap_err();
+        assert_eq!(err.path(), bad_path);
+        assert!(err.to_string().starts_with(&format!("{}:", bad_path.display())));
+
+        let mut loader = ModelLoader::new(true, None, None);
+        let models = loader.load_all(&paths).unwrap();
+        assert_eq!(models.len(), 1);
+
+        let failures = loader.failures();
+        assert_eq!(failures.len(), 3);
+        assert!(matches!(failures[0], ModelLoadError::ParseError { .. }));
+        assert!(matches!(failures[1], ModelLoadError::ParseError { .. }));
+        assert_eq!(failures[1].path(), no_extension_path);
+        assert!(matches!(failures[2], ModelLoadError::IOError { .. }));
+
+        let mut summary = vec![];
+        loader.write_summary(&mut summary).unwrap();
+        assert!(String::from_utf8(summary).unwrap().starts_with("3 file(s) failed to load:\n"));
+
+        // a path that can't be expanded is skipped like a file that fails
+        // to load
+        let glob_paths = vec![dir.join("*.none"), PathBuf::from(YAML_PATH)];
+        let mut loader = ModelLoader::new(true, None, None);
+        assert_eq!(loader.load_all(&glob_paths).unwrap().len(), 1);
+        assert!(matches!(loader.failures(), [ModelLoadError::NoModels { .. }]));
+        assert!(ModelLoader::new(false, None, None).load_all(&glob_paths).is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn map_sources_order_test() {
+        let yaml = std::fs::read(YAML_PATH).unwrap();
+        let sources: Vec<_> = (0..20)
+            .map(|idx| ModelSource::Memory {
+                name: PathBuf::from(format!("model_{}", idx)),
+                // every fifth model is invalid
+                contents: if idx % 5 == 3 { b"activities: [".to_vec() } else { yaml.clone() },
+            })
+            .collect();
+
+        let pool = rayon::ThreadPoolBuilder::new().num_threads(4).build().unwrap();
+
+        let mut loader = ModelLoader::new(true, None, None);
+        let names = pool.install(|| {
+            loader.map_sources(&sources, |source| {
+                read_model_file(source, None, None).map(|_| source.name().to_owned())
+            })
+        }).unwrap();
+
+        let expected: Vec<_> = (0..20)
+            .filter(|idx| idx % 5 != 3)
+            .map(|idx| PathBuf::from(format!("model_{}", idx)))
+            .collect();
+        assert_eq!(names, expected);
+
+        let failed: Vec<_> = loader.failures().iter()
+            .map(|failure| failure.path().display().to_string())
+            .collect();
+        assert_eq!(failed, vec!["model_3", "model_8", "model_13", "model_18"]);
+
+        // without keep_going the first failure in input order is returned
+        let err = pool.install(|| {
+            ModelLoader::new(false, None, None).map_sources(&sources, |source| {
+                read_model_file(source, None, None)
+            })
+        }).unwrap_err();
+        assert_eq!(err.path(), PathBuf::from("model_3"));
+    }
+
+    #[test]
+    fn sniff_format_test() {
+        assert_eq!(sniff_format(b"  {\"id\": \"gomodel:1\", \"individuals\": [], \"facts\": []}"),
+                   InputFormat::MinervaJson);
+        assert_eq!(sniff_format(b"{\"id\": \"gomodel:1\", \"activities\": []}"),
+                   InputFormat::GocamPyJson);
+        // only the top-level keys count
+        assert_eq!(sniff_format(b"{\"id\": \"gomodel:1\", \"facts\": [{\"activities\": []}]}"),
+                   InputFormat::MinervaJson);
+        assert_eq!(sniff_format(b"{\"id\": "), InputFormat::MinervaJson);
+        assert_eq!(sniff_format(b"id: gomodel:1\nactivities: []\n"), InputFormat::GocamPyYaml);
+    }
+
+    #[test]
+    fn compressed_and_gocam_py_json_test() {
+        let dir = std::env::temp_dir().join(format!("model_format_test_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let yaml = fs::read(YAML_PATH).unwrap();
+
+        let gz_path = dir.join("model.yaml.gz");
+        let mut encoder = GzEncoder::new(vec![], Compression::default());
+        encoder.write_all(&yaml).unwrap();
+        fs::write(&gz_path, encoder.finish().unwrap()).unwrap();
+
+        let zst_path = dir.join("model.zst");
+        fs::write(&zst_path, zstd::encode_all(yaml.as_slice(), 0).unwrap()).unwrap();
+
+        let json_path = PathBuf::from("tests/data/67ae98b500000055_gocam_py.json");
+
+        let gz_source = ModelSource::File(gz_path.clone());
+        let json_source = ModelSource::File(json_path.clone());
+
+        let expected = read_model_file(&ModelSource::File(YAML_PATH.into()), None, None).unwrap();
+
+        for path in [&gz_path, &zst_path, &json_path] {
+            let model = read_model_file(&ModelSource::File(path.clone()), None, None).unwrap();
+            assert_eq!(model.id(), expected.id());
+            assert_eq!(model.node_iterator().count(), expected.node_iterator().count());
+        }
+
+        assert!(is_model_file_name(&gz_path));
+        assert!(!is_model_file_name(&zst_path));
+
+        let (format, contents) = read_model_source(&gz_source, None).unwrap();
+        assert_eq!(format, InputFormat::GocamPyYaml);
+        assert_eq!(contents, yaml);
+
+        let (format, _) = read_model_source(&json_source, None).unwrap();
+        assert_eq!(format, InputFormat::GocamPyJson);
+
+        // --input-format overrides the sniffed format
+        assert!(matches!(read_model_file(&json_source, Some(InputFormat::MinervaJson), None),
+                         Err(ModelLoadError::ParseError { .. })));
+        assert!(matches!(read_raw_model_file(&json_source, None),
+                         Err(ModelLoadError::WrongFormat { .. })));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
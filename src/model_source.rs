@@ -0,0 +1,333 @@
+use std::{borrow::Cow, fs::File, io::{BufRead, BufReader, Read}, path::{Path, PathBuf}};
+
+use flate2::read::MultiGzDecoder;
+
+use crate::model_loader::{GZIP_MAGIC, ModelLoadError, ZSTD_MAGIC, is_model_file_name};
+
+/// A model file on disk, the contents of a model from a tar archive or a
+/// JSON-lines stream on standard input, or a whole tar archive
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum ModelSource {
+    File(PathBuf),
+    // name is eg. "models.tar.gz:models/66187e4700001744.json" or
+    // "<stdin>:12" and is used in messages
+    Memory { name: PathBuf, contents: Vec<u8> },
+    // the entries are read one at a time by for_each_archive_entry(), so
+    // large archives are never held in memory
+    Archive(PathBuf),
+}
+
+impl ModelSource {
+    pub fn name(&self) -> &Path {
+        match self {
+            ModelSource::File(path) => path,
+            ModelSource::Memory { name, .. } => name,
+            ModelSource::Archive(path) => path,
+        }
+    }
+
+    /// The path if the model is a file that could be rewritten
+    pub fn file_path(&self) -> Option<&Path> {
+        match self {
+            ModelSource::File(path) => Some(path),
+            ModelSource::Memory { .. } | ModelSource::Archive(_) => None,
+        }
+    }
+
+    pub fn read(&self) -> Result<Cow<'_, [u8]>, ModelLoadError> {
+        match self {
+            ModelSource::File(path) => {
+                let mut contents = vec![];
+                File::open(path)
+                    .and_then(|mut file| file.read_to_end(&mut contents))
+                    .map_err(|err| ModelLoadError::io_error(path, err))?;
+                Ok(Cow::Owned(contents))
+            },
+            ModelSource::Memory { contents, .. } => Ok(Cow::Borrowed(contents)),
+            ModelSource::Archive(path) => {
+                let err = std::io::Error::other("an archive isn't a model, read its entries");
+                Err(ModelLoadError::io_error(path, err))
+            },
+        }
+    }
+}
+
+impl From<&Path> for ModelSource {
+    fn from(path: &Path) -> Self {
+        ModelSource::File(path.to_owned())
+    }
+}
+
+const STDIN_NAME: &str = "-";
+
+fn is_archive_name(path: &Path) -> bool {
+    let name = path.to_string_lossy();
+    [".tar", ".tar.gz", ".tgz", ".tar.zst"].iter().any(|suffix| name.ends_with(suffix))
+}
+
+fn is_glob(path: &Path) -> bool {
+    !path.exists() && path.to_string_lossy().contains(['*', '?', '['])
+}
+
+// model files in a directory and its sub-directories, sorted by path so
+// that the output doesn't depend on the file system
+fn find_model_files(dir: &Path, ret: &mut Vec<ModelSource>) -> Result<(), ModelLoadError> {
+    let io_error = |err| ModelLoadError::io_error(dir, err);
+
+    let mut paths = vec![];
+    for entry in std::fs::read_dir(dir).map_err(io_error)? {
+        paths.push(entry.map_err(io_error)?.path());
+    }
+    paths.sort();
+
+    for path in paths {
+        if path.is_dir() {
+            find_model_files(&path, ret)?;
+        } else if is_model_file_name(&path) {
+            ret.push(ModelSource::File(path));
+        }
+    }
+
+    Ok(())
+}
+
+// a reader of the uncompressed tar stream
+fn open_archive(archive_path: &Path) -> Result<tar::Archive<Box<dyn Read>>, std::io::Error> {
+    let mut reader = BufReader::new(File::open(archive_path)?);
+    let magic = reader.fill_buf()?;
+
+    let reader: Box<dyn Read> = if magic.starts_with(GZIP_MAGIC) {
+        Box::new(MultiGzDecoder::new(reader))
+    } else if magic.starts_with(ZSTD_MAGIC) {
+        Box::new(zstd::Decoder::with_buffer(reader)?)
+    } else {
+        Box::new(reader)
+    };
+
+    Ok(tar::Archive::new(reader))
+}
+
+/// Call f with each model file in a tar archive, in the order of the
+/// archive, while the archive is decompressed and read.  Stops early if
+/// f returns false.
+pub(crate) fn for_each_archive_entry(archive_path: &Path,
+                                     f: &mut dyn FnMut(ModelSource) -> bool)
+    -> Result<(), ModelLoadError>
+{
+    let io_error = |err| ModelLoadError::io_error(archive_path, err);
+
+    let mut archive = open_archive(archive_path).map_err(io_error)?;
+
+    for entry in archive.entries().map_err(io_error)? {
+        let mut entry = entry.map_err(io_error)?;
+
+        let entry_path = entry.path().map_err(io_error)?.into_owned();
+        if !entry.header().entry_type().is_file() || !is_model_file_name(&entry_path) {
+            continue;
+        }
+
+        let mut entry_contents = vec![];
+        entry.read_to_end(&mut entry_contents).map_err(io_error)?;
+
+        let name = format!("{}:{}", archive_path.display(), entry_path.display());
+        let source = ModelSource::Memory {
+            name: PathBuf::from(name),
+            contents: entry_contents,
+        };
+
+        if !f(source) {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+fn expand_glob(pattern: &Path, ret: &mut Vec<ModelSource>) -> Result<(), ModelLoadError> {
+    let pattern_string = pattern.to_string_lossy();
+    let paths = glob::glob(&pattern_string)
+        .map_err(|err| ModelLoadError::parse_error(pattern, err))?;
+
+    let count = ret.len();
+
+    for path in paths {
+        let path = path.map_err(|err| ModelLoadError::io_error(pattern, err.into_error()))?;
+        expand_path(&path, ret)?;
+    }
+
+    if ret.len() == count {
+        return Err(ModelLoadError::NoModels { path: pattern.to_owned() });
+    }
+
+    Ok(())
+}
+
+// A list of paths, one per line, or a JSON-lines stream of models if the
+// input starts with "{"
+fn read_stdin_sources(reader: &mut dyn BufRead, ret: &mut Vec<ModelSource>)
+    -> Result<(), ModelLoadError>
+{
+    let mut contents = String::new();
+    reader.read_to_string(&mut contents)
+        .map_err(|err| ModelLoadError::io_error(Path::new(STDIN_NAME), err))?;
+
+    let is_json_lines = contents.trim_start().starts_with('{');
+
+    for (idx, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        if is_json_lines {
+            ret.push(ModelSource::Memory {
+                name: PathBuf::from(format!("<stdin>:{}", idx + 1)),
+                contents: line.as_bytes().to_vec(),
+            });
+        } else {
+            expand_path(Path::new(line), ret)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn expand_path(path: &Path, ret: &mut Vec<ModelSource>) -> Result<(), ModelLoadError> {
+    if path.is_dir() {
+        find_model_files(path, ret)
+    } else if is_archive_name(path) {
+        ret.push(ModelSource::Archive(path.to_owned()));
+        Ok(())
+    } else if is_glob(path) {
+        expand_glob(path, ret)
+    } else {
+        ret.push(ModelSource::File(path.to_owned()));
+        Ok(())
+    }
+}
+
+/// Turn the paths from the command line into the models to process:
+/// directories are searched recursively, tar archives are returned to
+/// be read entry by entry (see for_each_archive_entry()), glob
+/// patterns are expanded and "-" reads paths or JSON-lines models from
+/// standard input.  Other paths are returned as they are, whatever their
+/// extension.
+pub(crate) fn model_sources(paths: &[PathBuf]) -> Result<Vec<ModelSource>, ModelLoadError> {
+    let mut ret = vec![];
+
+    for path in paths {
+        if path.as_os_str() == STDIN_NAME {
+            read_stdin_sources(&mut std::io::stdin().lock(), &mut ret)?;
+        } else {
+            expand_path(path, &mut ret)?;
+        }
+    }
+
+    Ok(ret)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::{Path, PathBuf}};
+
+    use flate2::{Compression, write::GzEncoder};
+
+    use crate::model_loader::{ModelLoadError, ModelLoader, read_model_file};
+
+    use super::{ModelSource, for_each_archive_entry, model_sources, read_stdin_sources};
+
+    const YAML_PATH: &str = "tests/data/67ae98b500000055.yaml";
+
+    fn names(sources: &[ModelSource]) -> Vec<String> {
+        sources.iter().map(|source| source.name().display().to_string()).collect()
+    }
+
+    #[test]
+    fn model_sources_test() {
+        let dir = std::env::temp_dir().join(format!("model_source_test_{}", std::process::id()));
+        let sub_dir = dir.join("models").join("sub");
+        fs::create_dir_all(&sub_dir).unwrap();
+
+        let models_dir = dir.join("models");
+        fs::copy(YAML_PATH, models_dir.join("b.yaml")).unwrap();
+        fs::copy(YAML_PATH, sub_dir.join("a.yaml")).unwrap();
+        fs::write(models_dir.join("notes.txt"), "not a model").unwrap();
+
+        let sources = model_sources(&[models_dir.clone()]).unwrap();
+        assert_eq!(names(&sources),
+                   vec![models_dir.join("b.yaml").display().to_string(),
+                        sub_dir.join("a.yaml").display().to_string()]);
+
+        let pattern = models_dir.join("*.yaml");
+        let sources = model_sources(&[pattern]).unwrap();
+        assert_eq!(sources, vec![ModelSource::File(models_dir.join("b.yaml"))]);
+
+        assert!(matches!(model_sources(&[models_dir.join("*.json")]),
+                         Err(ModelLoadError::NoModels { .. })));
+
+        // a plain path is used as it is, even without a model extension
+        let sources = model_sources(&[models_dir.join("notes.txt")]).unwrap();
+        assert_eq!(sources.len(), 1);
+
+        let archive_path = dir.join("models.tar.gz");
+        let encoder = GzEncoder::new(fs::File::create(&archive_path).unwrap(),
+                                     Compression::default());
+        let mut builder = tar::Builder::new(encoder);
+        builder.append_dir_all("models", &models_dir).unwrap();
+        builder.into_inner().unwrap().finish().unwrap();
+
+        let sources = model_sources(&[archive_path.clone()]).unwrap();
+        assert_eq!(sources, vec![ModelSource::Archive(archive_path.clone())]);
+        assert!(sources[0].file_path().is_none());
+
+        let mut sources = vec![];
+        for_each_archive_entry(&archive_path, &mut |source| {
+            sources.push(source);
+            true
+        }).unwrap();
+        assert_eq!(sources.len(), 2);
+        assert!(sources.iter().all(|source| source.file_path().is_none()));
+        assert!(names(&sources).contains(&format!("{}:models/sub/a.yaml",
+                                                  archive_path.display())));
+
+        let model = read_model_file(&sources[0], None, None).unwrap();
+        assert_eq!(model.id(), "gomodel:67ae98b500000055");
+
+        let models = ModelLoader::new(false, None, None).load_all(&[archive_path.clone()]).unwrap();
+        assert_eq!(models.len(), 2);
+
+        // stop after the first entry
+        let mut count = 0;
+        for_each_archive_entry(&archive_path, &mut |_| {
+            count += 1;
+            false
+        }).unwrap();
+        assert_eq!(count, 1);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn stdin_sources_test() {
+        let mut sources = vec![];
+        let mut paths = "tests/data/67ae98b500000055.yaml\n\ntests/data/existing.gaf\n".as_bytes();
+        read_stdin_sources(&mut paths, &mut sources).unwrap();
+        assert_eq!(sources,
+                   vec![ModelSource::File(PathBuf::from(YAML_PATH)),
+                        ModelSource::File(PathBuf::from("tests/data/existing.gaf"))]);
+
+        let json = fs::read_to_string("tests/data/67ae98b500000055_gocam_py.json").unwrap();
+        let json_line: String = serde_json::from_str::<serde_json::Value>(&json).unwrap()
+            .to_string();
+        let json_lines = format!("{}\n{}\n", json_line, json_line);
+
+        let mut sources = vec![];
+        read_stdin_sources(&mut json_lines.as_bytes(), &mut sources).unwrap();
+        assert_eq!(names(&sources), vec!["<stdin>:1", "<stdin>:2"]);
+
+        let model = read_model_file(&sources[1], None, None).unwrap();
+        assert_eq!(model.id(), "gomodel:67ae98b500000055");
+
+        assert_eq!(sources[0].name(), Path::new("<stdin>:1"));
+    }
+}
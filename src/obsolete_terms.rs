@@ -1,61 +1,255 @@
-use std::collections::HashSet;
-
-use pombase_gocam::GoCamModel;
+use pombase_gocam::{GoCamActivity, GoCamEnabledBy, GoCamModel, GoCamNodeType,
+                    gocam_py::GoCamPyModel};
 
+use crate::diagnostic::{Diagnostic, DiagnosticCollector, DiagnosticKind, DiagnosticTerm,
+                        Severity};
+use crate::gocam_py_terms::term_slots;
 use crate::ontology_info::OntologyInfo;
 
-pub type TermId = String;
-pub type TermName = String;
-pub type FieldType = String;
+// collects one Diagnostic per (term, field) pair
+struct ObsoleteCollector<'a> {
+    model_id: &'a str,
+    ontology_info: &'a OntologyInfo,
+    diagnostics: DiagnosticCollector<(String, String)>,
+}
+
+impl<'a> ObsoleteCollector<'a> {
+    fn new(model_id: &'a str, ontology_info: &'a OntologyInfo) -> ObsoleteCollector<'a> {
+        ObsoleteCollector {
+            model_id,
+            ontology_info,
+            diagnostics: DiagnosticCollector::default(),
+        }
+    }
+
+    fn check(&mut self, term_id: &str, term_name: &str, field: &str,
+             individual_id: Option<&str>) {
+        let ontology_info = self.ontology_info;
+        let model_id = self.model_id;
+
+        let (kind, severity) =
+            if ontology_info.is_obsolete_term(term_id) {
+                (DiagnosticKind::ObsoleteTerm, Severity::Error)
+            } else if ontology_info.is_unknown_term(term_id) {
+                (DiagnosticKind::UnknownTerm, Severity::Warning)
+            } else {
+                return;
+            };
+
+        let key = (term_id.to_owned(), field.to_owned());
+
+        self.diagnostics.add(key, individual_id, || {
+            let mut diagnostic = Diagnostic::new(kind, severity, model_id);
+            diagnostic.subject_term = Some(DiagnosticTerm {
+                id: term_id.to_owned(),
+                name: term_name.to_owned(),
+            });
+            diagnostic.field = Some(field.to_owned());
+            if let Some(term_info) = ontology_info.get_term_info(term_id) {
+                diagnostic.replaced_by = term_info.replaced_by.first().cloned();
+                diagnostic.consider.clone_from(&term_info.consider);
+            }
+            diagnostic
+        });
+    }
+
+    // for gocam-py models, which only have IDs, we use the name from the
+    // ontology file if there is one
+    fn check_id(&mut self, term_id: &str, field: &str, individual_id: Option<&str>) {
+        let ontology_info = self.ontology_info;
+        let term_name = ontology_info.term_name(term_id).unwrap_or_default();
+        self.check(term_id, term_name, field, individual_id);
+    }
+}
 
+// the "GO:NNNNNNN" IDs in a model title
+fn title_term_ids(title: &str) -> Vec<&str> {
+    let mut ret = vec![];
+    let mut rest = title;
+
+    while let Some(start) = rest.find("GO:") {
+        let candidate = &rest[start..];
+        let id_len = 3 + candidate[3..].chars().take_while(|c| c.is_ascii_digit()).count();
+        if id_len == 10 {
+            ret.push(&candidate[..id_len]);
+        }
+        rest = &candidate[id_len..];
+    }
+
+    ret
+}
+
+/// Find the obsolete and unknown terms used in a model.  Terms are only
+/// reported as obsolete (with replacements) if an ontology file was read.
 pub(crate) fn find_obsolete_terms(model: &GoCamModel,
                                   ontology_info: &OntologyInfo)
-    -> Vec<(TermId, TermName, FieldType, Option<String>)>
+    -> Vec<Diagnostic>
 {
-    let mut seen = HashSet::new();
-    let mut ret = vec![];
+    let mut collector = ObsoleteCollector::new(model.id(), ontology_info);
 
     for title_term_id in model.title_process_term_ids() {
-        if ontology_info.is_obsolete_term(title_term_id) {
-            let obsolete = (title_term_id.clone(), model.title().to_owned(),
-                            "model title".to_owned(), None);
-            ret.push(obsolete);
-        }
+        collector.check(title_term_id, model.title(), "model title", None);
     }
 
     for (_, node) in model.node_iterator() {
         if node.is_activity() {
-            if ontology_info.is_obsolete_term(&node.node_id) {
-                let obsolete = (node.node_id.clone(), node.label.clone(), "molecular_function".to_owned(), None);
-                if !seen.contains(&obsolete) {
-                    ret.push(obsolete.clone());
-                    seen.insert(obsolete);
-                }
-            }
+            let individual_id = node.individual_gocam_id.to_string();
+            let individual_id = Some(individual_id.as_str());
+
+            collector.check(&node.node_id, &node.label, "molecular_function", individual_id);
 
             for occurs_in in node.occurs_in.iter() {
-                if ontology_info.is_obsolete_term(occurs_in.id()) {
-                    let obsolete = (occurs_in.id().to_owned(), occurs_in.label().to_owned(), "occurs_in".to_owned(), None);
-                    if !seen.contains(&obsolete) {
-                        ret.push(obsolete.clone());
-                        seen.insert(obsolete);
-                    }
-                }
+                collector.check(occurs_in.id(), occurs_in.label(), "occurs_in", individual_id);
+            }
+
+            if let Some(ref part_of_process) = node.part_of_process {
+                collector.check(part_of_process.id(), &part_of_process.label,
+                                "part_of_process", individual_id);
             }
 
-            if let Some(ref part_of_process) = node.part_of_process &&
-                ontology_info.is_obsolete_term(part_of_process.id())
-            {
-                let obsolete = (part_of_process.id().to_owned(), part_of_process.label.to_owned(),
-                                "part_of_process".to_owned(), None);
-                if !seen.contains(&obsolete) {
-                    ret.push(obsolete.clone());
-                    seen.insert(obsolete);
+            if let GoCamNodeType::Activity(GoCamActivity { ref enabler, .. }) = node.node_type {
+                match enabler {
+                    GoCamEnabledBy::Chemical(chemical) => {
+                        collector.check(chemical.id(), chemical.label(), "enabled_by", individual_id);
+                    },
+                    GoCamEnabledBy::Complex(complex) => {
+                        collector.check(complex.id(), complex.label(), "enabled_by", individual_id);
+                    },
+                    GoCamEnabledBy::ModifiedProtein(protein) => {
+                        collector.check(protein.id(), protein.label(), "enabled_by", individual_id);
+                    },
+                    GoCamEnabledBy::Gene(_) => (),
                 }
             }
+        }
 
+        if let GoCamNodeType::Chemical(ref chemical) = node.node_type {
+            let individual_id = node.individual_gocam_id.to_string();
+            let individual_id = Some(individual_id.as_str());
+
+            collector.check(&node.node_id, &node.label, "chemical", individual_id);
+
+            if let Some(ref located_in) = chemical.located_in {
+                collector.check(located_in.id(), located_in.label(),
+                                "chemical.located_in", individual_id);
+            }
+        }
+
+        if let Some(ref happens_during) = node.happens_during {
+            let individual_id = node.individual_gocam_id.to_string();
+            collector.check(happens_during.id(), happens_during.label_or_id(),
+                            "happens_during", Some(individual_id.as_str()));
         }
     }
 
-    ret
+    collector.diagnostics.into_diagnostics()
+}
+
+/// Find the obsolete and unknown terms in every term slot of a gocam-py
+/// model, including complex members, happens_during and evidence codes.
+/// The field of each Diagnostic is the path to the slot, eg.
+/// "activities[3].has_input[0].molecule"
+pub(crate) fn find_obsolete_terms_in_gocam_py(model: &GoCamPyModel,
+                                              ontology_info: &OntologyInfo)
+    -> Vec<Diagnostic>
+{
+    let mut collector = ObsoleteCollector::new(&model.id, ontology_info);
+
+    for title_term_id in title_term_ids(&model.title) {
+        collector.check_id(title_term_id, "title", None);
+    }
+
+    for slot in term_slots(model) {
+        collector.check_id(slot.term_id, &slot.field, Some(slot.individual_id));
+    }
+
+    collector.diagnostics.into_diagnostics()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::BufReader;
+    use std::fs::File;
+
+    use pombase_gocam::gocam_py::gocam_py_parse;
+
+    use crate::diagnostic::DiagnosticKind;
+    use crate::ontology_info::{OntologyInfo, TermInfo, parse_closure};
+
+    use super::{find_obsolete_terms_in_gocam_py, title_term_ids};
+
+    #[test]
+    fn title_term_ids_test() {
+        assert_eq!(title_term_ids("iron import into cell (GO:0033212) / siderophore biosynthetic process (GO:0019290)"),
+                   vec!["GO:0033212", "GO:0019290"]);
+        assert!(title_term_ids("GO:123 something").is_empty());
+    }
+
+    #[test]
+    fn gocam_py_paths_test() {
+        let obo_file = File::open("tests/data/terms.obo").unwrap();
+        let mut obo_buf_reader = BufReader::new(obo_file);
+        let mut ontology_info = OntologyInfo::default();
+        ontology_info.add_terms(crate::ontology_file::parse_obo(&mut obo_buf_reader).unwrap());
+
+        // pretend that a chemical and a CC term are obsolete
+        for id in ["CHEBI:29033", "GO:0005829"] {
+            ontology_info.add_terms(vec![TermInfo {
+                id: id.to_owned(),
+                is_obsolete: true,
+                ..Default::default()
+            }]);
+        }
+
+        // and an evidence code and a phase
+        for id in ["ECO:0000304", "GO:0000084"] {
+            ontology_info.add_terms(vec![TermInfo {
+                id: id.to_owned(),
+                is_obsolete: true,
+                ..Default::default()
+            }]);
+        }
+
+        let mut source = File::open("tests/data/67ae98b500000055.yaml").unwrap();
+        let mut model = gocam_py_parse(&mut source).unwrap();
+
+        model.activities[0].part_of.as_mut().unwrap().happens_during =
+            Some("GO:0000084".to_owned());
+        model.activities[0].enabled_by.members = vec!["GO:0000004".to_owned()];
+
+        let diagnostics = find_obsolete_terms_in_gocam_py(&model, &ontology_info);
+
+        let obsolete_fields: Vec<_> = diagnostics.iter()
+            .filter(|diagnostic| diagnostic.kind == DiagnosticKind::ObsoleteTerm)
+            .map(|diagnostic| diagnostic.field.clone().unwrap())
+            .collect();
+
+        assert!(obsolete_fields.iter().any(|field| field.ends_with(".has_input[0].molecule")));
+        assert!(obsolete_fields.iter().any(|field| field.starts_with("molecules[") &&
+                                           field.ends_with("].located_in.term")));
+        assert!(obsolete_fields.iter().all(|field| !field.contains("molecular_function")));
+        assert!(obsolete_fields.contains(&"activities[0].enabled_by.evidence[0].term".to_owned()));
+        assert!(obsolete_fields.contains(&"activities[0].part_of.happens_during.term".to_owned()));
+        assert!(obsolete_fields.contains(&"activities[0].enabled_by.members[0]".to_owned()));
+    }
+
+    #[test]
+    fn gocam_py_closure_only_test() {
+        let closure_file = File::open("tests/data/closure.tsv").unwrap();
+        let ontology_info = parse_closure(&mut BufReader::new(closure_file)).unwrap();
+
+        let mut source = File::open("tests/data/67ae98b500000055.yaml").unwrap();
+        let model = gocam_py_parse(&mut source).unwrap();
+
+        let diagnostics = find_obsolete_terms_in_gocam_py(&model, &ontology_info);
+
+        let unknown_ids: Vec<_> = diagnostics.iter()
+            .filter(|diagnostic| diagnostic.kind == DiagnosticKind::UnknownTerm)
+            .map(|diagnostic| diagnostic.subject_term.as_ref().unwrap().id.as_str())
+            .collect();
+
+        // GO terms that aren't in the small test closure are still reported
+        assert!(!unknown_ids.is_empty());
+        assert!(unknown_ids.iter().all(|id| id.starts_with("GO:")));
+    }
 }
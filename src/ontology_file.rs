@@ -0,0 +1,423 @@
+use std::{collections::HashMap, io::{BufRead, Read}};
+
+use serde_derive::Deserialize;
+use thiserror::Error;
+
+use crate::ontology_info::{Synonym, TermId, TermInfo};
+
+#[derive(Error, Debug)]
+pub enum OntologyFileError {
+    #[error("I/O error: {0}")]
+    IOError(#[from] std::io::Error),
+    #[error("JSON error: {0}")]
+    JsonError(#[from] serde_json::Error),
+}
+
+const REPLACED_BY_PRED: &str = "http://purl.obolibrary.org/obo/IAO_0100001";
+const CONSIDER_PRED: &str = "http://www.geneontology.org/formats/oboInOwl#consider";
+const ALT_ID_PRED: &str = "http://www.geneontology.org/formats/oboInOwl#hasAlternativeId";
+const NAMESPACE_PRED: &str = "http://www.geneontology.org/formats/oboInOwl#hasOBONamespace";
+
+const IS_A_REL_ID: &str = "rdfs:subClassOf";
+const NEVER_IN_TAXON_REL_ID: &str = "RO:0002161";
+const NEVER_IN_TAXON_PRED: &str = "http://purl.obolibrary.org/obo/RO_0002161";
+
+// "GO:0000001 {source=\"GOC:x\"} ! some name" -> "GO:0000001"
+fn id_value(value: &str) -> TermId {
+    let value = value.split(" !").next().unwrap_or(value);
+    value.split(" {").next().unwrap_or(value).trim().to_owned()
+}
+
+// convert "http://purl.obolibrary.org/obo/GO_0000001" to "GO:0000001",
+// leaving CURIEs alone
+fn id_from_iri(iri: &str) -> TermId {
+    if let Some(local_id) = iri.strip_prefix("http://purl.obolibrary.org/obo/") &&
+        let Some((prefix, local)) = local_id.split_once('_')
+    {
+        format!("{}:{}", prefix, local)
+    } else {
+        iri.to_owned()
+    }
+}
+
+// parse the quoted string at the start of an OBO def or synonym value,
+// returning the string and the rest of the value
+fn quoted_value(value: &str) -> Option<(String, &str)> {
+    let rest = value.strip_prefix('"')?;
+
+    let mut ret = String::new();
+    let mut chars = rest.char_indices();
+
+    while let Some((idx, c)) = chars.next() {
+        match c {
+            '\\' => {
+                if let Some((_, escaped)) = chars.next() {
+                    ret.push(escaped);
+                }
+            },
+            '"' => return Some((ret, rest[idx + 1..].trim_start())),
+            _ => ret.push(c),
+        }
+    }
+
+    None
+}
+
+fn new_term_info(id: &str) -> TermInfo {
+    TermInfo {
+        id: id.to_owned(),
+        ..Default::default()
+    }
+}
+
+// secondary IDs are treated as obsolete terms replaced by the primary ID
+fn alt_id_term_info(alt_id: &str, term_info: &TermInfo) -> TermInfo {
+    TermInfo {
+        id: alt_id.to_owned(),
+        name: term_info.name.clone(),
+        namespace: term_info.namespace.clone(),
+        is_obsolete: true,
+        replaced_by: vec![term_info.id.clone()],
+        ..Default::default()
+    }
+}
+
+enum OboStanza {
+    Term(TermInfo, Vec<TermId>),
+    // the id and the xref of a relation, eg. "part_of" and "BFO:0000050"
+    Typedef(String, Option<String>),
+    Other,
+}
+
+/// Read the [Term] stanzas of an OBO file.  Relation names in
+/// "relationship" tags are converted to IDs using the xrefs of the
+/// [Typedef] stanzas.
+pub(crate) fn parse_obo(buf_reader: &mut dyn BufRead)
+    -> Result<Vec<TermInfo>, OntologyFileError>
+{
+    let mut ret = vec![];
+    let mut rel_ids_by_name = HashMap::new();
+
+    let mut current = OboStanza::Other;
+
+    let mut finish_stanza = |stanza: OboStanza| {
+        match stanza {
+            OboStanza::Term(term_info, alt_ids) => {
+                if !term_info.id.is_empty() {
+                    for alt_id in alt_ids {
+                        ret.push(alt_id_term_info(&alt_id, &term_info));
+                    }
+                    ret.push(term_info);
+                }
+            },
+            OboStanza::Typedef(name, Some(xref)) => {
+                rel_ids_by_name.insert(name, xref);
+            },
+            _ => (),
+        }
+    };
+
+    for line_result in buf_reader.lines() {
+        let line = line_result?;
+        let line = line.trim();
+
+        if line.starts_with('[') {
+            finish_stanza(std::mem::replace(&mut current, OboStanza::Other));
+            if line == "[Term]" {
+                current = OboStanza::Term(new_term_info(""), vec![]);
+            } else if line == "[Typedef]" {
+                current = OboStanza::Typedef(String::new(), None);
+            }
+            continue;
+        }
+
+        let Some((tag, value)) = line.split_once(':')
+        else {
+            continue;
+        };
+
+        let value = value.trim();
+
+        match current {
+            OboStanza::Term(ref mut term_info, ref mut alt_ids) => {
+                match tag {
+                    "id" => term_info.id = value.to_owned(),
+                    "name" => term_info.name = Some(value.to_owned()),
+                    "namespace" => term_info.namespace = Some(value.to_owned()),
+                    "def" => {
+                        if let Some((definition, _)) = quoted_value(value) {
+                            term_info.definition = Some(definition);
+                        }
+                    },
+                    "synonym" => {
+                        if let Some((name, rest)) = quoted_value(value) {
+                            let scope = rest.split_whitespace().next().unwrap_or("RELATED");
+                            term_info.synonyms.push(Synonym {
+                                scope: scope.to_owned(),
+                                name,
+                            });
+                        }
+                    },
+                    "is_a" => {
+                        term_info.parents.push((IS_A_REL_ID.to_owned(), id_value(value)));
+                    },
+                    "relationship" => {
+                        if let Some((rel_name, parent_id)) = value.split_once(' ') {
+                            term_info.parents.push((rel_name.to_owned(), id_value(parent_id)));
+                        }
+                    },
+                    // GO stores never_in_taxon constraints as property
+                    // values, so keep them with the relations
+                    "property_value" => {
+                        if let Some((prop, taxon_id)) = value.split_once(' ') &&
+                            (prop == NEVER_IN_TAXON_REL_ID || prop == "never_in_taxon")
+                        {
+                            term_info.parents.push((NEVER_IN_TAXON_REL_ID.to_owned(),
+                                                    id_value(taxon_id)));
+                        }
+                    },
+                    "is_obsolete" => term_info.is_obsolete = value == "true",
+                    "replaced_by" => term_info.replaced_by.push(id_value(value)),
+                    "consider" => term_info.consider.push(id_value(value)),
+                    "alt_id" => alt_ids.push(id_value(value)),
+                    _ => (),
+                }
+            },
+            OboStanza::Typedef(ref mut name, ref mut xref) => {
+                match tag {
+                    "id" => *name = value.to_owned(),
+                    "xref" => {
+                        if xref.is_none() {
+                            *xref = Some(id_value(value));
+                        }
+                    },
+                    _ => (),
+                }
+            },
+            OboStanza::Other => (),
+        }
+    }
+
+    finish_stanza(current);
+
+    for term_info in ret.iter_mut() {
+        for (rel, _) in term_info.parents.iter_mut() {
+            if let Some(rel_id) = rel_ids_by_name.get(rel.as_str()) {
+                rel.clone_from(rel_id);
+            }
+        }
+    }
+
+    Ok(ret)
+}
+
+#[derive(Deserialize)]
+struct ObographsDocument {
+    #[serde(default)]
+    graphs: Vec<ObographsGraph>,
+}
+
+#[derive(Deserialize)]
+struct ObographsGraph {
+    #[serde(default)]
+    nodes: Vec<ObographsNode>,
+    #[serde(default)]
+    edges: Vec<ObographsEdge>,
+}
+
+#[derive(Deserialize)]
+struct ObographsNode {
+    id: String,
+    lbl: Option<String>,
+    #[serde(rename = "type")]
+    node_type: Option<String>,
+    meta: Option<ObographsMeta>,
+}
+
+#[derive(Deserialize)]
+struct ObographsEdge {
+    sub: String,
+    pred: String,
+    obj: String,
+}
+
+#[derive(Deserialize)]
+struct ObographsMeta {
+    #[serde(default)]
+    deprecated: bool,
+    definition: Option<ObographsDefinition>,
+    #[serde(default)]
+    synonyms: Vec<ObographsSynonym>,
+    #[serde(default, rename = "basicPropertyValues")]
+    basic_property_values: Vec<ObographsPropertyValue>,
+}
+
+#[derive(Deserialize)]
+struct ObographsDefinition {
+    val: String,
+}
+
+#[derive(Deserialize)]
+struct ObographsSynonym {
+    pred: String,
+    val: String,
+}
+
+#[derive(Deserialize)]
+struct ObographsPropertyValue {
+    pred: String,
+    val: String,
+}
+
+fn synonym_scope(pred: &str) -> &str {
+    match pred {
+        "hasExactSynonym" => "EXACT",
+        "hasBroadSynonym" => "BROAD",
+        "hasNarrowSynonym" => "NARROW",
+        _ => "RELATED",
+    }
+}
+
+/// Read the class nodes of an obographs JSON file
+pub(crate) fn parse_obographs_json(reader: &mut dyn Read)
+    -> Result<Vec<TermInfo>, OntologyFileError>
+{
+    let document: ObographsDocument = serde_json::from_reader(reader)?;
+
+    let mut ret = vec![];
+    let mut parents: HashMap<TermId, Vec<(String, TermId)>> = HashMap::new();
+
+    for graph in document.graphs {
+        for edge in graph.edges {
+            let rel_id =
+                if edge.pred == "is_a" {
+                    IS_A_REL_ID.to_owned()
+                } else {
+                    id_from_iri(&edge.pred)
+                };
+            parents.entry(id_from_iri(&edge.sub))
+                .or_default()
+                .push((rel_id, id_from_iri(&edge.obj)));
+        }
+
+        for node in graph.nodes {
+            if node.node_type.as_deref().is_some_and(|node_type| node_type != "CLASS") {
+                continue;
+            }
+
+            let mut term_info = new_term_info(&id_from_iri(&node.id));
+            term_info.name = node.lbl;
+
+            let mut alt_ids = vec![];
+
+            if let Some(meta) = node.meta {
+                term_info.is_obsolete = meta.deprecated;
+                term_info.definition = meta.definition.map(|definition| definition.val);
+                term_info.synonyms = meta.synonyms.into_iter()
+                    .map(|synonym| Synonym {
+                        scope: synonym_scope(&synonym.pred).to_owned(),
+                        name: synonym.val,
+                    })
+                    .collect();
+
+                for property_value in meta.basic_property_values {
+                    match property_value.pred.as_str() {
+                        REPLACED_BY_PRED => term_info.replaced_by.push(id_from_iri(&property_value.val)),
+                        CONSIDER_PRED => term_info.consider.push(id_from_iri(&property_value.val)),
+                        ALT_ID_PRED => alt_ids.push(id_from_iri(&property_value.val)),
+                        NAMESPACE_PRED => term_info.namespace = Some(property_value.val),
+                        NEVER_IN_TAXON_PRED => {
+                            term_info.parents.push((NEVER_IN_TAXON_REL_ID.to_owned(),
+                                                    id_from_iri(&property_value.val)));
+                        },
+                        _ => (),
+                    }
+                }
+            }
+
+            for alt_id in alt_ids {
+                ret.push(alt_id_term_info(&alt_id, &term_info));
+            }
+
+            ret.push(term_info);
+        }
+    }
+
+    for term_info in ret.iter_mut() {
+        if !term_info.is_obsolete &&
+            let Some(term_parents) = parents.remove(&term_info.id)
+        {
+            term_info.parents.extend(term_parents);
+        }
+    }
+
+    Ok(ret)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::BufReader;
+    use std::fs::File;
+
+    use super::{parse_obo, parse_obographs_json};
+
+    #[test]
+    fn parse_obo_test() {
+        let file = File::open("tests/data/terms.obo").unwrap();
+        let mut buf_reader = BufReader::new(file);
+        let terms = parse_obo(&mut buf_reader).unwrap();
+
+        assert_eq!(terms.len(), 5);
+
+        let obsolete = terms.iter().find(|term| term.id == "GO:0000004").unwrap();
+        assert!(obsolete.is_obsolete);
+        assert_eq!(obsolete.replaced_by, vec!["GO:0008150".to_owned()]);
+
+        let alt_id = terms.iter().find(|term| term.id == "GO:0000007").unwrap();
+        assert!(alt_id.is_obsolete);
+        assert_eq!(alt_id.replaced_by, vec!["GO:0003824".to_owned()]);
+
+        let catalytic = terms.iter().find(|term| term.id == "GO:0003824").unwrap();
+        assert_eq!(catalytic.namespace.as_deref(), Some("molecular_function"));
+        assert_eq!(catalytic.definition.as_deref(),
+                   Some("Catalysis of a \"biochemical\" reaction."));
+        assert_eq!(catalytic.synonyms.len(), 1);
+        assert_eq!(catalytic.synonyms[0].scope, "EXACT");
+        assert_eq!(catalytic.synonyms[0].name, "enzyme activity");
+        // the qualifier blocks ("{source=...}") aren't part of the IDs
+        assert_eq!(catalytic.parents,
+                   vec![("rdfs:subClassOf".to_owned(), "GO:0003674".to_owned()),
+                        ("BFO:0000050".to_owned(), "GO:0008150".to_owned())]);
+
+        let mf = terms.iter().find(|term| term.id == "GO:0003674").unwrap();
+        assert_eq!(mf.parents,
+                   vec![("RO:0002160".to_owned(), "NCBITaxon:131567".to_owned())]);
+
+        let bp = terms.iter().find(|term| term.id == "GO:0008150").unwrap();
+        assert_eq!(bp.parents,
+                   vec![("RO:0002161".to_owned(), "NCBITaxon:10239".to_owned())]);
+    }
+
+    #[test]
+    fn parse_obographs_json_test() {
+        let mut file = File::open("tests/data/terms.json").unwrap();
+        let terms = parse_obographs_json(&mut file).unwrap();
+
+        assert_eq!(terms.len(), 3);
+
+        let obsolete = terms.iter().find(|term| term.id == "GO:0000004").unwrap();
+        assert!(obsolete.is_obsolete);
+        assert_eq!(obsolete.name.as_deref(), Some("obsolete biological_process"));
+        assert_eq!(obsolete.replaced_by, vec!["GO:0008150".to_owned()]);
+        assert_eq!(obsolete.consider, vec!["GO:0003674".to_owned()]);
+
+        let mf = terms.iter().find(|term| term.id == "GO:0003674").unwrap();
+        assert_eq!(mf.namespace.as_deref(), Some("molecular_function"));
+        assert_eq!(mf.definition.as_deref(), Some("A molecular process."));
+        assert_eq!(mf.synonyms[0].scope, "EXACT");
+        assert_eq!(mf.synonyms[0].name, "molecular function");
+
+        let bp = terms.iter().find(|term| term.id == "GO:0008150").unwrap();
+        assert_eq!(bp.parents, vec![("BFO:0000050".to_owned(), "GO:0003674".to_owned())]);
+    }
+}
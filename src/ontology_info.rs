@@ -1,4 +1,4 @@
-use std::{collections::{HashMap, HashSet}, io::BufRead};
+use std::{collections::{HashMap, HashSet, VecDeque}, io::BufRead};
 
 use thiserror::Error;
 
@@ -13,16 +13,202 @@ pub enum ClosureError {
 pub type TermId = String;
 pub type RelId = String;
 
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Synonym {
+    // "EXACT", "BROAD", "NARROW" or "RELATED"
+    pub scope: String,
+    pub name: String,
+}
+
+/// Term details read from an OBO or obographs JSON file
+#[derive(Clone, Debug, Default)]
+pub(crate) struct TermInfo {
+    pub id: TermId,
+    pub name: Option<String>,
+    // eg. "molecular_function"
+    pub namespace: Option<String>,
+    pub definition: Option<String>,
+    pub synonyms: Vec<Synonym>,
+    // the direct parents, with is_a as "rdfs:subClassOf" like the closure
+    // file and other relations as IDs (eg. "BFO:0000050") where possible
+    pub parents: Vec<(RelId, TermId)>,
+    pub is_obsolete: bool,
+    pub replaced_by: Vec<TermId>,
+    pub consider: Vec<TermId>,
+}
+
+const IS_A_REL_ID: &str = "rdfs:subClassOf";
+
+pub type InternedId = u32;
+
+/// The (relation, ancestor) pairs of every term in a closure file.  The
+/// IDs are stored once, sorted, and referred to by their index so this is
+/// much smaller than a map of strings.
+#[derive(Default, Debug, PartialEq, Eq)]
+pub(crate) struct TermClosure {
+    // every term and relation ID, sorted so we can binary search
+    pub(crate) ids: Vec<String>,
+    // the (rel, ancestor) pairs of term ids[i] are
+    // parents[parent_offsets[i]..parent_offsets[i + 1]], sorted
+    pub(crate) parent_offsets: Vec<u32>,
+    pub(crate) parents: Vec<(InternedId, InternedId)>,
+}
+
+impl TermClosure {
+    pub fn is_empty(&self) -> bool {
+        self.parents.is_empty()
+    }
+
+    // the number of terms with at least one parent
+    pub fn term_count(&self) -> usize {
+        self.parent_offsets.windows(2)
+            .filter(|offsets| offsets[0] != offsets[1])
+            .count()
+    }
+
+    pub fn intern_id(&self, id: &str) -> Option<InternedId> {
+        self.ids.binary_search_by(|probe| probe.as_str().cmp(id))
+            .ok()
+            .map(|idx| idx as InternedId)
+    }
+
+    /// Return true if any ID in the closure has this prefix, eg. "GO"
+    pub fn has_id_prefix(&self, prefix: &str) -> bool {
+        let prefix = format!("{}:", prefix);
+        let idx = self.ids.partition_point(|id| id.as_str() < prefix.as_str());
+        self.ids.get(idx).is_some_and(|id| id.starts_with(&prefix))
+    }
+
+    pub fn id_str(&self, id: InternedId) -> &str {
+        &self.ids[id as usize]
+    }
+
+    fn interned_parents(&self, id: InternedId) -> &[(InternedId, InternedId)] {
+        let start = self.parent_offsets[id as usize] as usize;
+        let end = self.parent_offsets[id as usize + 1] as usize;
+        &self.parents[start..end]
+    }
+
+    pub fn get_parents<'a>(&'a self, id: &str)
+        -> Option<impl Iterator<Item = (&'a str, &'a str)> + use<'a>>
+    {
+        let parents = self.interned_parents(self.intern_id(id)?);
+
+        if parents.is_empty() {
+            return None;
+        }
+
+        Some(parents.iter()
+             .map(|(rel, parent)| (self.id_str(*rel), self.id_str(*parent))))
+    }
+
+    pub fn has_parent(&self, id: &str, rel: &str, parent_id: &str) -> bool {
+        let (Some(id), Some(rel), Some(parent_id)) =
+            (self.intern_id(id), self.intern_id(rel), self.intern_id(parent_id))
+        else {
+            return false;
+        };
+
+        self.interned_parents(id).binary_search(&(rel, parent_id)).is_ok()
+    }
+}
+
+// collects the lines of a closure file, then sorts the IDs and builds a
+// TermClosure
+#[derive(Default)]
+pub(crate) struct TermClosureBuilder {
+    index: HashMap<String, InternedId>,
+    ids: Vec<String>,
+    term_ids: HashSet<InternedId>,
+    triples: Vec<(InternedId, InternedId, InternedId)>,
+}
+
+impl TermClosureBuilder {
+    fn intern(&mut self, id: &str) -> InternedId {
+        if let Some(interned_id) = self.index.get(id) {
+            return *interned_id;
+        }
+
+        let interned_id = self.ids.len() as InternedId;
+        self.ids.push(id.to_owned());
+        self.index.insert(id.to_owned(), interned_id);
+        interned_id
+    }
+
+    pub fn add(&mut self, subject: &str, rel: &str, object: &str) {
+        let subject = self.intern(subject);
+        let rel = self.intern(rel);
+        let object = self.intern(object);
+
+        self.term_ids.insert(subject);
+        self.term_ids.insert(object);
+
+        self.triples.push((subject, rel, object));
+    }
+
+    // every term is_a itself
+    pub fn build(mut self) -> TermClosure {
+        let is_a = self.intern(IS_A_REL_ID);
+        for term_id in std::mem::take(&mut self.term_ids) {
+            self.triples.push((term_id, is_a, term_id));
+        }
+
+        let mut order: Vec<InternedId> = (0..self.ids.len() as InternedId).collect();
+        order.sort_by(|a, b| self.ids[*a as usize].cmp(&self.ids[*b as usize]));
+
+        let mut new_ids = vec![0; order.len()];
+        for (new_id, old_id) in order.iter().enumerate() {
+            new_ids[*old_id as usize] = new_id as InternedId;
+        }
+
+        let mut triples: Vec<_> = self.triples.into_iter()
+            .map(|(subject, rel, object)| {
+                (new_ids[subject as usize], new_ids[rel as usize], new_ids[object as usize])
+            })
+            .collect();
+        triples.sort_unstable();
+        triples.dedup();
+
+        let mut ids: Vec<Option<String>> = self.ids.into_iter().map(Some).collect();
+        let ids: Vec<String> = order.iter()
+            .map(|old_id| ids[*old_id as usize].take().unwrap())
+            .collect();
+
+        let mut parent_offsets = Vec::with_capacity(ids.len() + 1);
+        let mut triple_idx = 0;
+        for id in 0..ids.len() as InternedId {
+            parent_offsets.push(triple_idx as u32);
+            while triple_idx < triples.len() && triples[triple_idx].0 == id {
+                triple_idx += 1;
+            }
+        }
+        parent_offsets.push(triple_idx as u32);
+
+        TermClosure {
+            ids,
+            parent_offsets,
+            parents: triples.into_iter().map(|(_, rel, object)| (rel, object)).collect(),
+        }
+    }
+}
+
+#[derive(Default)]
 pub(crate) struct OntologyInfo {
-    pub term_parents: HashMap<TermId, HashSet<(RelId, TermId)>>,
+    pub closure: TermClosure,
+    pub terms: HashMap<TermId, TermInfo>,
+    // the ID prefixes (eg. "GO") of the terms from ontology files, so we
+    // don't report CHEBI IDs as unknown when only GO has been read
+    pub term_id_prefixes: HashSet<String>,
+}
+
+fn id_prefix(id: &str) -> &str {
+    id.split(':').next().unwrap_or(id)
 }
 
 pub(crate) fn parse_closure(buf_reader: &mut dyn BufRead)
     -> Result<OntologyInfo, ClosureError>
 {
-    let mut term_parents = HashMap::new();
-
-    let mut all_term_ids = HashSet::new();
+    let mut builder = TermClosureBuilder::default();
 
     for line_result in buf_reader.lines() {
         let line = line_result?;
@@ -36,36 +222,120 @@ pub(crate) fn parse_closure(buf_reader: &mut dyn BufRead)
             });
         }
 
-        let subject = bits[0];
-        all_term_ids.insert(subject.to_owned());
-        let rel = bits[1].to_owned();
-        let object = bits[2];
-        all_term_ids.insert(object.to_owned());
-
-        term_parents.entry(subject.to_owned())
-            .or_insert_with(HashSet::new)
-            .insert((rel, object.to_owned()));
-    }
-
-    for term_id in all_term_ids.into_iter() {
-        term_parents.entry(term_id.clone())
-            .or_insert_with(HashSet::new)
-            .insert(("rdfs:subClassOf".to_owned(), term_id));
+        builder.add(bits[0], bits[1], bits[2]);
     }
 
     Ok(OntologyInfo {
-        term_parents,
+        closure: builder.build(),
+        ..Default::default()
     })
 }
 
 impl OntologyInfo {
-    pub fn get_term_parents(&self, id: &str) -> Option<&HashSet<(RelId, TermId)>> {
-        self.term_parents.get(id)
+    pub fn get_term_parents<'a>(&'a self, id: &str)
+        -> Option<impl Iterator<Item = (&'a str, &'a str)> + use<'a>>
+    {
+        self.closure.get_parents(id)
+    }
+
+    /// Return true if ancestor_id is id or one of its is_a ancestors
+    pub fn is_a(&self, id: &str, ancestor_id: &str) -> bool {
+        id == ancestor_id || self.closure.has_parent(id, IS_A_REL_ID, ancestor_id)
     }
 
+    // the is_a parents of a term that aren't also ancestors of another of
+    // its parents, so this works whether or not the closure is transitive
+    fn direct_is_a_parents(&self, id: &str) -> Vec<&str> {
+        let Some(parents) = self.closure.get_parents(id)
+        else {
+            return vec![];
+        };
+
+        let is_a_ancestors: HashSet<&str> = parents
+            .filter(|(rel, parent_id)| *rel == IS_A_REL_ID && *parent_id != id)
+            .map(|(_, parent_id)| parent_id)
+            .collect();
+
+        is_a_ancestors.iter()
+            .filter(|ancestor_id| {
+                !is_a_ancestors.iter().any(|other_id| {
+                    other_id != *ancestor_id &&
+                        self.closure.has_parent(other_id, IS_A_REL_ID, ancestor_id)
+                })
+            })
+            .cloned()
+            .collect()
+    }
+
+    /// Return the smallest number of is_a steps from a term up to one of
+    /// its ancestors, 0 if the IDs are the same or None if ancestor_id
+    /// isn't an is_a ancestor of id
+    pub fn is_a_distance(&self, id: &str, ancestor_id: &str) -> Option<usize> {
+        if id == ancestor_id {
+            return Some(0);
+        }
+
+        let mut seen = HashSet::new();
+        let mut queue = VecDeque::new();
+        queue.push_back((id, 0));
+
+        while let Some((current_id, distance)) = queue.pop_front() {
+            for parent_id in self.direct_is_a_parents(current_id) {
+                if parent_id == ancestor_id {
+                    return Some(distance + 1);
+                }
+                if seen.insert(parent_id) {
+                    queue.push_back((parent_id, distance + 1));
+                }
+            }
+        }
+
+        None
+    }
+
+    pub fn add_terms(&mut self, terms: Vec<TermInfo>) {
+        for term_info in terms {
+            self.term_id_prefixes.insert(id_prefix(&term_info.id).to_owned());
+            self.terms.insert(term_info.id.clone(), term_info);
+        }
+    }
+
+    pub fn has_closure(&self) -> bool {
+        !self.closure.is_empty()
+    }
+
+    pub fn get_term_info(&self, id: &str) -> Option<&TermInfo> {
+        self.terms.get(id)
+    }
+
+    pub fn term_name(&self, id: &str) -> Option<&str> {
+        self.terms.get(id)?.name.as_deref()
+    }
+
+    pub fn term_namespace(&self, id: &str) -> Option<&str> {
+        self.terms.get(id)?.namespace.as_deref()
+    }
+
+    /// Return true if an ontology file marks the term as obsolete.  Always
+    /// false if only a closure file was read.
     pub fn is_obsolete_term(&self, id: &str) -> bool {
-        // this is a hack:
-        !self.term_parents.contains_key(id)
+        self.terms.get(id).is_some_and(|term_info| term_info.is_obsolete)
+    }
+
+    /// Return true if the term isn't in the ontology files, or if only a
+    /// closure was read, isn't in the closure.  Terms with an ID prefix
+    /// that none of the ontology files (or the closure) use aren't
+    /// unknown, so gene IDs and evidence codes aren't reported.
+    pub fn is_unknown_term(&self, id: &str) -> bool {
+        if self.terms.is_empty() {
+            // this is a hack: the term might be obsolete, mistyped or from
+            // a newer release than the closure
+            self.closure.has_id_prefix(id_prefix(id)) &&
+                self.closure.get_parents(id).is_none()
+        } else {
+            self.term_id_prefixes.contains(id_prefix(id)) &&
+                !self.terms.contains_key(id)
+        }
     }
 }
 
@@ -79,20 +349,59 @@ mod tests {
     fn parse_test() {
         let file = File::open("tests/data/closure.tsv").unwrap();
         let mut buf_reader = BufReader::new(file);
-        let closure = crate::parse_closure(&mut buf_reader).unwrap();
+        let closure = crate::ontology_info::parse_closure(&mut buf_reader).unwrap();
 
-        assert_eq!(closure.term_parents.len(), 29);
+        assert_eq!(closure.closure.term_count(), 29);
 
-        let go_0070858_parents = closure.term_parents.get("GO:0070858").unwrap();
+        let go_0070858_parents: HashSet<_> = closure.get_term_parents("GO:0070858").unwrap()
+            .collect();
         assert_eq!(go_0070858_parents.len(), 2);
 
         let mut expected = HashSet::new();
-        expected.insert(("RO:0002212".to_owned(), "GO:0032787".to_owned()));
-        expected.insert(("rdfs:subClassOf".to_owned(), "GO:0070858".to_owned()));
-        assert_eq!(go_0070858_parents, &expected);
+        expected.insert(("RO:0002212", "GO:0032787"));
+        expected.insert(("rdfs:subClassOf", "GO:0070858"));
+        assert_eq!(go_0070858_parents, expected);
 
         let go_1901953_parents = closure.get_term_parents("GO:1901953").unwrap();
 
-        assert_eq!(go_1901953_parents.len(), 3);
+        assert_eq!(go_1901953_parents.count(), 3);
+    }
+
+    #[test]
+    fn is_a_distance_test() {
+        let file = File::open("tests/data/closure.tsv").unwrap();
+        let mut buf_reader = BufReader::new(file);
+        let closure = crate::ontology_info::parse_closure(&mut buf_reader).unwrap();
+
+        assert_eq!(closure.is_a_distance("GO:0020015", "GO:0020015"), Some(0));
+        assert_eq!(closure.is_a_distance("GO:0042929", "GO:0003824"), Some(1));
+        assert_eq!(closure.is_a_distance("GO:0020015", "GO:0005575"), Some(2));
+        assert_eq!(closure.is_a_distance("GO:0003824", "GO:0042929"), None);
+        // part_of isn't followed
+        assert_eq!(closure.is_a_distance("GO:0034468", "GO:0020015"), None);
+    }
+
+    #[test]
+    fn obsolete_and_unknown_test() {
+        let file = File::open("tests/data/closure.tsv").unwrap();
+        let mut buf_reader = BufReader::new(file);
+        let mut ontology_info = crate::ontology_info::parse_closure(&mut buf_reader).unwrap();
+
+        assert!(!ontology_info.is_obsolete_term("GO:0000004"));
+        assert!(ontology_info.is_unknown_term("GO:0000004"));
+        assert!(!ontology_info.is_unknown_term("GO:0003824"));
+        // the closure has no PomBase or ECO IDs
+        assert!(!ontology_info.is_unknown_term("PomBase:SPAC23G3.02c"));
+        assert!(!ontology_info.is_unknown_term("ECO:0000315"));
+
+        let obo_file = File::open("tests/data/terms.obo").unwrap();
+        let mut obo_buf_reader = BufReader::new(obo_file);
+        let terms = crate::ontology_file::parse_obo(&mut obo_buf_reader).unwrap();
+        ontology_info.add_terms(terms);
+
+        assert!(ontology_info.is_obsolete_term("GO:0000004"));
+        assert!(!ontology_info.is_unknown_term("GO:0000004"));
+        assert!(ontology_info.is_unknown_term("GO:9999999"));
+        assert!(!ontology_info.is_unknown_term("CHEBI:29033"));
     }
 }
@@ -0,0 +1,598 @@
+use std::{collections::{HashMap, HashSet}, io::BufRead};
+
+use thiserror::Error;
+
+use pombase_gocam::gocam_py::gocam_py_parse;
+
+use crate::ontology_info::{OntologyInfo, TermId};
+
+#[derive(Error, Debug)]
+pub enum TermMappingError {
+    #[error("line doesn't have 2 fields: {0}")]
+    FieldCountError(String),
+    #[error("I/O error: {0}")]
+    IOError(#[from] std::io::Error),
+}
+
+#[derive(Error, Debug)]
+pub enum ReplaceTermsError {
+    #[error("failed to parse model before replacing terms: {0}")]
+    ParseBefore(String),
+    #[error("failed to parse model after replacing terms: {0}")]
+    ParseAfter(String),
+}
+
+pub type TermReplacements = HashMap<TermId, TermId>;
+
+/// Read a TSV file of old term ID to new term ID.  Blank lines and lines
+/// starting with "#" are ignored.
+pub(crate) fn parse_term_mapping(buf_reader: &mut dyn BufRead)
+    -> Result<TermReplacements, TermMappingError>
+{
+    let mut ret = HashMap::new();
+
+    for line_result in buf_reader.lines() {
+        let line = line_result?;
+        let line = line.trim_end();
+
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let bits: Vec<_> = line.split("\t").collect();
+
+        if bits.len() < 2 {
+            return Err(TermMappingError::FieldCountError(line.to_owned()));
+        }
+
+        ret.insert(bits[0].trim().to_owned(), bits[1].trim().to_owned());
+    }
+
+    Ok(ret)
+}
+
+/// Make a replacement for each obsolete term that has exactly one
+/// replaced_by term (including secondary IDs)
+pub(crate) fn replacements_from_ontology(ontology_info: &OntologyInfo) -> TermReplacements {
+    ontology_info.terms.values()
+        .filter(|term_info| term_info.is_obsolete && term_info.replaced_by.len() == 1)
+        .map(|term_info| (term_info.id.clone(), term_info.replaced_by[0].clone()))
+        .collect()
+}
+
+// follow chains of replacements, eg. A -> B and B -> C gives C for A
+fn final_replacement<'a>(replacements: &'a TermReplacements, term_id: &str)
+    -> Option<&'a TermId>
+{
+    let mut current = replacements.get(term_id)?;
+    let mut steps = 0;
+
+    while let Some(next) = replacements.get(current.as_str()) {
+        steps += 1;
+        if steps > replacements.len() {
+            // a cycle
+            break;
+        }
+        current = next;
+    }
+
+    Some(current)
+}
+
+/// One changed line of a model file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TermChange {
+    pub line_number: usize,
+    pub old_term_id: TermId,
+    pub new_term_id: TermId,
+}
+
+// split "  - term: 'GO:0000001'\n" into ("  - term: '", "GO:0000001", "'\n")
+fn split_value(line: &str) -> Option<(&str, &str, &str)> {
+    let line_end = line.trim_end_matches(['\r', '\n']);
+    let trimmed = line_end.trim_start().trim_start_matches("- ");
+
+    let (key, value) = trimmed.split_once(": ")?;
+
+    if key != "term" && key != "id" {
+        return None;
+    }
+
+    let value_start = line_end.len() - value.len();
+    let value = value.trim_end();
+    let unquoted = value.trim_matches(['\'', '"']);
+    let unquoted_start = value_start + (value.len() - value.trim_start_matches(['\'', '"']).len());
+    let unquoted_end = unquoted_start + unquoted.len();
+
+    Some((&line[..unquoted_start], &line[unquoted_start..unquoted_end], &line[unquoted_end..]))
+}
+
+// replace IDs in the model title, which can contain several "(GO:...)"
+fn replace_in_title_line(line: &str, replacements: &TermReplacements)
+    -> (String, Vec<(TermId, TermId)>)
+{
+    let mut new_line = String::new();
+    let mut changes = vec![];
+    let mut rest = line;
+
+    while let Some(start) = rest.find("GO:") {
+        let id_len = 3 + rest[start + 3..].chars().take_while(|c| c.is_ascii_digit()).count();
+        let term_id = &rest[start..start + id_len];
+
+        new_line.push_str(&rest[..start]);
+
+        if let Some(new_term_id) = final_replacement(replacements, term_id) {
+            new_line.push_str(new_term_id);
+            changes.push((term_id.to_owned(), new_term_id.to_owned()));
+        } else {
+            new_line.push_str(term_id);
+        }
+
+        rest = &rest[start + id_len..];
+    }
+
+    new_line.push_str(rest);
+
+    (new_line, changes)
+}
+
+// a single quoted YAML scalar, so that names like "[x]" or "a: b" are
+// read back unchanged
+fn yaml_quoted(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
+
+fn is_top_level(line: &str) -> bool {
+    !line.starts_with([' ', '-', '\t', '#', '\r', '\n'])
+}
+
+// the IDs of the entries in "objects" that aren't replaced
+fn kept_object_ids<'a>(text: &'a str, replacements: &TermReplacements) -> HashSet<&'a str> {
+    let mut ret = HashSet::new();
+    let mut in_objects = false;
+
+    for line in text.split_inclusive('\n') {
+        if is_top_level(line) {
+            in_objects = line.starts_with("objects:");
+        } else if in_objects && line.starts_with("- ") &&
+            let Some((_, value, _)) = split_value(line) &&
+            final_replacement(replacements, value).is_none()
+        {
+            ret.insert(value);
+        }
+    }
+
+    ret
+}
+
+// what to do with the label of an object after its ID is replaced
+#[derive(Clone, Copy)]
+enum LabelAction<'a> {
+    Replace(&'a str),
+    // the new term has no name so the old label would be wrong
+    Drop,
+}
+
+/// Replace term IDs in the text of a gocam-py YAML file.  Only the values
+/// of "term" and "id" keys and the IDs in the title are changed, so the
+/// rest of the file is unchanged byte for byte.  If new_term_names has a
+/// name for a replacement term, the "label" of its entry in "objects" is
+/// updated, otherwise the label is removed.  An entry in "objects" whose
+/// new ID already has an entry is removed.
+pub(crate) fn replace_terms_in_yaml(text: &str, replacements: &TermReplacements,
+                                    new_term_names: &HashMap<TermId, String>)
+    -> (String, Vec<TermChange>)
+{
+    let mut new_text = String::with_capacity(text.len());
+    let mut changes = vec![];
+
+    let mut object_ids = kept_object_ids(text, replacements);
+
+    let mut in_title = false;
+    let mut in_objects = false;
+    // set after replacing an object ID, so the following label can be fixed
+    let mut pending_label: Option<LabelAction> = None;
+    // set while skipping the rest of a duplicate entry in "objects"
+    let mut skipping_object = false;
+
+    for (idx, line) in text.split_inclusive('\n').enumerate() {
+        let line_number = idx + 1;
+
+        let is_top_level = is_top_level(line);
+
+        if is_top_level {
+            in_title = line.starts_with("title:");
+            in_objects = line.starts_with("objects:");
+        }
+
+        if skipping_object {
+            if is_top_level || line.starts_with("- ") {
+                skipping_object = false;
+            } else {
+                continue;
+            }
+        }
+
+        if in_title {
+            let (new_line, title_changes) = replace_in_title_line(line, replacements);
+            for (old_term_id, new_term_id) in title_changes {
+                changes.push(TermChange { line_number, old_term_id, new_term_id });
+            }
+            new_text.push_str(&new_line);
+            continue;
+        }
+
+        if let Some(label_action) = pending_label &&
+            let Some((prefix, rest)) = line.split_once("label: ") &&
+            prefix.trim().is_empty()
+        {
+            if let LabelAction::Replace(new_name) = label_action {
+                let line_ending = &rest[rest.trim_end_matches(['\r', '\n']).len()..];
+                new_text.push_str(prefix);
+                new_text.push_str("label: ");
+                new_text.push_str(&yaml_quoted(new_name));
+                new_text.push_str(line_ending);
+            }
+            pending_label = None;
+            continue;
+        }
+
+        if line.trim_start().starts_with("- ") {
+            // a new list item, so any label belongs to something else
+            pending_label = None;
+        }
+
+        if let Some((before, value, after)) = split_value(line) &&
+            let Some(new_term_id) = final_replacement(replacements, value)
+        {
+            changes.push(TermChange {
+                line_number,
+                old_term_id: value.to_owned(),
+                new_term_id: new_term_id.to_owned(),
+            });
+
+            if in_objects {
+                if !object_ids.insert(new_term_id) {
+                    skipping_object = true;
+                    continue;
+                }
+
+                pending_label = Some(match new_term_names.get(new_term_id) {
+                    Some(new_name) => LabelAction::Replace(new_name),
+                    None => LabelAction::Drop,
+                });
+            }
+
+            new_text.push_str(before);
+            new_text.push_str(new_term_id);
+            new_text.push_str(after);
+
+            continue;
+        }
+
+        new_text.push_str(line);
+    }
+
+    (new_text, changes)
+}
+
+/// Replace the terms in a gocam-py YAML file, checking that the file
+/// parses before and after
+pub(crate) fn replace_terms_in_model(text: &str, replacements: &TermReplacements,
+                                     new_term_names: &HashMap<TermId, String>)
+    -> Result<(String, Vec<TermChange>), ReplaceTermsError>
+{
+    gocam_py_parse(&mut text.as_bytes())
+        .map_err(|err| ReplaceTermsError::ParseBefore(err.to_string()))?;
+
+    let (new_text, changes) = replace_terms_in_yaml(text, replacements, new_term_names);
+
+    if !changes.is_empty() {
+        gocam_py_parse(&mut new_text.as_bytes())
+            .map_err(|err| ReplaceTermsError::ParseAfter(err.to_string()))?;
+    }
+
+    Ok((new_text, changes))
+}
+
+const DIFF_CONTEXT: usize = 3;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum DiffOp {
+    Equal,
+    Delete,
+    Insert,
+}
+
+// the shortest edit script that turns old_lines into new_lines, using
+// Myers' algorithm, which is quick when there are few differences
+fn diff_ops(old_lines: &[&str], new_lines: &[&str]) -> Vec<DiffOp> {
+    let old_len = old_lines.len() as isize;
+    let new_len = new_lines.len() as isize;
+    let max_edits = old_lines.len() + new_lines.len();
+    // furthest old line reached on each diagonal k, stored at k + offset
+    let offset = max_edits as isize + 1;
+    let mut furthest = vec![0isize; 2 * max_edits + 3];
+    let mut trace = vec![];
+
+    let prev_diagonal = |furthest: &[isize], k: isize, edits: isize| {
+        let idx = (k + offset) as usize;
+        if k == -edits || (k != edits && furthest[idx - 1] < furthest[idx + 1]) {
+            k + 1
+        } else {
+            k - 1
+        }
+    };
+
+    'edits: for edits in 0..=max_edits as isize {
+        trace.push(furthest.clone());
+
+        for k in (-edits..=edits).step_by(2) {
+            let prev_k = prev_diagonal(&furthest, k, edits);
+            let mut x = furthest[(prev_k + offset) as usize];
+            if prev_k < k {
+                x += 1;
+            }
+            let mut y = x - k;
+
+            while x < old_len && y < new_len && old_lines[x as usize] == new_lines[y as usize] {
+                x += 1;
+                y += 1;
+            }
+
+            furthest[(k + offset) as usize] = x;
+
+            if x >= old_len && y >= new_len {
+                break 'edits;
+            }
+        }
+    }
+
+    let mut ops = vec![];
+    let (mut x, mut y) = (old_len, new_len);
+
+    for (edits, furthest) in trace.iter().enumerate().rev() {
+        let edits = edits as isize;
+        let prev_k = prev_diagonal(furthest, x - y, edits);
+        let prev_x = furthest[(prev_k + offset) as usize];
+        let prev_y = prev_x - prev_k;
+
+        while x > prev_x && y > prev_y {
+            ops.push(DiffOp::Equal);
+            x -= 1;
+            y -= 1;
+        }
+
+        if edits > 0 {
+            ops.push(if x == prev_x { DiffOp::Insert } else { DiffOp::Delete });
+        }
+
+        x = prev_x;
+        y = prev_y;
+    }
+
+    ops.reverse();
+    ops
+}
+
+/// Make a unified diff of two versions of a file
+pub(crate) fn unified_diff(path: &str, old_text: &str, new_text: &str) -> String {
+    let old_lines: Vec<_> = old_text.split_inclusive('\n').collect();
+    let new_lines: Vec<_> = new_text.split_inclusive('\n').collect();
+
+    let ops = diff_ops(&old_lines, &new_lines);
+
+    let changed: Vec<usize> = (0..ops.len())
+        .filter(|idx| ops[*idx] != DiffOp::Equal)
+        .collect();
+
+    if changed.is_empty() {
+        return String::new();
+    }
+
+    let mut ret = format!("--- a/{}\n+++ b/{}\n", path, path);
+
+    // the position in each file before each op
+    let mut positions = Vec::with_capacity(ops.len() + 1);
+    let (mut old_idx, mut new_idx) = (0, 0);
+    for op in &ops {
+        positions.push((old_idx, new_idx));
+        if *op != DiffOp::Insert {
+            old_idx += 1;
+        }
+        if *op != DiffOp::Delete {
+            new_idx += 1;
+        }
+    }
+    positions.push((old_idx, new_idx));
+
+    // group changes whose contexts overlap into hunks of ops
+    let mut hunks: Vec<(usize, usize)> = vec![];
+    for idx in changed.iter().cloned() {
+        let start = idx.saturating_sub(DIFF_CONTEXT);
+        let end = (idx + DIFF_CONTEXT + 1).min(ops.len());
+        if let Some(last) = hunks.last_mut() && start <= last.1 {
+            last.1 = end;
+        } else {
+            hunks.push((start, end));
+        }
+    }
+
+    let with_newline = |line: &str| {
+        if line.ends_with('\n') {
+            line.to_owned()
+        } else {
+            format!("{}\n\\ No newline at end of file\n", line)
+        }
+    };
+
+    // an empty range starts at the line before it
+    let range = |start: usize, end: usize| {
+        let len = end - start;
+        format!("{},{}", if len == 0 { start } else { start + 1 }, len)
+    };
+
+    for (start, end) in hunks {
+        let (old_start, new_start) = positions[start];
+        let (old_end, new_end) = positions[end];
+        ret.push_str(&format!("@@ -{} +{} @@\n", range(old_start, old_end),
+                              range(new_start, new_end)));
+
+        for (op, (old_idx, new_idx)) in ops[start..end].iter().zip(&positions[start..end]) {
+            match op {
+                DiffOp::Equal => {
+                    ret.push(' ');
+                    ret.push_str(&with_newline(old_lines[*old_idx]));
+                },
+                DiffOp::Delete => {
+                    ret.push('-');
+                    ret.push_str(&with_newline(old_lines[*old_idx]));
+                },
+                DiffOp::Insert => {
+                    ret.push('+');
+                    ret.push_str(&with_newline(new_lines[*new_idx]));
+                },
+            }
+        }
+    }
+
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::fs::read_to_string;
+
+    use pombase_gocam::gocam_py::gocam_py_parse;
+
+    use super::{parse_term_mapping, replace_terms_in_model, replace_terms_in_yaml, unified_diff};
+
+    #[test]
+    fn parse_mapping_test() {
+        let mapping_text = "# old\tnew\nGO:0005829\tGO:0005737\n\nGO:0000007\tGO:0003824\n";
+        let mapping = parse_term_mapping(&mut mapping_text.as_bytes()).unwrap();
+        assert_eq!(mapping.len(), 2);
+        assert_eq!(mapping.get("GO:0005829").unwrap(), "GO:0005737");
+
+        assert!(parse_term_mapping(&mut "GO:0005829\n".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn replace_test() {
+        let text = read_to_string("tests/data/67ae98b500000055.yaml").unwrap();
+
+        let mut replacements = HashMap::new();
+        replacements.insert("GO:0005829".to_owned(), "GO:0005737".to_owned());
+        replacements.insert("GO:0033212".to_owned(), "GO:0033213".to_owned());
+        // chains are followed
+        replacements.insert("GO:0033213".to_owned(), "GO:0033214".to_owned());
+
+        let mut new_term_names = HashMap::new();
+        new_term_names.insert("GO:0005737".to_owned(), "cytoplasm".to_owned());
+
+        let (new_text, changes) =
+            replace_terms_in_model(&text, &replacements, &new_term_names).unwrap();
+
+        // 9 "term:" lines, one object ID and the title
+        assert_eq!(changes.len(), 11);
+        assert!(!new_text.contains("GO:0005829"));
+        assert!(new_text.contains("iron import into cell (GO:0033214)"));
+        assert!(new_text.contains("- id: GO:0005737\n  label: 'cytoplasm'\n"));
+        assert!(!new_text.contains("label: cytosol"));
+
+        let old_lines: Vec<_> = text.split_inclusive('\n').collect();
+        let new_lines: Vec<_> = new_text.split_inclusive('\n').collect();
+        assert_eq!(old_lines.len(), new_lines.len());
+        let changed_line_count = old_lines.iter().zip(new_lines.iter())
+            .filter(|(old, new)| old != new)
+            .count();
+        // the label line changes too
+        assert_eq!(changed_line_count, 12);
+    }
+
+    #[test]
+    fn no_replacements_test() {
+        let text = "id: gomodel:1\nactivities:\n- id: gomodel:1/2\n  molecular_function:\n    term: 'GO:0003824'\n";
+        let replacements = HashMap::new();
+        let (new_text, changes) = replace_terms_in_yaml(text, &replacements, &HashMap::new());
+        assert_eq!(new_text, text);
+        assert!(changes.is_empty());
+    }
+
+    #[test]
+    fn quoted_value_and_diff_test() {
+        let text = "id: gomodel:1\nactivities:\n- id: gomodel:1/2\n  molecular_function:\n    term: 'GO:0000007'\n  enabled_by:\n    term: PomBase:SPAC1F7.07c\n";
+        let mut replacements = HashMap::new();
+        replacements.insert("GO:0000007".to_owned(), "GO:0003824".to_owned());
+
+        let (new_text, changes) = replace_terms_in_yaml(text, &replacements, &HashMap::new());
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].line_number, 5);
+        assert!(new_text.contains("    term: 'GO:0003824'\n"));
+
+        let diff = unified_diff("model.yaml", text, &new_text);
+        assert_eq!(diff, "--- a/model.yaml\n+++ b/model.yaml\n\
+                          @@ -2,6 +2,6 @@\n \
+                          activities:\n \
+                          - id: gomodel:1/2\n   \
+                          molecular_function:\n\
+                          -    term: 'GO:0000007'\n\
+                          +    term: 'GO:0003824'\n   \
+                          enabled_by:\n     \
+                          term: PomBase:SPAC1F7.07c\n");
+    }
+
+    #[test]
+    fn quoted_label_test() {
+        let text = read_to_string("tests/data/67ae98b500000055.yaml").unwrap();
+
+        let mut replacements = HashMap::new();
+        replacements.insert("GO:0005829".to_owned(), "GO:0005737".to_owned());
+
+        let new_name = "[cytoplasm]: the cell's #1 part";
+        let mut new_term_names = HashMap::new();
+        new_term_names.insert("GO:0005737".to_owned(), new_name.to_owned());
+
+        let (new_text, _) =
+            replace_terms_in_model(&text, &replacements, &new_term_names).unwrap();
+
+        assert!(new_text.contains("  label: '[cytoplasm]: the cell''s #1 part'\n"));
+
+        let model = gocam_py_parse(&mut new_text.as_bytes()).unwrap();
+        let object = model.objects.iter()
+            .find(|object| object.id == "GO:0005737")
+            .unwrap();
+        assert_eq!(object.label.as_deref(), Some(new_name));
+    }
+
+    #[test]
+    fn object_labels_and_duplicates_test() {
+        let text = read_to_string("tests/data/67ae98b500000055.yaml").unwrap();
+
+        let mut replacements = HashMap::new();
+        // GO:0033214 already has an entry in "objects"
+        replacements.insert("GO:0005829".to_owned(), "GO:0033214".to_owned());
+        // a term with no name in the ontology
+        replacements.insert("GO:0031169".to_owned(), "GO:9999999".to_owned());
+
+        let (new_text, changes) =
+            replace_terms_in_model(&text, &replacements, &HashMap::new()).unwrap();
+
+        // 12 "term:" lines and two object IDs
+        assert_eq!(changes.len(), 14);
+        assert_eq!(new_text.matches("- id: GO:0033214\n").count(), 1);
+        assert!(!new_text.contains("label: cytosol"));
+        assert!(new_text.contains("- id: GO:9999999\n  type: gocam:Object\n"));
+        assert!(!new_text.contains("ferrichrome biosynthetic process"));
+
+        let old_line_count = text.split_inclusive('\n').count();
+        assert_eq!(new_text.split_inclusive('\n').count(), old_line_count - 4);
+
+        let diff = unified_diff("model.yaml", &text, &new_text);
+        assert!(diff.contains("@@ -2855,12 +2855,8 @@\n"));
+        assert!(diff.contains("\n-  label: ferrichrome biosynthetic process\n"));
+        assert!(diff.contains("\n-  label: cytosol\n"));
+        assert!(diff.contains("\n+- id: GO:9999999\n"));
+    }
+}
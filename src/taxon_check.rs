@@ -0,0 +1,406 @@
+use std::collections::{HashMap, HashSet};
+
+use pombase_gocam::{GoCamModel, GoCamNodeType, gocam_py::GoCamPyModel};
+
+use crate::diagnostic::{Diagnostic, DiagnosticCollector, DiagnosticKind, DiagnosticRelation,
+                        DiagnosticTerm, Severity};
+use crate::gocam_py_terms::{SlotKind, term_slots};
+use crate::ontology_info::{OntologyInfo, TermId};
+
+const ONLY_IN_TAXON_REL_ID: &str = "RO:0002160";
+const NEVER_IN_TAXON_REL_ID: &str = "RO:0002161";
+
+// constraints are inherited over these relations
+const INHERITING_REL_IDS: [&str; 2] = ["rdfs:subClassOf", "BFO:0000050"];
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum TaxonConstraintType {
+    OnlyIn,
+    NeverIn,
+}
+
+#[derive(Clone, Debug)]
+struct TaxonConstraint {
+    constraint_type: TaxonConstraintType,
+    taxon_id: TermId,
+    // the term in the ontology that has the constraint: the term itself
+    // or one of its ancestors
+    source_term_id: TermId,
+}
+
+impl TaxonConstraint {
+    fn relation(&self) -> DiagnosticRelation {
+        let (id, name) = match self.constraint_type {
+            TaxonConstraintType::OnlyIn => (ONLY_IN_TAXON_REL_ID, "only_in_taxon"),
+            TaxonConstraintType::NeverIn => (NEVER_IN_TAXON_REL_ID, "never_in_taxon"),
+        };
+
+        DiagnosticRelation {
+            id: id.to_owned(),
+            name: name.to_owned(),
+        }
+    }
+}
+
+fn constraint_type(rel: &str) -> Option<TaxonConstraintType> {
+    match rel {
+        ONLY_IN_TAXON_REL_ID | "only_in_taxon" => Some(TaxonConstraintType::OnlyIn),
+        NEVER_IN_TAXON_REL_ID | "never_in_taxon" => Some(TaxonConstraintType::NeverIn),
+        _ => None,
+    }
+}
+
+// the term and all its is_a and part_of ancestors, from the closure file
+// and the parents read from the ontology files
+fn self_and_ancestors<'a>(ontology_info: &'a OntologyInfo, term_id: &'a str)
+    -> HashSet<&'a str>
+{
+    let mut ret = HashSet::new();
+    ret.insert(term_id);
+
+    if let Some(closure_parents) = ontology_info.get_term_parents(term_id) {
+        for (rel, parent_id) in closure_parents {
+            if INHERITING_REL_IDS.contains(&rel) {
+                ret.insert(parent_id);
+            }
+        }
+    }
+
+    let mut queue: Vec<&str> = ret.iter().cloned().collect();
+
+    while let Some(current_id) = queue.pop() {
+        let Some(term_info) = ontology_info.get_term_info(current_id)
+        else {
+            continue;
+        };
+
+        for (rel, parent_id) in &term_info.parents {
+            if INHERITING_REL_IDS.contains(&rel.as_str()) && ret.insert(parent_id.as_str()) {
+                queue.push(parent_id.as_str());
+            }
+        }
+    }
+
+    ret
+}
+
+fn taxon_constraints(ontology_info: &OntologyInfo, term_id: &str) -> Vec<TaxonConstraint> {
+    let mut ret: Vec<_> = self_and_ancestors(ontology_info, term_id).into_iter()
+        .filter_map(|ancestor_id| ontology_info.get_term_info(ancestor_id))
+        .flat_map(|term_info| {
+            term_info.parents.iter()
+                .filter_map(|(rel, taxon_id)| {
+                    Some(TaxonConstraint {
+                        constraint_type: constraint_type(rel)?,
+                        taxon_id: taxon_id.clone(),
+                        source_term_id: term_info.id.clone(),
+                    })
+                })
+        })
+        .collect();
+
+    ret.sort_by(|a, b| {
+        (&a.source_term_id, &a.taxon_id).cmp(&(&b.source_term_id, &b.taxon_id))
+    });
+
+    ret
+}
+
+// model taxons are sometimes stored without the prefix
+fn normalise_taxon_id(taxon: &str) -> String {
+    if taxon.contains(':') {
+        taxon.to_owned()
+    } else {
+        format!("NCBITaxon:{}", taxon)
+    }
+}
+
+struct TaxonCollector<'a> {
+    model_id: &'a str,
+    model_taxon: String,
+    ontology_info: &'a OntologyInfo,
+    taxonomy: &'a OntologyInfo,
+    constraints_cache: HashMap<String, Vec<TaxonConstraint>>,
+    diagnostics: DiagnosticCollector<(String, String, String, String)>,
+}
+
+impl<'a> TaxonCollector<'a> {
+    fn new(model_id: &'a str, model_taxon: String, ontology_info: &'a OntologyInfo,
+           taxonomy: &'a OntologyInfo)
+        -> TaxonCollector<'a>
+    {
+        TaxonCollector {
+            model_id,
+            model_taxon,
+            ontology_info,
+            taxonomy,
+            constraints_cache: HashMap::new(),
+            diagnostics: DiagnosticCollector::default(),
+        }
+    }
+
+    fn is_violated(&self, constraint: &TaxonConstraint) -> bool {
+        let in_taxon = self.taxonomy.is_a(&self.model_taxon, &constraint.taxon_id);
+
+        match constraint.constraint_type {
+            TaxonConstraintType::OnlyIn => !in_taxon,
+            TaxonConstraintType::NeverIn => in_taxon,
+        }
+    }
+
+    fn check(&mut self, term_id: &str, term_name: &str, field: &str, individual_id: &str) {
+        let ontology_info = self.ontology_info;
+        let constraints = self.constraints_cache.entry(term_id.to_owned())
+            .or_insert_with(|| taxon_constraints(ontology_info, term_id))
+            .clone();
+
+        for constraint in constraints {
+            if !self.is_violated(&constraint) {
+                continue;
+            }
+
+            let key = (term_id.to_owned(), field.to_owned(),
+                       constraint.source_term_id.clone(), constraint.taxon_id.clone());
+
+            let model_id = self.model_id;
+            let model_taxon = &self.model_taxon;
+            let taxonomy = self.taxonomy;
+
+            self.diagnostics.add(key, Some(individual_id), || {
+                let mut diagnostic =
+                    Diagnostic::new(DiagnosticKind::TaxonConstraintViolation, Severity::Error,
+                                    model_id);
+                diagnostic.subject_term = Some(DiagnosticTerm {
+                    id: term_id.to_owned(),
+                    name: term_name.to_owned(),
+                });
+                diagnostic.object_term = Some(DiagnosticTerm {
+                    id: constraint.taxon_id.clone(),
+                    name: taxonomy.term_name(&constraint.taxon_id)
+                        .unwrap_or_default().to_owned(),
+                });
+                diagnostic.relation = Some(constraint.relation());
+                diagnostic.rule_source = Some(constraint.source_term_id.clone());
+                diagnostic.field = Some(field.to_owned());
+                diagnostic.taxon = Some(model_taxon.clone());
+                diagnostic
+            });
+        }
+    }
+
+    fn check_id(&mut self, term_id: &str, field: &str, individual_id: &str) {
+        let ontology_info = self.ontology_info;
+        let term_name = ontology_info.term_name(term_id).unwrap_or_default();
+        self.check(term_id, term_name, field, individual_id);
+    }
+}
+
+// None if the model has no taxon or the taxon isn't in the taxonomy
+// closure, in which case we can't check anything
+fn model_taxon(taxon: &str, taxonomy: &OntologyInfo) -> Option<String> {
+    if taxon.is_empty() {
+        return None;
+    }
+
+    let taxon = normalise_taxon_id(taxon);
+
+    if taxonomy.get_term_parents(&taxon).is_some() {
+        Some(taxon)
+    } else {
+        None
+    }
+}
+
+/// Find terms with only_in_taxon or never_in_taxon constraints (on the
+/// term or an ancestor) that the model's taxon doesn't satisfy.  The
+/// taxonomy is an NCBITaxon closure file, read with parse_closure().
+pub(crate) fn find_taxon_violations(model: &GoCamModel, ontology_info: &OntologyInfo,
+                                    taxonomy: &OntologyInfo)
+    -> Vec<Diagnostic>
+{
+    let Some(taxon) = model_taxon(model.taxon(), taxonomy)
+    else {
+        return vec![];
+    };
+
+    let mut collector = TaxonCollector::new(model.id(), taxon, ontology_info, taxonomy);
+
+    for (_, node) in model.node_iterator() {
+        let individual_id = node.individual_gocam_id.to_string();
+
+        if node.is_activity() {
+            collector.check(&node.node_id, &node.label, "molecular_function", &individual_id);
+
+            for occurs_in in node.occurs_in.iter() {
+                collector.check(occurs_in.id(), occurs_in.label(), "occurs_in", &individual_id);
+            }
+
+            if let Some(ref part_of_process) = node.part_of_process {
+                collector.check(part_of_process.id(), &part_of_process.label,
+                                "part_of_process", &individual_id);
+            }
+        }
+
+        if let GoCamNodeType::Chemical(ref chemical) = node.node_type &&
+            let Some(ref located_in) = chemical.located_in
+        {
+            collector.check(located_in.id(), located_in.label(), "chemical.located_in",
+                            &individual_id);
+        }
+
+        if let Some(ref happens_during) = node.happens_during {
+            collector.check(happens_during.id(), happens_during.label_or_id(),
+                            "happens_during", &individual_id);
+        }
+    }
+
+    collector.diagnostics.into_diagnostics()
+}
+
+/// The same as find_taxon_violations() but with the field paths of the
+/// gocam-py model
+pub(crate) fn find_taxon_violations_in_gocam_py(model: &GoCamPyModel,
+                                                ontology_info: &OntologyInfo,
+                                                taxonomy: &OntologyInfo)
+    -> Vec<Diagnostic>
+{
+    let Some(taxon) = model_taxon(model.taxon.as_deref().unwrap_or_default(), taxonomy)
+    else {
+        return vec![];
+    };
+
+    let mut collector = TaxonCollector::new(&model.id, taxon, ontology_info, taxonomy);
+
+    // evidence codes don't have taxon constraints
+    for slot in term_slots(model) {
+        if slot.kind != SlotKind::Evidence {
+            collector.check_id(slot.term_id, &slot.field, slot.individual_id);
+        }
+    }
+
+    collector.diagnostics.into_diagnostics()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::BufReader;
+    use std::fs::File;
+
+    use pombase_gocam::gocam_py::gocam_py_parse;
+
+    use crate::diagnostic::DiagnosticKind;
+    use crate::ontology_info::{OntologyInfo, TermInfo, parse_closure};
+
+    use super::find_taxon_violations_in_gocam_py;
+
+    fn term_info(id: &str, name: &str, parents: &[(&str, &str)]) -> TermInfo {
+        TermInfo {
+            id: id.to_owned(),
+            name: Some(name.to_owned()),
+            parents: parents.iter()
+                .map(|(rel, parent_id)| (rel.to_string(), parent_id.to_string()))
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn taxon_test() {
+        let taxonomy_file = File::open("tests/data/taxonomy_closure.tsv").unwrap();
+        let mut taxonomy_reader = BufReader::new(taxonomy_file);
+        let taxonomy = parse_closure(&mut taxonomy_reader).unwrap();
+
+        let mut ontology_info = OntologyInfo::default();
+        ontology_info.add_terms(vec![
+            // plant only
+            term_info("GO:0042929", "ferrichrome transmembrane transporter activity",
+                      &[("RO:0002160", "NCBITaxon:33090")]),
+            // inherited from a parent
+            term_info("GO:0005886", "plasma membrane", &[("rdfs:subClassOf", "GO:0016020")]),
+            term_info("GO:0016020", "membrane", &[("RO:0002161", "NCBITaxon:4751")]),
+            // satisfied
+            term_info("GO:0033214", "siderophore-iron import into cell", &[("RO:0002160", "NCBITaxon:4895")]),
+        ]);
+
+        let mut source = File::open("tests/data/67ae98b500000055.yaml").unwrap();
+        let model = gocam_py_parse(&mut source).unwrap();
+
+        let diagnostics = find_taxon_violations_in_gocam_py(&model, &ontology_info, &taxonomy);
+
+        assert!(diagnostics.iter()
+                .all(|diagnostic| diagnostic.kind == DiagnosticKind::TaxonConstraintViolation));
+
+        let mf_diagnostic = diagnostics.iter()
+            .find(|diagnostic| diagnostic.subject_term_id() == "GO:0042929")
+            .unwrap();
+        assert_eq!(mf_diagnostic.field.as_deref(), Some("activities[0].molecular_function.term"));
+        assert_eq!(mf_diagnostic.to_string(),
+                   "activities[0].molecular_function.term GO:0042929 ferrichrome transmembrane transporter activity not valid for NCBITaxon:4896: only_in_taxon NCBITaxon:33090");
+
+        let cc_diagnostic = diagnostics.iter()
+            .find(|diagnostic| diagnostic.subject_term_id() == "GO:0005886")
+            .unwrap();
+        assert_eq!(cc_diagnostic.rule_source.as_deref(), Some("GO:0016020"));
+        assert!(cc_diagnostic.to_string()
+                .ends_with("never_in_taxon NCBITaxon:4751 (from GO:0016020)"));
+
+        assert!(diagnostics.iter()
+                .all(|diagnostic| diagnostic.subject_term_id() != "GO:0033214"));
+    }
+
+    #[test]
+    fn taxon_happens_during_and_members_test() {
+        let taxonomy_file = File::open("tests/data/taxonomy_closure.tsv").unwrap();
+        let mut taxonomy_reader = BufReader::new(taxonomy_file);
+        let taxonomy = parse_closure(&mut taxonomy_reader).unwrap();
+
+        let mut ontology_info = OntologyInfo::default();
+        ontology_info.add_terms(vec![
+            term_info("GO:0000084", "mitotic S phase", &[("RO:0002161", "NCBITaxon:4751")]),
+            term_info("PomBase:SPAC23G3.02c", "sib1", &[("RO:0002160", "NCBITaxon:33090")]),
+        ]);
+
+        let mut source = File::open("tests/data/67ae98b500000055.yaml").unwrap();
+        let mut model = gocam_py_parse(&mut source).unwrap();
+
+        model.activities[0].part_of.as_mut().unwrap().happens_during =
+            Some("GO:0000084".to_owned());
+        model.activities[0].enabled_by.members = vec!["PomBase:SPAC23G3.02c".to_owned()];
+
+        let diagnostics = find_taxon_violations_in_gocam_py(&model, &ontology_info, &taxonomy);
+
+        let fields: Vec<_> = diagnostics.iter()
+            .map(|diagnostic| diagnostic.field.as_deref().unwrap())
+            .collect();
+        assert!(fields.contains(&"activities[0].part_of.happens_during.term"));
+        assert!(fields.contains(&"activities[0].enabled_by.members[0]"));
+    }
+
+    #[test]
+    fn taxon_not_inherited_over_regulates_test() {
+        let taxonomy_file = File::open("tests/data/taxonomy_closure.tsv").unwrap();
+        let mut taxonomy_reader = BufReader::new(taxonomy_file);
+        let taxonomy = parse_closure(&mut taxonomy_reader).unwrap();
+
+        let mut closure_reader =
+            "GO:0033214\tRO:0002211\tGO:9000001\nGO:0033214\trdfs:subClassOf\tGO:9000002\n"
+            .as_bytes();
+        let mut ontology_info = parse_closure(&mut closure_reader).unwrap();
+        ontology_info.add_terms(vec![
+            term_info("GO:9000001", "regulated process", &[("RO:0002161", "NCBITaxon:4751")]),
+            term_info("GO:9000002", "parent process", &[("RO:0002161", "NCBITaxon:4751")]),
+        ]);
+
+        let mut source = File::open("tests/data/67ae98b500000055.yaml").unwrap();
+        let model = gocam_py_parse(&mut source).unwrap();
+
+        let diagnostics = find_taxon_violations_in_gocam_py(&model, &ontology_info, &taxonomy);
+
+        // the constraint on the is_a parent applies but the constraint on
+        // the regulated process doesn't
+        assert!(diagnostics.iter()
+                .any(|diagnostic| diagnostic.rule_source.as_deref() == Some("GO:9000002")));
+        assert!(diagnostics.iter()
+                .all(|diagnostic| diagnostic.rule_source.as_deref() != Some("GO:9000001")));
+    }
+}
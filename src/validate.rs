@@ -0,0 +1,321 @@
+use std::{io::Write, path::Path};
+
+use itertools::Itertools;
+use serde_derive::Serialize;
+
+use pombase_gocam::{GoCamModel, gocam_py::GoCamPyModel, raw::{GoCamRawModel, gocam_parse_raw}};
+use pombase_gocam_process::{GoCamMissing, GoCamMissingType, find_detached_chemicals,
+                            find_detached_genes, find_holes, find_missing,
+                            find_missing_evidence};
+
+use crate::{allowed_relation_check::check_relations,
+            aspect_check::{find_aspect_mismatches, find_aspect_mismatches_in_gocam_py},
+            allowed_relation_config::AllowedRelConfig,
+            diagnostic::{Diagnostic, Severity},
+            model_cache::ModelCache,
+            model_loader::{InputFormat, ModelLoadError, parse_gocam_py_contents,
+                           read_model_source},
+            model_source::ModelSource,
+            obsolete_terms::{find_obsolete_terms, find_obsolete_terms_in_gocam_py},
+            ontology_info::OntologyInfo,
+            taxon_check::{find_taxon_violations, find_taxon_violations_in_gocam_py}};
+
+/// One problem found by one of the QC checks
+#[derive(Serialize, Clone, Debug)]
+pub struct ValidationIssue {
+    pub model_id: String,
+    pub check: String,
+    pub severity: Severity,
+    pub individual_id: Option<String>,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub diagnostic: Option<Diagnostic>,
+}
+
+/// A model parsed once into every representation that the checks need.
+/// The raw Minerva model is only available for JSON files and the
+/// gocam-py model only for YAML files.
+pub(crate) struct LoadedModel {
+    pub model: GoCamModel,
+    pub raw_model: Option<GoCamRawModel>,
+    pub gocam_py_model: Option<GoCamPyModel>,
+}
+
+pub(crate) fn load_model(source: &ModelSource, input_format: Option<InputFormat>,
+                         cache: Option<&ModelCache>)
+    -> Result<LoadedModel, ModelLoadError>
+{
+    let path = source.name();
+    let (format, contents) = read_model_source(source, input_format)?;
+
+    if !format.is_gocam_py() {
+        let raw_model = gocam_parse_raw(&mut contents.as_slice())
+            .map_err(|err| ModelLoadError::parse_error(path, err))?;
+        let model = GoCamModel::new_from_raw(raw_model.clone());
+
+        Ok(LoadedModel {
+            model,
+            raw_model: Some(raw_model),
+            gocam_py_model: None,
+        })
+    } else {
+        // the cache is keyed by input_format, like in the other commands
+        let gocam_py_model = parse_gocam_py_contents(path, input_format, &contents, cache)?;
+        let model = GoCamModel::new_from_gocam_py(gocam_py_model.clone());
+
+        Ok(LoadedModel {
+            model,
+            raw_model: None,
+            gocam_py_model: Some(gocam_py_model),
+        })
+    }
+}
+
+/// Check the terms of a model, using the gocam-py model if there is one
+/// because it has the most detailed field paths
+pub(crate) fn find_obsolete_terms_in_loaded_model(loaded_model: &LoadedModel,
+                                                  ontology_info: &OntologyInfo)
+    -> Vec<Diagnostic>
+{
+    if let Some(ref gocam_py_model) = loaded_model.gocam_py_model {
+        find_obsolete_terms_in_gocam_py(gocam_py_model, ontology_info)
+    } else {
+        find_obsolete_terms(&loaded_model.model, ontology_info)
+    }
+}
+
+/// Check the GO aspects of the terms in each slot of a model, using the
+/// gocam-py model if there is one
+pub(crate) fn find_aspect_mismatches_in_loaded_model(loaded_model: &LoadedModel,
+                                                     ontology_info: &OntologyInfo)
+    -> Vec<Diagnostic>
+{
+    if let Some(ref gocam_py_model) = loaded_model.gocam_py_model {
+        find_aspect_mismatches_in_gocam_py(gocam_py_model, ontology_info)
+    } else {
+        find_aspect_mismatches(&loaded_model.model, ontology_info)
+    }
+}
+
+/// Check the terms of a model against the taxon constraints in the
+/// ontology, using the gocam-py model if there is one
+pub(crate) fn find_taxon_violations_in_loaded_model(loaded_model: &LoadedModel,
+                                                    ontology_info: &OntologyInfo,
+                                                    taxonomy: &OntologyInfo)
+    -> Vec<Diagnostic>
+{
+    if let Some(ref gocam_py_model) = loaded_model.gocam_py_model {
+        find_taxon_violations_in_gocam_py(gocam_py_model, ontology_info, taxonomy)
+    } else {
+        find_taxon_violations(&loaded_model.model, ontology_info, taxonomy)
+    }
+}
+
+/// The ontology data and config needed by the checks that use them.  A
+/// check is skipped if its inputs weren't supplied.
+pub(crate) struct ValidationConfig<'a> {
+    pub ontology_info: Option<&'a OntologyInfo>,
+    pub allowed_rel_config: Option<&'a AllowedRelConfig>,
+    // an NCBITaxon closure, for checking taxon constraints
+    pub taxonomy: Option<&'a OntologyInfo>,
+}
+
+fn missing_message(what: &str, missing: &GoCamMissing) -> String {
+    format!("{} for {} {} enabled by {} {}", what,
+            missing.mf_term_id, missing.mf_term_name.as_deref().unwrap_or(""),
+            missing.enabler_id, missing.enabler_label.as_deref().unwrap_or(""))
+}
+
+/// Run every QC check on a model and return all the problems found
+pub(crate) fn validate_model(loaded_model: &LoadedModel, config: &ValidationConfig)
+    -> Vec<ValidationIssue>
+{
+    let model = &loaded_model.model;
+    let model_id = model.id().to_owned();
+
+    let mut ret = vec![];
+
+    let mut add_issue = |check: &str, severity: Severity,
+                         individual_id: Option<String>, message: String| {
+        ret.push(ValidationIssue {
+            model_id: model_id.clone(),
+            check: check.to_owned(),
+            severity,
+            individual_id,
+            message,
+            diagnostic: None,
+        });
+    };
+
+    for hole_node in find_holes(model) {
+        add_issue("find-holes", Severity::Warning,
+                  Some(hole_node.individual_gocam_id.to_string()),
+                  format!("activity with no enabler: {} {}", hole_node.node_id, hole_node.label));
+    }
+
+    if let Some(ref gocam_py_model) = loaded_model.gocam_py_model {
+        for (missing_type, aspect) in [(GoCamMissingType::BiologicalProcess, "BP"),
+                                       (GoCamMissingType::CellularComponent, "CC")] {
+            for missing in find_missing(missing_type, gocam_py_model) {
+                add_issue("find-missing", Severity::Warning,
+                          Some(missing.enabler_id.to_string()),
+                          missing_message(&format!("missing {}", aspect), &missing));
+            }
+        }
+
+        for (missing_type, aspect) in [(GoCamMissingType::MolecularFunction, "MF"),
+                                       (GoCamMissingType::BiologicalProcess, "BP"),
+                                       (GoCamMissingType::CellularComponent, "CC")] {
+            for missing in find_missing_evidence(missing_type, gocam_py_model) {
+                add_issue("find-missing-evidence", Severity::Warning,
+                          Some(missing.enabler_id.to_string()),
+                          missing_message(&format!("missing {} evidence", aspect), &missing));
+            }
+        }
+    }
+
+    let mut diagnostics = vec![];
+
+    if let Some(ontology_info) = config.ontology_info {
+        if let Some(allowed_rel_config) = config.allowed_rel_config &&
+            ontology_info.has_closure()
+        {
+            let relation_diagnostics = check_relations(model, allowed_rel_config, ontology_info);
+            diagnostics.extend(relation_diagnostics.into_iter()
+                               .map(|diagnostic| ("check-allowed-relations", diagnostic)));
+        }
+
+        let obsolete_diagnostics =
+            find_obsolete_terms_in_loaded_model(loaded_model, ontology_info);
+        diagnostics.extend(obsolete_diagnostics.into_iter()
+                           .map(|diagnostic| ("find-obsolete-terms", diagnostic)));
+
+        let aspect_diagnostics =
+            find_aspect_mismatches_in_loaded_model(loaded_model, ontology_info);
+        diagnostics.extend(aspect_diagnostics.into_iter()
+                           .map(|diagnostic| ("check-aspects", diagnostic)));
+
+        if let Some(taxonomy) = config.taxonomy {
+            let taxon_diagnostics =
+                find_taxon_violations_in_loaded_model(loaded_model, ontology_info, taxonomy);
+            diagnostics.extend(taxon_diagnostics.into_iter()
+                               .map(|diagnostic| ("check-taxon-constraints", diagnostic)));
+        }
+    }
+
+    if let Some(ref raw_model) = loaded_model.raw_model {
+        for (id, gene_id, gene_label) in find_detached_genes(raw_model) {
+            add_issue("detached-genes", Severity::Warning, Some(id.to_string()),
+                      format!("detached gene: {} {}", gene_id, gene_label));
+        }
+    }
+
+    for chemical in find_detached_chemicals(model) {
+        add_issue("detached-chemicals", Severity::Warning, None,
+                  format!("detached chemical: {} {}", chemical.id(), chemical.label()));
+    }
+
+    for (check, diagnostic) in diagnostics {
+        let individual_id =
+            if diagnostic.individual_ids.is_empty() {
+                None
+            } else {
+                Some(diagnostic.individual_ids.join(","))
+            };
+
+        ret.push(ValidationIssue {
+            model_id: model_id.clone(),
+            check: check.to_owned(),
+            severity: diagnostic.severity,
+            individual_id,
+            message: diagnostic.to_string(),
+            diagnostic: Some(diagnostic),
+        });
+    }
+
+    ret
+}
+
+pub(crate) fn write_tsv(writer: &mut dyn Write, issues: &[ValidationIssue])
+    -> Result<(), std::io::Error>
+{
+    writeln!(writer, "model_id\tcheck\tseverity\tindividual_id\tmessage")?;
+
+    for issue in issues {
+        writeln!(writer, "{}\t{}\t{}\t{}\t{}", issue.model_id, issue.check,
+                 issue.severity, issue.individual_id.as_deref().unwrap_or(""),
+                 issue.message)?;
+    }
+
+    Ok(())
+}
+
+pub(crate) fn write_json(writer: &mut dyn Write, issues: &[ValidationIssue])
+    -> Result<(), std::io::Error>
+{
+    serde_json::to_writer(&mut *writer, issues)?;
+    writeln!(writer)
+}
+
+pub(crate) fn write_text(writer: &mut dyn Write, issues: &[ValidationIssue])
+    -> Result<(), std::io::Error>
+{
+    for (model_id, model_issues) in &issues.iter().chunk_by(|issue| &issue.model_id) {
+        writeln!(writer, "{}:", model_id)?;
+
+        for issue in model_issues {
+            if let Some(ref individual_id) = issue.individual_id {
+                writeln!(writer, "  {} [{}] {}: {}", issue.severity, issue.check,
+                         individual_id, issue.message)?;
+            } else {
+                writeln!(writer, "  {} [{}] {}", issue.severity, issue.check,
+                         issue.message)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::BufReader;
+    use std::fs::File;
+    use std::path::Path;
+
+    use crate::model_source::ModelSource;
+
+    use super::{ValidationConfig, load_model, validate_model};
+
+    #[test]
+    fn validate_test() {
+        let closure_file = File::open("tests/data/closure.tsv").unwrap();
+        let mut closure_buf_reader = BufReader::new(closure_file);
+        let closure = crate::ontology_info::parse_closure(&mut closure_buf_reader).unwrap();
+
+        let config_file = File::open("tests/data/config.tsv").unwrap();
+        let mut config_buf_reader = BufReader::new(config_file);
+        let config = crate::parse_allowed_relations_config(&mut config_buf_reader).unwrap();
+
+        let source = ModelSource::from(Path::new("tests/data/67ae98b500000055.yaml"));
+        let loaded_model = load_model(&source, None, None).unwrap();
+        assert!(loaded_model.gocam_py_model.is_some());
+        assert!(loaded_model.raw_model.is_none());
+
+        let validation_config = ValidationConfig {
+            ontology_info: Some(&closure),
+            allowed_rel_config: Some(&config),
+            taxonomy: None,
+        };
+
+        let issues = validate_model(&loaded_model, &validation_config);
+
+        let relation_issues: Vec<_> = issues.iter()
+            .filter(|issue| issue.check == "check-allowed-relations")
+            .collect();
+        assert_eq!(relation_issues.len(), 2);
+        assert_eq!(relation_issues[0].model_id, "gomodel:67ae98b500000055");
+        assert_eq!(relation_issues[0].message, "relation directly negatively regulates (RO:0002630) not allowed for GO:0003713 because of config for GO:0003824");
+        assert!(relation_issues[0].diagnostic.is_some());
+    }
+}